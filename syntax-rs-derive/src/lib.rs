@@ -0,0 +1,364 @@
+//! The `#[derive(Parse)]` and `#[derive(Spanned)]` macros of `syntax-rs`. Use them through the
+//! re-exports in `syntax_rs::parse::Parse` and `syntax_rs::Spanned`.
+//!
+//! A struct is parsed as the sequence of its fields. An enum is parsed by trying its variants in
+//! order with `ParseStream::try_parse`, so that the error of an enum that didn't match expects any
+//! of its variants. The `#[parse(...)]` attribute takes the following options:
+//!
+//! - `token = "..."` on a struct, variant or field: the literal comes before the fields, or before
+//!   the field. A unit struct or variant is just its token.
+//! - `peek = "..."` on a variant: the variant is only tried if the input starts with the literal,
+//!   and is not backtracked out of once it has been chosen. A bare `peek` peeks the `token` of the variant.
+//! - `span` on a field of type `Span`: the field is set to the span of everything that was parsed,
+//!   and `#[derive(Spanned)]` returns it. A field named `span` is used by `#[derive(Spanned)]` as well.
+//! - `memo` on a struct or enum: the results are memoized with `ParseStream::memoize`, which
+//!   requires the type to be `Clone`.
+//!
+//! ```ignore
+//! #[derive(Parse, Spanned)]
+//! enum Statement {
+//!     #[parse(token = "let", peek)]
+//!     Let {
+//!         name: Ident,
+//!         #[parse(token = "=")]
+//!         value: Expr,
+//!         #[parse(span)]
+//!         span: Span,
+//!     },
+//!     Expr(Expr),
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned as _, Attribute, Data, DeriveInput, Error,
+    Fields, Ident, LitStr, Member, Result,
+};
+
+#[proc_macro_derive(Parse, attributes(parse))]
+pub fn derive_parse(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_parse(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Spanned, attributes(parse))]
+pub fn derive_spanned(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_spanned(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The options of the `#[parse(...)]` attributes of an item.
+#[derive(Default)]
+struct Attrs {
+    token: Option<LitStr>,
+    /// `Some(None)` for a bare `peek`, which peeks `token`.
+    peek: Option<Option<LitStr>>,
+    span: bool,
+    memo: bool,
+}
+
+impl Attrs {
+    fn from_attrs(attrs: &[Attribute]) -> Result<Attrs> {
+        let mut result = Attrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("parse")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("token") {
+                    result.token = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("peek") {
+                    result.peek = if meta.input.peek(syn::Token![=]) {
+                        Some(Some(meta.value()?.parse()?))
+                    } else {
+                        Some(None)
+                    };
+                } else if meta.path.is_ident("span") {
+                    result.span = true;
+                } else if meta.path.is_ident("memo") {
+                    result.memo = true;
+                } else {
+                    return Err(meta.error("expected `token`, `peek`, `span` or `memo`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(result)
+    }
+
+    /// The literal a variant peeks, if it has the `peek` option.
+    fn peeked(&self, variant: &Ident) -> Result<Option<&LitStr>> {
+        match &self.peek {
+            None => Ok(None),
+            Some(Some(lit)) => Ok(Some(lit)),
+            Some(None) => match &self.token {
+                Some(lit) => Ok(Some(lit)),
+                None => Err(Error::new(
+                    variant.span(),
+                    "a bare `peek` needs a `token` to peek",
+                )),
+            },
+        }
+    }
+}
+
+/// The literal surrounded by backticks, as it is listed in the expected items of an error.
+fn expected(lit: &LitStr) -> String {
+    format!("`{}`", lit.value())
+}
+
+/// A condition that is true if the input starts with `lit`.
+fn peek_token(lit: &LitStr) -> TokenStream2 {
+    let len = lit.value().chars().count();
+    quote! {
+        ::std::matches!(stream.cur().peek_n(#len), ::std::option::Option::Some(found) if found == #lit)
+    }
+}
+
+/// Consumes `lit`, or returns an error that expected it.
+fn parse_token(lit: &LitStr) -> TokenStream2 {
+    let len = lit.value().chars().count();
+    let peek = peek_token(lit);
+    let expected = expected(lit);
+    quote! {
+        if #peek {
+            stream.cur().advance_n(#len);
+        } else {
+            return ::std::result::Result::Err(stream.error(#expected));
+        }
+    }
+}
+
+/// A block that parses `fields` after the optional `token` and evaluates to `Ok(#path { ... })`.
+fn parse_fields(
+    path: TokenStream2,
+    fields: &Fields,
+    token: Option<&LitStr>,
+) -> Result<TokenStream2> {
+    if let (Fields::Unit, None) = (fields, token) {
+        return Err(Error::new(
+            path.span(),
+            "a unit struct or variant needs a `#[parse(token = \"...\")]`",
+        ));
+    }
+
+    let mut statements = Vec::new();
+    if let Some(lit) = token {
+        statements.push(parse_token(lit));
+    }
+    let mut values = Vec::new();
+    let mut spanned = false;
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = Attrs::from_attrs(&field.attrs)?;
+        if attrs.peek.is_some() || attrs.memo {
+            return Err(Error::new(
+                field.span(),
+                "`peek` and `memo` can't be used on fields",
+            ));
+        }
+        if let Some(lit) = &attrs.token {
+            statements.push(parse_token(lit));
+        }
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(i.into()),
+        };
+        if attrs.span {
+            spanned = true;
+            values.push(quote!(#member: stream.since(snapshot)));
+        } else {
+            let var = Ident::new(&format!("field{i}"), proc_macro2::Span::call_site());
+            let ty = &field.ty;
+            statements.push(quote!(let #var = stream.parse::<#ty>()?;));
+            values.push(quote!(#member: #var));
+        }
+    }
+
+    let snapshot = spanned.then(|| quote!(let snapshot = stream.snapshot();));
+    let value = match fields {
+        Fields::Unit => path,
+        _ => quote!(#path { #(#values),* }),
+    };
+    Ok(quote! {{
+        #snapshot
+        #(#statements)*
+        ::std::result::Result::Ok(#value)
+    }})
+}
+
+fn expand_parse(mut input: DeriveInput) -> Result<TokenStream2> {
+    let container = Attrs::from_attrs(&input.attrs)?;
+    if container.peek.is_some() || container.span {
+        return Err(Error::new(
+            input.ident.span(),
+            "only `token` and `memo` can be used on a struct or enum",
+        ));
+    }
+
+    let body = match &input.data {
+        Data::Struct(data) => parse_fields(quote!(Self), &data.fields, container.token.as_ref())?,
+        Data::Enum(data) => {
+            if container.token.is_some() {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "`token` can't be used on an enum, put it on its variants",
+                ));
+            }
+            let mut alternatives = Vec::new();
+            let mut peeked = Vec::new();
+            for variant in &data.variants {
+                let attrs = Attrs::from_attrs(&variant.attrs)?;
+                if attrs.span || attrs.memo {
+                    return Err(Error::new(
+                        variant.ident.span(),
+                        "`span` and `memo` can't be used on variants",
+                    ));
+                }
+                let ident = &variant.ident;
+                let parse =
+                    parse_fields(quote!(Self::#ident), &variant.fields, attrs.token.as_ref())?;
+                // A peeked variant is parsed directly, so its errors are returned as they are.
+                alternatives.push(match attrs.peeked(ident)? {
+                    Some(lit) => {
+                        let peek = peek_token(lit);
+                        peeked.push(expected(lit));
+                        quote! {
+                            if #peek {
+                                return #parse;
+                            }
+                        }
+                    }
+                    None => quote! {
+                        if let ::std::result::Result::Ok(value) = stream.try_parse(|stream| #parse) {
+                            return ::std::result::Result::Ok(value);
+                        }
+                    },
+                });
+            }
+            let error = match peeked.split_first() {
+                Some((first, rest)) => quote! {
+                    stream.error(#first)#(.merge(stream.error(#rest)))*
+                },
+                None => quote!(stream.unexpected()),
+            };
+            quote! {{
+                #(#alternatives)*
+                ::std::result::Result::Err(#error)
+            }}
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                "#[derive(Parse)] can't be used on unions",
+            ))
+        }
+    };
+
+    let body = if container.memo {
+        quote!({ stream.memoize(|stream| #body) })
+    } else {
+        body
+    };
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::syntax_rs::parse::Parse));
+        // The memo table finds results by the `TypeId` of the type, which has to be `'static` for that.
+        if container.memo {
+            param.bounds.push(parse_quote!('static));
+        }
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::syntax_rs::parse::Parse for #name #ty_generics #where_clause {
+            fn parse(stream: &mut ::syntax_rs::parse::ParseStream) -> ::syntax_rs::Result<Self> #body
+        }
+    })
+}
+
+/// Where the span of a struct or variant comes from.
+enum SpanSource {
+    /// A field of type `Span`.
+    Field(Member),
+    /// The only field, which implements `Spanned` itself.
+    Inner(Member),
+}
+
+fn span_source(fields: &Fields, name: &Ident) -> Result<SpanSource> {
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = Attrs::from_attrs(&field.attrs)?;
+        match &field.ident {
+            Some(ident) if attrs.span || ident == "span" => {
+                return Ok(SpanSource::Field(Member::Named(ident.clone())))
+            }
+            None if attrs.span => return Ok(SpanSource::Field(Member::Unnamed(i.into()))),
+            _ => {}
+        }
+    }
+    match fields.len() {
+        1 => Ok(SpanSource::Inner(match &fields.iter().next().unwrap().ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(0.into()),
+        })),
+        _ => Err(Error::new(
+            name.span(),
+            "#[derive(Spanned)] needs a field marked with `#[parse(span)]`, a field named `span` or a single field",
+        )),
+    }
+}
+
+fn expand_spanned(input: DeriveInput) -> Result<TokenStream2> {
+    let (span, span_ref_mut) = match &input.data {
+        Data::Struct(data) => match span_source(&data.fields, &input.ident)? {
+            SpanSource::Field(member) => (quote!(self.#member), quote!(&mut self.#member)),
+            SpanSource::Inner(member) => (
+                quote!(::syntax_rs::Spanned::span(&self.#member)),
+                quote!(::syntax_rs::Spanned::span_ref_mut(&mut self.#member)),
+            ),
+        },
+        Data::Enum(data) => {
+            let mut span_arms = Vec::new();
+            let mut span_ref_mut_arms = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let (member, span, span_ref_mut) = match span_source(&variant.fields, ident)? {
+                    SpanSource::Field(member) => (member, quote!(*field), quote!(field)),
+                    SpanSource::Inner(member) => (
+                        member,
+                        quote!(::syntax_rs::Spanned::span(field)),
+                        quote!(::syntax_rs::Spanned::span_ref_mut(field)),
+                    ),
+                };
+                span_arms.push(quote!(Self::#ident { #member: field, .. } => #span));
+                span_ref_mut_arms
+                    .push(quote!(Self::#ident { #member: field, .. } => #span_ref_mut));
+            }
+            (
+                quote!(match self { #(#span_arms,)* }),
+                quote!(match self { #(#span_ref_mut_arms,)* }),
+            )
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                "#[derive(Spanned)] can't be used on unions",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::syntax_rs::Spanned for #name #ty_generics #where_clause {
+            fn span(&self) -> ::syntax_rs::Span {
+                #span
+            }
+
+            fn span_ref_mut(&mut self) -> &mut ::syntax_rs::Span {
+                #span_ref_mut
+            }
+        }
+    })
+}
@@ -0,0 +1,96 @@
+use syntax_rs::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    pratt::{Assoc, Pratt},
+    simple_tok, Span,
+};
+
+simple_tok!(Plus, "+");
+simple_tok!(Minus, "-");
+simple_tok!(StarStar, "**");
+simple_tok!(Star, "*");
+simple_tok!(Slash, "/");
+simple_tok!(Bang, "!");
+
+#[derive(Debug, Clone, Copy)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Int(u64, Span),
+    Neg(Box<Expr>, Span),
+    Factorial(Box<Expr>, Span),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Span),
+}
+
+impl Expr {
+    fn span(&self) -> Span {
+        match self {
+            Expr::Int(_, span)
+            | Expr::Neg(_, span)
+            | Expr::Factorial(_, span)
+            | Expr::Binary(_, _, _, span) => *span,
+        }
+    }
+
+    fn eval(&self) -> i64 {
+        match self {
+            Expr::Int(value, _) => *value as i64,
+            Expr::Neg(expr, _) => -expr.eval(),
+            Expr::Factorial(expr, _) => (1..=expr.eval()).product(),
+            Expr::Binary(op, lhs, rhs, _) => {
+                let (lhs, rhs) = (lhs.eval(), rhs.eval());
+                match op {
+                    BinOp::Add => lhs + rhs,
+                    BinOp::Sub => lhs - rhs,
+                    BinOp::Mul => lhs * rhs,
+                    BinOp::Div => lhs / rhs,
+                    BinOp::Pow => lhs.pow(rhs as u32),
+                }
+            }
+        }
+    }
+}
+
+fn atom(stream: &mut ParseStream, pratt: &Pratt<Expr>) -> syntax_rs::Result<Expr> {
+    if stream.cur().peek0() == Some('(') {
+        let mut content;
+        parenthesized!(content in stream);
+        return pratt.parse(&mut content);
+    }
+    let snapshot = stream.snapshot();
+    let error = stream.error("integer").or("`(`");
+    let digits = stream.cur().advance_while(|c| c.is_ascii_digit());
+    let value = digits.parse().map_err(|_| error)?;
+    Ok(Expr::Int(value, stream.since(snapshot)))
+}
+
+fn binary<O: Parse>(op: BinOp) -> impl Fn(Expr, O, Expr, Span) -> Expr {
+    move |lhs, _, rhs, span| Expr::Binary(op, Box::new(lhs), Box::new(rhs), span)
+}
+
+fn main() {
+    let pratt = Pratt::new(atom)
+        .skip_whitespace(true)
+        .infix::<Plus>(1, Assoc::Left, binary(BinOp::Add))
+        .infix::<Minus>(1, Assoc::Left, binary(BinOp::Sub))
+        // `**` starts with `*`, so it has to be added first.
+        .infix::<StarStar>(3, Assoc::Right, binary(BinOp::Pow))
+        .infix::<Star>(2, Assoc::Left, binary(BinOp::Mul))
+        .infix::<Slash>(2, Assoc::Left, binary(BinOp::Div))
+        .prefix::<Minus>(4, |_, expr, span| Expr::Neg(Box::new(expr), span))
+        .postfix::<Bang>(5, |expr, _, span| Expr::Factorial(Box::new(expr), span));
+
+    for code in ["1 + 2 * 3 - 4", "-(1 + 2) * 3!", "2 ** 3 ** 2", "1 + * 2"] {
+        match pratt.parse(&mut syntax_rs::parse_stream(code)) {
+            Ok(expr) => println!("{code} = {} ({:?})", expr.eval(), expr.span()),
+            Err(err) => println!("{code}: {}", err.locate(code)),
+        }
+    }
+}
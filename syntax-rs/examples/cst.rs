@@ -0,0 +1,130 @@
+use syntax_rs::{
+    ast::{self, AstNode},
+    ast_node,
+    cst::{SyntaxElement, SyntaxKind, SyntaxToken},
+    cursor::Cursor,
+    lookahead::Peek,
+    parse::{Parse, ParseStream},
+    simple_tok, spec,
+};
+
+const LET_KW: SyntaxKind = SyntaxKind(0);
+const IDENT: SyntaxKind = SyntaxKind(1);
+const EQ: SyntaxKind = SyntaxKind(2);
+const INT: SyntaxKind = SyntaxKind(3);
+const SEMICOLON: SyntaxKind = SyntaxKind(4);
+const LET_STMT: SyntaxKind = SyntaxKind(5);
+const ROOT: SyntaxKind = SyntaxKind(6);
+
+simple_tok!(KwLet, "let");
+simple_tok!(Equals, "=");
+simple_tok!(Semicolon, ";");
+
+/// Whitespaces and line comments.
+fn trivia(cursor: &mut Cursor) {
+    loop {
+        cursor.advance_while(spec::is_whitespace);
+        if cursor.peek_n(2) != Some("//") {
+            break;
+        }
+        cursor.advance_while(|c| c != '\n');
+    }
+}
+
+fn ident(stream: &mut ParseStream) -> syntax_rs::Result<()> {
+    let error = stream.error("identifier");
+    match stream.cur().advance_while(char::is_alphanumeric) {
+        "" => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn int(stream: &mut ParseStream) -> syntax_rs::Result<()> {
+    let error = stream.error("integer");
+    match stream.cur().advance_while(|c| c.is_ascii_digit()) {
+        "" => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// `let <ident> = <int or ident>;`, recorded as a `LET_STMT` node.
+struct Statement;
+
+impl Parse for Statement {
+    fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
+        stream.node(LET_STMT, |stream| {
+            stream.token(LET_KW, |stream| stream.parse::<KwLet>())?;
+            stream.token(IDENT, ident)?;
+            stream.token(EQ, |stream| stream.parse::<Equals>())?;
+            stream
+                .try_parse(|stream| stream.token(INT, int))
+                .or_else(|_| stream.token(IDENT, ident))?;
+            stream.token(SEMICOLON, |stream| stream.parse::<Semicolon>())?;
+            Ok(Statement)
+        })
+    }
+}
+
+ast_node!(LetStmt, LET_STMT);
+
+impl LetStmt {
+    fn name(&self) -> Option<SyntaxToken> {
+        ast::token(self.syntax(), IDENT)
+    }
+
+    fn value(&self) -> Option<SyntaxToken> {
+        let mut elements = self.syntax().children_with_tokens();
+        elements.find(|element| element.kind() == EQ)?;
+        match elements.next()? {
+            SyntaxElement::Token(token) if token.kind() != SEMICOLON => Some(token),
+            _ => None,
+        }
+    }
+}
+
+const SOURCE: &str = "\
+// Two statements and an error.
+let x = 1; // The first one.
+
+let = 2;
+let y   =  x;
+";
+
+fn main() {
+    let mut stream = syntax_rs::parse_stream(SOURCE);
+    stream.set_trivia(trivia);
+    stream.enable_cst();
+    while !stream.is_only_trivia() {
+        stream.parse_or_skip::<Statement>(&[KwLet::peek]);
+    }
+    let errors = stream.take_errors();
+    let root = stream.finish_cst(ROOT).unwrap();
+
+    print!("{}", root.debug_tree());
+    assert_eq!(root.text(), SOURCE);
+    for err in errors {
+        println!("{}", err.locate(SOURCE));
+    }
+
+    for stmt in ast::children::<LetStmt>(&root) {
+        let text = |token: Option<SyntaxToken>| token.map(|token| token.text().to_string());
+        println!("{:?} = {:?}", text(stmt.name()), text(stmt.value()));
+    }
+
+    // Renaming `x` keeps the comments and the formatting.
+    let renamed: String = root
+        .tokens()
+        .map(|token| {
+            let text = match token.text() {
+                "x" if token.kind() == IDENT => "value",
+                text => text,
+            };
+            format!(
+                "{}{text}{}",
+                token.leading_trivia(),
+                token.trailing_trivia()
+            )
+        })
+        .collect();
+    print!("{renamed}");
+}
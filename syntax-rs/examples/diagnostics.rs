@@ -0,0 +1,25 @@
+use syntax_rs::{diagnostics::Diagnostic, parse::ParseStream, simple_tok_spanned, SourceMap, Span};
+
+simple_tok_spanned!(Semicolon, ";");
+
+const SOURCE: &str = "fn main() {\n    let x = 1\n}\n";
+
+fn main() {
+    let mut sources = SourceMap::new();
+    let id = sources.add_file("main.tpl", SOURCE);
+    let file = sources.file(id);
+
+    // Parse the whole file, so that the error points into it and not into a part of it.
+    let mut stream = ParseStream::new_in_file(file.source(), id);
+    let semicolon = SOURCE.find('1').unwrap() + 1;
+    stream.cur().advance_n(SOURCE[..semicolon].chars().count());
+    let err = stream.parse::<Semicolon>().unwrap_err().locate(SOURCE);
+    println!("{err}");
+
+    let diagnostic = Diagnostic::from(&err)
+        .with_label(Span::new(0, 2).in_file(id), "in this function")
+        .with_help("add a `;` after the statement");
+    print!("{}", diagnostic.render(file, true));
+    print!("{}", diagnostic.render(file, false));
+    println!("{}", diagnostic.to_json(file));
+}
@@ -1,4 +1,5 @@
 use syntax_rs::{
+    cursor::Cursor,
     parse::{Parse, ParseStream},
     simple_tok_spanned, spec, Span, Spanned,
 };
@@ -11,9 +12,9 @@ struct LitInt {
 
 impl Parse for LitInt {
     fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        fn to_u32(chars: &str) -> syntax_rs::Result<u32> {
+        fn to_u32(chars: &str) -> Option<u32> {
             if chars.len() == 0 {
-                return Err("Expected integer.");
+                return None;
             }
             let mut number = 0;
 
@@ -23,12 +24,14 @@ impl Parse for LitInt {
                 number *= 10;
                 number += digit;
             }
-            Ok(number)
+            Some(number)
         }
 
         stream.try_parse(|stream| {
+            let error = stream.error("integer");
             let negative = stream.cur().consume('-');
-            let mut value = to_u32(stream.cur().advance_while(|c| c.is_digit(10)))? as i64;
+            let mut value =
+                to_u32(stream.cur().advance_while(|c| c.is_ascii_digit())).ok_or(error)? as i64;
             if negative {
                 value *= -1;
             }
@@ -45,7 +48,7 @@ struct LitStr {
 
 impl Parse for LitStr {
     fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        stream.parse::<Quote>()?;
+        stream.parse::<Quote>().map_err(|err| err.label("string"))?;
 
         let inside = stream.try_parse(|stream| {
             Ok(LitStr {
@@ -58,42 +61,20 @@ impl Parse for LitStr {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Parse)]
 enum Literal {
     Int(LitInt),
     String(LitStr),
 }
 
-impl Parse for Literal {
-    fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        if let Ok(lit_int) = stream.parse::<LitInt>() {
-            Ok(Literal::Int(lit_int))
-        } else if let Ok(lit_str) = stream.parse::<LitStr>() {
-            Ok(Literal::String(lit_str))
-        } else {
-            Err("Expected integer or str.")
-        }
-    }
-}
-
 simple_tok_spanned!(Quote, "\"");
 
-#[derive(Debug)]
+#[derive(Debug, Spanned)]
 struct Ident {
     string: String,
     span: Span,
 }
 
-impl Spanned for Ident {
-    fn span(&self) -> Span {
-        self.span
-    }
-
-    fn span_ref_mut(&mut self) -> &mut Span {
-        &mut self.span
-    }
-}
-
 impl Parse for Ident {
     fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
         stream.try_parse(|stream| {
@@ -108,7 +89,7 @@ impl Parse for Ident {
                 }
             });
             if slice.is_empty() {
-                Err("Expected identifier.")
+                Err(stream.error("identifier"))
             } else {
                 Ok(Ident {
                     string: String::from(slice),
@@ -129,9 +110,7 @@ enum Symbol {
 
 impl Parse for Symbol {
     fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        let ident: Ident = stream
-            .parse()
-            .map_err(|_e| "Expected identifier, `function`, `let` or `if`.")?;
+        let ident: Ident = stream.parse()?;
 
         Ok(match ident.string.as_str() {
             "fun" => Symbol::KwFunction,
@@ -142,59 +121,63 @@ impl Parse for Symbol {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Parse)]
 enum Punctuation {
+    #[parse(token = "+")]
     Plus,
+    #[parse(token = "-")]
     Minus,
+    #[parse(token = "*")]
     Star,
+    #[parse(token = "/")]
     Slash,
 }
 
-impl Parse for Punctuation {
+#[derive(Debug)]
+struct Whitespace;
+
+impl Parse for Whitespace {
     fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        stream.try_parse(|stream| {
-            Ok(
-                match stream
-                    .cur()
-                    .advance()
-                    .ok_or("Expected `+`, `-`, `*` or `/`.")?
-                {
-                    '+' => Punctuation::Plus,
-                    '-' => Punctuation::Minus,
-                    '*' => Punctuation::Star,
-                    '/' => Punctuation::Slash,
-                    _ => return Err("Expected `+`, `-`, `*` or `/`."),
-                },
-            )
-        })
+        stream.skip_all(spec::is_whitespace);
+        Ok(Whitespace)
     }
 }
 
-// NOTE: A #[derive(Parse)] and #[derive(Spanned)] will probably be added in the future.
-#[derive(Debug)]
-enum Token {
-    Punctuation(Punctuation),
+// Every variant is tried with `try_parse`, so the error expects any of them.
+#[derive(Debug, Parse)]
+enum TokenKind {
     Literal(Literal),
+    Punctuation(Punctuation),
     Symbol(Symbol),
 }
 
-impl Parse for Token {
-    fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Self> {
-        stream.skip_all(spec::is_whitespace);
-        if let Ok(lit) = stream.parse::<Literal>() {
-            Ok(Token::Literal(lit))
-        } else if let Ok(punctuation) = stream.parse::<Punctuation>() {
-            Ok(Token::Punctuation(punctuation))
-        } else if let Ok(symbol) = stream.parse::<Symbol>() {
-            Ok(Token::Symbol(symbol))
-        } else {
-            Err("Expected punctuation, symbol or literal.")
-        }
-    }
+#[derive(Debug, Parse)]
+struct Token {
+    _whitespace: Whitespace,
+    kind: TokenKind,
 }
 
 const CODE: &str = "1+2+3+4+5+6+7+8+9+10";
+const INVALID_CODE: &str = "1+2\n3 $ 4 # 5";
 
 fn main() {
-    println!("{:?}", syntax_rs::exhaustive_parse::<Token>(CODE));
+    let tokens = syntax_rs::exhaustive_parse::<Token>(CODE);
+    println!(
+        "{:?}",
+        tokens.map(|tokens| tokens
+            .into_iter()
+            .map(|token| token.kind)
+            .collect::<Vec<_>>())
+    );
+    // Every invalid token is reported, and tokenizing continues at the next whitespace.
+    let (tokens, errors) =
+        syntax_rs::exhaustive_parse_with_errors::<Token>(INVALID_CODE, &[starts_with_whitespace]);
+    println!("{} tokens", tokens.len());
+    for err in errors {
+        println!("{err}");
+    }
+}
+
+fn starts_with_whitespace(cursor: Cursor) -> bool {
+    cursor.peek0().is_some_and(spec::is_whitespace)
 }
@@ -0,0 +1,110 @@
+//! Typed nodes on top of a [concrete syntax tree](crate::cst).
+//!
+//! An AST node is a [`SyntaxNode`] of a specific kind, with accessors for the children that
+//! belong to it. The accessors find the children in the tree when they are called, so a tree with
+//! errors still has an AST, in which the missing children are `None`.
+//!
+//! ```ignore
+//! ast_node!(pub LetStmt, LET_STMT);
+//!
+//! impl LetStmt {
+//!     pub fn name(&self) -> Option<SyntaxToken> {
+//!         ast::token(self.syntax(), IDENT)
+//!     }
+//!
+//!     pub fn value(&self) -> Option<Expr> {
+//!         ast::child(self.syntax())
+//!     }
+//! }
+//! ```
+
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+
+pub trait AstNode: Sized {
+    fn can_cast(kind: SyntaxKind) -> bool;
+
+    /// The node as `Self`, if it has the kind of `Self`.
+    fn cast(node: SyntaxNode) -> Option<Self>;
+
+    fn syntax(&self) -> &SyntaxNode;
+}
+
+pub trait AstToken: Sized {
+    fn can_cast(kind: SyntaxKind) -> bool;
+
+    /// The token as `Self`, if it has the kind of `Self`.
+    fn cast(token: SyntaxToken) -> Option<Self>;
+
+    fn syntax(&self) -> &SyntaxToken;
+
+    /// The text of the token without its trivia.
+    fn text(&self) -> &str {
+        self.syntax().text()
+    }
+}
+
+/// The first child of `parent` that is an `N`.
+pub fn child<N: AstNode>(parent: &SyntaxNode) -> Option<N> {
+    parent.children().find_map(N::cast)
+}
+
+/// The children of `parent` that are `N`s.
+pub fn children<'p, N: AstNode + 'p>(parent: &'p SyntaxNode) -> impl Iterator<Item = N> + 'p {
+    parent.children().filter_map(N::cast)
+}
+
+/// The first token of `parent` of kind `kind`, not counting the tokens of its child nodes.
+pub fn token(parent: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxToken> {
+    parent
+        .children_with_tokens()
+        .find_map(|element| match element {
+            SyntaxElement::Token(token) if token.kind() == kind => Some(token),
+            _ => None,
+        })
+}
+
+/// Declares an AST node that wraps a [`SyntaxNode`] of kind `$kind`.
+#[macro_export]
+macro_rules! ast_node {
+    ($vis:vis $name:ident, $kind:expr) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        $vis struct $name($crate::cst::SyntaxNode);
+
+        impl $crate::ast::AstNode for $name {
+            fn can_cast(kind: $crate::cst::SyntaxKind) -> bool {
+                kind == $kind
+            }
+
+            fn cast(node: $crate::cst::SyntaxNode) -> Option<$name> {
+                <$name as $crate::ast::AstNode>::can_cast(node.kind()).then(|| $name(node))
+            }
+
+            fn syntax(&self) -> &$crate::cst::SyntaxNode {
+                &self.0
+            }
+        }
+    };
+}
+
+/// Declares an AST token that wraps a [`SyntaxToken`] of kind `$kind`.
+#[macro_export]
+macro_rules! ast_token {
+    ($vis:vis $name:ident, $kind:expr) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        $vis struct $name($crate::cst::SyntaxToken);
+
+        impl $crate::ast::AstToken for $name {
+            fn can_cast(kind: $crate::cst::SyntaxKind) -> bool {
+                kind == $kind
+            }
+
+            fn cast(token: $crate::cst::SyntaxToken) -> Option<$name> {
+                <$name as $crate::ast::AstToken>::can_cast(token.kind()).then(|| $name(token))
+            }
+
+            fn syntax(&self) -> &$crate::cst::SyntaxToken {
+                &self.0
+            }
+        }
+    };
+}
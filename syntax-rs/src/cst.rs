@@ -0,0 +1,806 @@
+//! Lossless concrete syntax trees.
+//!
+//! A tree is made of immutable green nodes and tokens, which only know their kind and text and can
+//! be shared between trees, and red [`SyntaxNode`]s and [`SyntaxToken`]s on top of them, which know
+//! their parent and where they are in the input. Every token carries the trivia around it, such as
+//! whitespaces and comments: the trailing trivia up to the end of its line and the leading trivia
+//! before it. So the text of a tree is exactly the input it was parsed from.
+//!
+//! Trees are built while parsing by a [`ParseStream`](crate::parse::ParseStream) in tree mode, see
+//! [`ParseStream::enable_cst`](crate::parse::ParseStream::enable_cst), or by hand with a [`GreenNodeBuilder`].
+//! The [`ast`](crate::ast) module adds typed nodes on top of them.
+
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::cursor::Cursor;
+#[cfg(feature = "span")]
+use crate::Span;
+
+/// The kind of a node or token. The kinds are defined by the language that is parsed, except for
+/// [`SyntaxKind::ERROR`] and [`SyntaxKind::EOF`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SyntaxKind(pub u16);
+
+impl SyntaxKind {
+    /// Input that was skipped while recovering from an error.
+    pub const ERROR: SyntaxKind = SyntaxKind(u16::MAX);
+    /// The empty token at the end of the input, whose leading trivia is the trivia after the last token.
+    pub const EOF: SyntaxKind = SyntaxKind(u16::MAX - 1);
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct GreenTokenData {
+    kind: SyntaxKind,
+    leading: Box<str>,
+    text: Box<str>,
+    trailing: Box<str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenToken(Rc<GreenTokenData>);
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, leading: &str, text: &str, trailing: &str) -> GreenToken {
+        GreenToken(Rc::new(GreenTokenData {
+            kind,
+            leading: leading.into(),
+            text: text.into(),
+            trailing: trailing.into(),
+        }))
+    }
+
+    #[inline]
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.kind
+    }
+
+    /// The text of the token without its trivia.
+    #[inline]
+    pub fn text(&self) -> &str {
+        &self.0.text
+    }
+
+    #[inline]
+    pub fn leading_trivia(&self) -> &str {
+        &self.0.leading
+    }
+
+    #[inline]
+    pub fn trailing_trivia(&self) -> &str {
+        &self.0.trailing
+    }
+
+    /// The length of the token in bytes, including its trivia.
+    #[inline]
+    pub fn text_len(&self) -> usize {
+        self.0.leading.len() + self.0.text.len() + self.0.trailing.len()
+    }
+}
+
+impl fmt::Display for GreenToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.0.leading, self.0.text, self.0.trailing)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct GreenNodeData {
+    kind: SyntaxKind,
+    text_len: usize,
+    children: Vec<GreenElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenNode(Rc<GreenNodeData>);
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> GreenNode {
+        GreenNode(Rc::new(GreenNodeData {
+            kind,
+            text_len: children.iter().map(GreenElement::text_len).sum(),
+            children,
+        }))
+    }
+
+    #[inline]
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.kind
+    }
+
+    /// The length of the node in bytes, including all trivia.
+    #[inline]
+    pub fn text_len(&self) -> usize {
+        self.0.text_len
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[GreenElement] {
+        &self.0.children
+    }
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.children.iter().try_for_each(|child| child.fmt(f))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(GreenNode),
+    Token(GreenToken),
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind(),
+            GreenElement::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn text_len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.text_len(),
+            GreenElement::Token(token) => token.text_len(),
+        }
+    }
+}
+
+impl fmt::Display for GreenElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GreenElement::Node(node) => node.fmt(f),
+            GreenElement::Token(token) => token.fmt(f),
+        }
+    }
+}
+
+impl From<GreenNode> for GreenElement {
+    fn from(node: GreenNode) -> GreenElement {
+        GreenElement::Node(node)
+    }
+}
+
+impl From<GreenToken> for GreenElement {
+    fn from(token: GreenToken) -> GreenElement {
+        GreenElement::Token(token)
+    }
+}
+
+/// A position in the children of a [`GreenNodeBuilder`] that a node can be started at later,
+/// for wrapping elements that have already been added, like the left operand of a binary expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(pub(crate) usize);
+
+/// Builds a green tree from the top down.
+#[derive(Debug, Default)]
+pub struct GreenNodeBuilder {
+    /// The kinds of the open nodes and the index of their first child in `children`.
+    parents: Vec<(SyntaxKind, usize)>,
+    children: Vec<GreenElement>,
+    /// The index in `children` of every node that was finished, so that a rollback can put the
+    /// elements of the nodes finished since its state back.
+    finished: Vec<usize>,
+}
+
+/// The state of a [`GreenNodeBuilder`] that [`GreenNodeBuilder::rollback`] restores.
+#[derive(Debug, Clone)]
+pub(crate) struct BuilderState {
+    parents: Vec<(SyntaxKind, usize)>,
+    children: usize,
+    finished: usize,
+}
+
+impl GreenNodeBuilder {
+    pub fn new() -> GreenNodeBuilder {
+        GreenNodeBuilder::default()
+    }
+
+    pub fn start_node(&mut self, kind: SyntaxKind) {
+        self.parents.push((kind, self.children.len()));
+    }
+
+    #[inline]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.children.len())
+    }
+
+    /// Starts a node that contains everything added since `checkpoint`.
+    ///
+    /// # Panics
+    /// Panics if a node that is still open was started after `checkpoint`.
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        assert!(
+            checkpoint.0 <= self.children.len()
+                && self
+                    .parents
+                    .last()
+                    .is_none_or(|(_, first)| *first <= checkpoint.0),
+            "GreenNodeBuilder::start_node_at: the checkpoint is outside of the current node"
+        );
+        self.parents.push((kind, checkpoint.0));
+    }
+
+    pub fn token(&mut self, token: GreenToken) {
+        self.children.push(token.into());
+    }
+
+    /// # Panics
+    /// Panics if there is no open node.
+    pub fn finish_node(&mut self) {
+        let (kind, first) = self
+            .parents
+            .pop()
+            .expect("GreenNodeBuilder::finish_node: there is no open node");
+        let children = self.children.split_off(first);
+        self.finished.push(first);
+        self.children.push(GreenNode::new(kind, children).into());
+    }
+
+    /// # Panics
+    /// Panics if a node is still open or the builder doesn't contain exactly one node.
+    pub fn finish(mut self) -> GreenNode {
+        assert!(
+            self.parents.is_empty(),
+            "GreenNodeBuilder::finish: a node is still open"
+        );
+        match (self.children.pop(), self.children.is_empty()) {
+            (Some(GreenElement::Node(node)), true) => node,
+            _ => panic!("GreenNodeBuilder::finish: the builder doesn't contain exactly one node"),
+        }
+    }
+
+    pub(crate) fn state(&self) -> BuilderState {
+        BuilderState {
+            parents: self.parents.clone(),
+            children: self.children.len(),
+            finished: self.finished.len(),
+        }
+    }
+
+    /// Removes everything added since `state`, and takes the elements that were added before it
+    /// back out of the nodes that were finished since, such as a node started at an earlier
+    /// checkpoint.
+    pub(crate) fn rollback(&mut self, state: BuilderState) {
+        for index in self.finished.drain(state.finished..).rev() {
+            self.children.truncate(index + 1);
+            match self.children.pop() {
+                Some(GreenElement::Node(node)) => self.children.extend_from_slice(node.children()),
+                _ => unreachable!("GreenNodeBuilder::rollback: a finished node is missing"),
+            }
+        }
+        self.children.truncate(state.children);
+        self.parents = state.parents;
+    }
+}
+
+/// Records the tree of a [`ParseStream`](crate::parse::ParseStream) in tree mode.
+pub(crate) struct CstBuilder<'a> {
+    source: &'a str,
+    /// The end of the last token, everything before it is in the tree.
+    pos: usize,
+    builder: GreenNodeBuilder,
+}
+
+impl<'a> CstBuilder<'a> {
+    pub(crate) fn new(source: &'a str, pos: usize) -> CstBuilder<'a> {
+        CstBuilder {
+            source,
+            pos,
+            builder: GreenNodeBuilder::new(),
+        }
+    }
+
+    /// The state to roll back to if an alternative fails.
+    pub(crate) fn state(&self) -> (BuilderState, usize) {
+        (self.builder.state(), self.pos)
+    }
+
+    pub(crate) fn rollback(&mut self, (state, pos): (BuilderState, usize)) {
+        self.builder.rollback(state);
+        self.pos = pos;
+    }
+
+    #[inline]
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        self.builder.checkpoint()
+    }
+
+    /// Wraps everything added since `checkpoint` in a node.
+    pub(crate) fn wrap(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        self.builder.start_node_at(checkpoint, kind);
+        self.builder.finish_node();
+    }
+
+    /// Adds a token whose leading trivia starts at `begin`, text is `text` and trailing trivia
+    /// ends at `end`. Input that was skipped before it is added as an error token.
+    pub(crate) fn token(&mut self, kind: SyntaxKind, begin: usize, text: Range<usize>, end: usize) {
+        self.skipped(begin);
+        self.builder.token(GreenToken::new(
+            kind,
+            &self.source[begin..text.start],
+            &self.source[text.clone()],
+            &self.source[text.end..end],
+        ));
+        self.pos = end;
+    }
+
+    /// Adds the input from the end of the last token up to `end` as an error token.
+    pub(crate) fn skipped(&mut self, end: usize) {
+        if end > self.pos {
+            let skipped = &self.source[self.pos..end];
+            self.builder
+                .token(GreenToken::new(SyntaxKind::ERROR, "", skipped, ""));
+            self.pos = end;
+        }
+    }
+
+    /// Adds the rest of the input and returns the tree as a node of kind `root`.
+    pub(crate) fn finish(mut self, root: SyntaxKind, trivia: fn(&mut Cursor)) -> GreenNode {
+        let mut cursor = Cursor::new(self.source);
+        cursor.seek(self.pos);
+        trivia(&mut cursor);
+        let trivia_end = cursor.index();
+        let end = self.source.len();
+        if trivia_end < end {
+            self.token(SyntaxKind::ERROR, self.pos, trivia_end..end, end);
+            self.token(SyntaxKind::EOF, end, end..end, end);
+        } else {
+            self.token(SyntaxKind::EOF, self.pos, end..end, end);
+        }
+        self.wrap(Checkpoint(0), root);
+        self.builder.finish()
+    }
+}
+
+struct NodeData {
+    green: GreenNode,
+    parent: Option<SyntaxNode>,
+    /// The byte offset the node starts at.
+    offset: usize,
+}
+
+/// A node of a tree, which knows its parent and position.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+impl SyntaxNode {
+    pub fn new_root(green: GreenNode) -> SyntaxNode {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            parent: None,
+            offset: 0,
+        }))
+    }
+
+    #[inline]
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind()
+    }
+
+    #[inline]
+    pub fn green(&self) -> &GreenNode {
+        &self.0.green
+    }
+
+    #[inline]
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    /// The bytes the node covers, including the trivia of its first and last token.
+    #[inline]
+    pub fn text_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.text_len()
+    }
+
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn span(&self) -> Span {
+        let range = self.text_range();
+        Span::new(range.start, range.end)
+    }
+
+    /// The text of the node, including all trivia.
+    pub fn text(&self) -> String {
+        self.0.green.to_string()
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0.green.children().iter().map(move |child| {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    parent: Some(self.clone()),
+                    offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                    offset,
+                }),
+            };
+            offset += child.text_len();
+            element
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens()
+            .filter_map(|element| match element {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+    }
+
+    /// The node and its parents, up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxNode> {
+        std::iter::successors(Some(self.clone()), SyntaxNode::parent)
+    }
+
+    /// The node and all nodes below it, in the order they appear in the input.
+    pub fn descendants(&self) -> impl Iterator<Item = SyntaxNode> {
+        let mut stack = vec![self.clone()];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            let len = stack.len();
+            stack.extend(node.children());
+            stack[len..].reverse();
+            Some(node)
+        })
+    }
+
+    /// All tokens below the node, in the order they appear in the input.
+    pub fn tokens(&self) -> impl Iterator<Item = SyntaxToken> {
+        let mut stack = vec![SyntaxElement::Node(self.clone())];
+        std::iter::from_fn(move || loop {
+            match stack.pop()? {
+                SyntaxElement::Token(token) => return Some(token),
+                SyntaxElement::Node(node) => {
+                    let len = stack.len();
+                    stack.extend(node.children_with_tokens());
+                    stack[len..].reverse();
+                }
+            }
+        })
+    }
+
+    pub fn first_token(&self) -> Option<SyntaxToken> {
+        self.tokens().next()
+    }
+
+    /// The tree below the node with one line for every node and token, for debugging.
+    pub fn debug_tree(&self) -> String {
+        fn go(out: &mut String, element: SyntaxElement, depth: usize) {
+            let range = element.text_range();
+            let _ = write!(
+                out,
+                "{:indent$}{:?}@{}..{}",
+                "",
+                element.kind().0,
+                range.start,
+                range.end,
+                indent = depth * 2
+            );
+            match element {
+                SyntaxElement::Node(node) => {
+                    out.push('\n');
+                    for child in node.children_with_tokens() {
+                        go(out, child, depth + 1);
+                    }
+                }
+                SyntaxElement::Token(token) => {
+                    let _ = writeln!(out, " {:?}", token.green.to_string());
+                }
+            }
+        }
+
+        let mut out = String::new();
+        go(&mut out, SyntaxElement::Node(self.clone()), 0);
+        out
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &SyntaxNode) -> bool {
+        Rc::ptr_eq(&self.0.green.0, &other.0.green.0) && self.0.offset == other.0.offset
+    }
+}
+
+impl Eq for SyntaxNode {}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = self.text_range();
+        write!(f, "{:?}@{}..{}", self.kind().0, range.start, range.end)
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.green.fmt(f)
+    }
+}
+
+/// A token of a tree, which knows its parent and position.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SyntaxToken {
+    green: GreenToken,
+    parent: SyntaxNode,
+    /// The byte offset the leading trivia starts at.
+    offset: usize,
+}
+
+impl SyntaxToken {
+    #[inline]
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind()
+    }
+
+    #[inline]
+    pub fn green(&self) -> &GreenToken {
+        &self.green
+    }
+
+    #[inline]
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    /// The text of the token without its trivia.
+    #[inline]
+    pub fn text(&self) -> &str {
+        self.green.text()
+    }
+
+    #[inline]
+    pub fn leading_trivia(&self) -> &str {
+        self.green.leading_trivia()
+    }
+
+    #[inline]
+    pub fn trailing_trivia(&self) -> &str {
+        self.green.trailing_trivia()
+    }
+
+    /// The bytes of the text of the token, without its trivia.
+    pub fn text_range(&self) -> Range<usize> {
+        let begin = self.offset + self.leading_trivia().len();
+        begin..begin + self.text().len()
+    }
+
+    /// The bytes the token covers, including its trivia.
+    #[inline]
+    pub fn full_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text_len()
+    }
+
+    /// The span of the text of the token, without its trivia.
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn span(&self) -> Span {
+        let range = self.text_range();
+        Span::new(range.start, range.end)
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = self.text_range();
+        write!(
+            f,
+            "{:?}@{}..{} {:?}",
+            self.kind().0,
+            range.start,
+            range.end,
+            self.text()
+        )
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.green.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            SyntaxElement::Node(node) => node.kind(),
+            SyntaxElement::Token(token) => token.kind(),
+        }
+    }
+
+    /// The bytes the element covers, including trivia.
+    pub fn text_range(&self) -> Range<usize> {
+        match self {
+            SyntaxElement::Node(node) => node.text_range(),
+            SyntaxElement::Token(token) => token.full_range(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lookahead::Peek;
+    use crate::parse::{Parse, ParseStream};
+    use crate::{simple_tok, spec};
+
+    const LET_KW: SyntaxKind = SyntaxKind(0);
+    const IDENT: SyntaxKind = SyntaxKind(1);
+    const SEMICOLON: SyntaxKind = SyntaxKind(2);
+    const LET_STMT: SyntaxKind = SyntaxKind(3);
+    const ROOT: SyntaxKind = SyntaxKind(4);
+    const PLUS: SyntaxKind = SyntaxKind(5);
+    const INT: SyntaxKind = SyntaxKind(6);
+    const BIN_EXPR: SyntaxKind = SyntaxKind(7);
+
+    simple_tok!(KwLet, "let");
+    simple_tok!(Semicolon, ";");
+    simple_tok!(Plus, "+");
+
+    /// Whitespaces and line comments.
+    fn trivia(cursor: &mut Cursor) {
+        loop {
+            cursor.advance_while(spec::is_whitespace);
+            if cursor.peek_n(2) != Some("//") {
+                break;
+            }
+            cursor.advance_while(|c| c != '\n');
+        }
+    }
+
+    fn ident(stream: &mut ParseStream) -> crate::Result<()> {
+        let error = stream.error("identifier");
+        match stream.cur().advance_while(char::is_alphabetic) {
+            "" => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn int(stream: &mut ParseStream) -> crate::Result<()> {
+        let error = stream.error("integer");
+        match stream.cur().advance_while(|c| c.is_ascii_digit()) {
+            "" => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// `let <ident>;`
+    struct Statement;
+
+    impl Parse for Statement {
+        fn parse(stream: &mut ParseStream) -> crate::Result<Self> {
+            stream.node(LET_STMT, |stream| {
+                stream.token(LET_KW, |stream| stream.parse::<KwLet>())?;
+                stream.token(IDENT, ident)?;
+                stream.token(SEMICOLON, |stream| stream.parse::<Semicolon>())?;
+                Ok(Statement)
+            })
+        }
+    }
+
+    fn parse(source: &str) -> SyntaxNode {
+        let mut stream = ParseStream::new(source);
+        stream.set_trivia(trivia);
+        stream.enable_cst();
+        while !stream.is_only_trivia() {
+            stream.parse_or_skip::<Statement>(&[KwLet::peek]);
+        }
+        stream.finish_cst(ROOT).unwrap()
+    }
+
+    fn errors(root: &SyntaxNode) -> Vec<String> {
+        root.tokens()
+            .filter(|token| token.kind() == SyntaxKind::ERROR)
+            .map(|token| token.text().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_errors() {
+        let source = "let a; 1 2 let b;\nlet ; let c; ?";
+        let root = parse(source);
+        assert_eq!(root.to_string(), source);
+        assert_eq!(root.text(), source);
+        // The `let` of the incomplete statement stays in the tree, the rest of it is skipped.
+        assert_eq!(errors(&root), ["1 2 ", "; ", "?"]);
+    }
+
+    #[test]
+    fn round_trips_trailing_trivia() {
+        let source = "let a; // a\n\n  // The end.\n  ";
+        let root = parse(source);
+        assert_eq!(root.to_string(), source);
+        assert!(errors(&root).is_empty());
+
+        let eof = root.tokens().last().unwrap();
+        assert_eq!(eof.kind(), SyntaxKind::EOF);
+        assert_eq!(eof.text(), "");
+        assert_eq!(eof.leading_trivia(), "\n  // The end.\n  ");
+    }
+
+    #[test]
+    fn round_trips_unparsed_input() {
+        let source = "let a;  ?? b\n ";
+        let mut stream = ParseStream::new(source);
+        stream.set_trivia(trivia);
+        stream.enable_cst();
+        stream.parse::<Statement>().ok().unwrap();
+        let root = stream.finish_cst(ROOT).unwrap();
+        assert_eq!(root.to_string(), source);
+        assert_eq!(errors(&root), ["?? b\n "]);
+    }
+
+    #[test]
+    fn round_trips_only_trivia() {
+        for source in ["", " \n", "// Nothing.\n"] {
+            let root = parse(source);
+            assert_eq!(root.to_string(), source);
+            assert_eq!(root.tokens().count(), 1);
+        }
+    }
+
+    #[test]
+    fn backtracks_out_of_nodes_started_at_checkpoints() {
+        let source = "a + b";
+        let mut stream = ParseStream::new(source);
+        stream.enable_cst();
+        let checkpoint = stream.checkpoint();
+        stream.token(IDENT, ident).unwrap();
+        // `a + 1` fails at `b` after wrapping `a +` in a node.
+        let int_rhs = stream.try_parse(|stream| {
+            stream.node_at(checkpoint, BIN_EXPR, |stream| {
+                stream.token(PLUS, |stream| stream.parse::<Plus>())?;
+                stream.token(INT, int)
+            })
+        });
+        assert!(int_rhs.is_err());
+        stream
+            .node_at(checkpoint, BIN_EXPR, |stream| {
+                stream.token(PLUS, |stream| stream.parse::<Plus>())?;
+                stream.token(IDENT, ident)
+            })
+            .unwrap();
+        let root = stream.finish_cst(ROOT).unwrap();
+
+        assert_eq!(root.to_string(), source);
+        let expr = root.children().collect::<Vec<_>>();
+        assert_eq!(expr.len(), 1);
+        assert_eq!(expr[0].kind(), BIN_EXPR);
+        let kinds = expr[0]
+            .children_with_tokens()
+            .map(|element| element.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [IDENT, PLUS, IDENT]);
+    }
+
+    #[test]
+    fn failed_tokens_leave_their_trivia() {
+        let source = "  b";
+        let mut stream = ParseStream::new(source);
+        stream.enable_cst();
+        assert!(stream.token(INT, int).is_err());
+        assert_eq!(stream.cur().index(), 0);
+        stream.token(IDENT, ident).unwrap();
+        let root = stream.finish_cst(ROOT).unwrap();
+
+        assert_eq!(root.to_string(), source);
+        assert!(errors(&root).is_empty());
+        let ident = root.first_token().unwrap();
+        assert_eq!(ident.kind(), IDENT);
+        assert_eq!(ident.leading_trivia(), "  ");
+    }
+}
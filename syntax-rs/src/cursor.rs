@@ -108,6 +108,33 @@ impl<'a> Cursor<'a> {
     pub fn iter<'c>(&'c self) -> Iter<'a, 'c> {
         Iter(self.index, self)
     }
+
+    /// The remaining input that hasn't been consumed yet.
+    #[inline]
+    pub fn rest(&self) -> &'a str {
+        &self.slice[self.index..]
+    }
+
+    /// The whole input of the cursor, including what has been consumed.
+    #[inline]
+    pub(crate) fn source(&self) -> &'a str {
+        self.slice
+    }
+
+    /// Moves to the byte offset `index`, which has to be at a character boundary.
+    #[inline]
+    pub(crate) fn seek(&mut self, index: usize) {
+        debug_assert!(self.slice.is_char_boundary(index));
+        self.index = index;
+    }
+
+    /// A cursor at the same index that ends at the byte offset `end`.
+    pub(crate) fn truncated(&self, end: usize) -> Cursor<'a> {
+        Cursor {
+            slice: &self.slice[..end],
+            index: self.index,
+        }
+    }
 }
 
 impl<'a> Debug for Cursor<'a> {
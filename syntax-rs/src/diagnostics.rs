@@ -0,0 +1,435 @@
+//! Rendering of errors and warnings with the source they point at.
+//!
+//! A [`Diagnostic`] has a message, a primary span and optionally secondary spans, notes and help.
+//! It is rendered like the diagnostics of rustc:
+//!
+//! ```text
+//! error: Expected `;`, found `}`
+//!  --> main.tpl:2:14
+//!   |
+//! 1 | fn main() {
+//!   | - in this function
+//! 2 |     let x = 1
+//!   |              ^ expected `;`
+//!   |
+//!   = help: add a `;` after the statement
+//! ```
+//!
+//! or as JSON for tools. Spans that cover several lines are underlined on their first line.
+
+use crate::source_map::{Location, SourceFile};
+use crate::{ParseError, Span};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        }
+    }
+
+    /// The ANSI escape code the severity is colored with.
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;32m",
+            Severity::Help => "\x1b[1;36m",
+        }
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    /// Printed next to the underline. May be empty.
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            primary: Label {
+                span,
+                message: String::new(),
+            },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message, span)
+    }
+
+    /// Sets the message next to the underline of the primary span.
+    pub fn with_primary_label(mut self, message: impl Into<String>) -> Diagnostic {
+        self.primary.message = message.into();
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Renders the diagnostic for a terminal. `file` is the file the spans point into, and
+    /// `color` enables ANSI colors.
+    ///
+    /// # Panics
+    /// Panics if a span carries the id of another file.
+    pub fn render(&self, file: &SourceFile, color: bool) -> String {
+        self.check_file(file);
+        let paint = |code: &'static str| if color { code } else { "" };
+        let reset = paint(RESET);
+
+        let mut labels: Vec<(&Label, bool)> = std::iter::once((&self.primary, true))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+            .collect();
+        labels.sort_by_key(|(label, _)| label.span.begin);
+        let max_line = labels
+            .iter()
+            .map(|(label, _)| file.line(label.span.begin))
+            .max()
+            .unwrap_or(1);
+        let width = max_line.to_string().len();
+        let gutter = format!("{}{:width$} |{reset}", paint(BLUE), "");
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}{reset}{}: {}{reset}",
+            paint(self.severity.color()),
+            self.severity.as_str(),
+            paint(BOLD),
+            self.message
+        );
+        let location = file.location(self.primary.span.begin);
+        let _ = writeln!(
+            out,
+            "{}{:width$}-->{reset} {}:{}:{}",
+            paint(BLUE),
+            "",
+            file.name(),
+            location.line,
+            location.column
+        );
+        let _ = writeln!(out, "{gutter}");
+
+        let mut previous = None;
+        for (label, primary) in labels {
+            let Location { line, column, .. } = file.location(label.span.begin);
+            if previous != Some(line) {
+                if previous.is_some_and(|previous| line > previous + 1) {
+                    let _ = writeln!(out, "{}...{reset}", paint(BLUE));
+                }
+                let _ = writeln!(
+                    out,
+                    "{}{line:>width$} |{reset} {}",
+                    paint(BLUE),
+                    file.line_text(line)
+                );
+                previous = Some(line);
+            }
+
+            let text = file.line_text(line);
+            let start = text
+                .char_indices()
+                .nth(column - 1)
+                .map_or(text.len(), |(i, _)| i);
+            let end = (label.span.end - file.line_start(line)).clamp(start, text.len());
+            let len = text[start..end].chars().count().max(1);
+            let (mark, mark_color) = if primary {
+                ('^', self.severity.color())
+            } else {
+                ('-', BLUE)
+            };
+            let _ = write!(
+                out,
+                "{gutter} {:pad$}{}{}",
+                "",
+                paint(mark_color),
+                mark.to_string().repeat(len),
+                pad = column - 1
+            );
+            if !label.message.is_empty() {
+                let _ = write!(out, " {}", label.message);
+            }
+            let _ = writeln!(out, "{reset}");
+        }
+
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            let _ = writeln!(out, "{gutter}");
+        }
+        let notes = self.notes.iter().map(|note| ("note", note));
+        for (kind, text) in notes.chain(self.help.iter().map(|help| ("help", help))) {
+            let _ = writeln!(
+                out,
+                "{}{:width$} ={reset} {}{kind}{reset}: {text}",
+                paint(BLUE),
+                "",
+                paint(BOLD)
+            );
+        }
+        out
+    }
+
+    /// Renders the diagnostic as a JSON object, with 1-based lines and columns for every label.
+    ///
+    /// # Panics
+    /// Panics if a span carries the id of another file.
+    pub fn to_json(&self, file: &SourceFile) -> String {
+        self.check_file(file);
+        let label = |label: &Label, primary: bool| {
+            let begin = file.location(label.span.begin);
+            let end = file.location(label.span.end);
+            format!(
+                "{{\"primary\":{primary},\"begin\":{},\"end\":{},\"line\":{},\"column\":{},\
+                 \"end_line\":{},\"end_column\":{},\"message\":{}}}",
+                label.span.begin,
+                label.span.end,
+                begin.line,
+                begin.column,
+                end.line,
+                end.column,
+                json_string(&label.message)
+            )
+        };
+        let labels: Vec<String> = std::iter::once(label(&self.primary, true))
+            .chain(
+                self.secondary
+                    .iter()
+                    .map(|secondary| label(secondary, false)),
+            )
+            .collect();
+        let strings = |strings: &[String]| {
+            let strings: Vec<String> = strings.iter().map(|string| json_string(string)).collect();
+            format!("[{}]", strings.join(","))
+        };
+        format!(
+            "{{\"severity\":\"{}\",\"message\":{},\"file\":{},\"labels\":[{}],\"notes\":{},\"help\":{}}}",
+            self.severity.as_str(),
+            json_string(&self.message),
+            json_string(file.name()),
+            labels.join(","),
+            strings(&self.notes),
+            strings(&self.help)
+        )
+    }
+
+    fn check_file(&self, file: &SourceFile) {
+        for label in std::iter::once(&self.primary).chain(&self.secondary) {
+            assert!(
+                file.is_file_of(label.span),
+                "The span {}..{} doesn't point into `{}`.",
+                label.span.begin,
+                label.span.end,
+                file.name()
+            );
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    /// An error at the span of the found text, with a note for every cause.
+    fn from(err: &ParseError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(err.summary(), err.span());
+        if !err.expected_items().is_empty() {
+            let expected: Vec<&str> = err.expected_items().to_vec();
+            diagnostic = diagnostic.with_primary_label(format!("expected {}", expected.join(", ")));
+        }
+        let mut cause = err.cause();
+        while let Some(err) = cause {
+            diagnostic = diagnostic.with_note(format!("caused by: {}", err.summary()));
+            cause = err.cause();
+        }
+        diagnostic
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut out = String::with_capacity(string.len() + 2);
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `let x1 = 1;` to `let x12 = 12;`, one per line.
+    fn lets() -> SourceFile {
+        let source: String = (1..=12).map(|i| format!("let x{i} = {i};\n")).collect();
+        SourceFile::new("main.tpl", source)
+    }
+
+    fn redefined() -> Diagnostic {
+        // Line 11 starts at 9 * 12 + 14.
+        Diagnostic::error("Redefined `x`", Span::new(126, 129))
+            .with_primary_label("redefined here")
+            .with_label(Span::new(4, 6), "first defined here")
+            .with_label(Span::new(132, 134), "")
+            .with_note("`x` is shadowed")
+            .with_help("rename one of them")
+    }
+
+    /// Removes the ANSI escape codes from `colored`.
+    fn strip_colors(colored: &str) -> String {
+        let mut plain = String::new();
+        let mut rest = colored;
+        while let Some(escape) = rest.find('\x1b') {
+            plain.push_str(&rest[..escape]);
+            rest = &rest[escape + rest[escape..].find('m').unwrap() + 1..];
+        }
+        plain.push_str(rest);
+        plain
+    }
+
+    #[test]
+    fn renders_labels_under_their_lines() {
+        assert_eq!(
+            redefined().render(&lets(), false),
+            "\
+error: Redefined `x`
+  --> main.tpl:11:5
+   |
+ 1 | let x1 = 1;
+   |     -- first defined here
+...
+11 | let x11 = 11;
+   |     ^^^ redefined here
+   |           --
+   |
+   = note: `x` is shadowed
+   = help: rename one of them
+"
+        );
+    }
+
+    #[test]
+    fn gutter_fits_the_last_line_number() {
+        let file = SourceFile::new("short.tpl", "let x = 1\n");
+        let diagnostic = Diagnostic::warning("Unused `x`", Span::new(4, 5));
+        assert_eq!(
+            diagnostic.render(&file, false),
+            "\
+warning: Unused `x`
+ --> short.tpl:1:5
+  |
+1 | let x = 1
+  |     ^
+"
+        );
+    }
+
+    #[test]
+    fn underlines_spans_up_to_the_end_of_their_first_line() {
+        let file = SourceFile::new("multi.tpl", "{ é\n}\n");
+        // The span ends on the next line. `é` is two bytes but one column wide.
+        let diagnostic = Diagnostic::error("Unclosed", Span::new(0, 6));
+        assert!(diagnostic
+            .render(&file, false)
+            .contains("1 | { é\n  | ^^^\n"));
+    }
+
+    #[test]
+    fn colors_are_only_added() {
+        let colored = redefined().render(&lets(), true);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: Redefined `x`\x1b[0m\n"));
+        assert!(colored.contains("\x1b[1;31m^^^ redefined here\x1b[0m"));
+        assert!(colored.contains("\x1b[1;34m-- first defined here\x1b[0m"));
+        assert_eq!(strip_colors(&colored), redefined().render(&lets(), false));
+        assert!(!redefined().render(&lets(), false).contains('\x1b'));
+    }
+
+    #[test]
+    fn json_lists_every_label() {
+        let file = SourceFile::new("main.tpl", "let x = 1\n");
+        let diagnostic = Diagnostic::error("Expected `;`", Span::new(9, 10))
+            .with_label(Span::new(0, 3), "statement")
+            .with_help("add a `;`");
+        assert_eq!(
+            diagnostic.to_json(&file),
+            "{\"severity\":\"error\",\"message\":\"Expected `;`\",\"file\":\"main.tpl\",\"labels\":[\
+             {\"primary\":true,\"begin\":9,\"end\":10,\"line\":1,\"column\":10,\"end_line\":2,\"end_column\":1,\"message\":\"\"},\
+             {\"primary\":false,\"begin\":0,\"end\":3,\"line\":1,\"column\":1,\"end_line\":1,\"end_column\":4,\"message\":\"statement\"}],\
+             \"notes\":[],\"help\":[\"add a `;`\"]}"
+        );
+    }
+
+    #[test]
+    fn json_escapes_strings() {
+        let file = SourceFile::new("dir\\\"quoted\".tpl", "x");
+        let diagnostic =
+            Diagnostic::error("Found `\"`\n\tand \u{1}", Span::new(0, 1)).with_note("é\r");
+        let json = diagnostic.to_json(&file);
+        assert!(
+            json.contains("\"message\":\"Found `\\\"`\\n\\tand \\u0001\""),
+            "{json}"
+        );
+        assert!(
+            json.contains("\"file\":\"dir\\\\\\\"quoted\\\".tpl\""),
+            "{json}"
+        );
+        assert!(json.contains("\"notes\":[\"é\\r\"]"), "{json}");
+    }
+}
@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::FileId;
+#[cfg(feature = "span")]
+use crate::Span;
+
+/// The error returned when parsing fails.
+///
+/// An error either lists the items that were expected where parsing failed or carries a custom
+/// message. Errors of alternatives that failed at the same index are merged into one that expects
+/// any of them, see [`ParseStream::try_parse`](crate::parse::ParseStream::try_parse).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    begin: usize,
+    end: usize,
+    expected: Vec<&'static str>,
+    found: Option<String>,
+    message: Option<String>,
+    /// The 1-based line and column of `begin`, once the error has been located in its source.
+    /// Stored as `u32`s so that results with a `ParseError` stay small.
+    location: Option<(u32, u32)>,
+    /// The file the offsets are in, if the input was registered in a `SourceMap`.
+    file: Option<FileId>,
+    cause: Option<Box<ParseError>>,
+}
+
+impl ParseError {
+    /// An error at `index` that expected `expected` but found `found`, which is `None` at the end of the input.
+    pub fn expected(index: usize, expected: &'static str, found: Option<&str>) -> ParseError {
+        ParseError {
+            begin: index,
+            end: index + found.map_or(0, str::len),
+            expected: vec![expected],
+            found: found.map(String::from),
+            message: None,
+            location: None,
+            file: None,
+            cause: None,
+        }
+    }
+
+    /// An error at `index` that found `found` without expecting anything in particular.
+    pub fn unexpected(index: usize, found: Option<&str>) -> ParseError {
+        ParseError {
+            expected: Vec::new(),
+            ..ParseError::expected(index, "", found)
+        }
+    }
+
+    /// An error at `index` with a message instead of a list of expected items.
+    pub fn custom(index: usize, message: impl Into<String>) -> ParseError {
+        ParseError {
+            begin: index,
+            end: index,
+            expected: Vec::new(),
+            found: None,
+            message: Some(message.into()),
+            location: None,
+            file: None,
+            cause: None,
+        }
+    }
+
+    /// The byte offset parsing failed at.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.begin
+    }
+
+    /// The span of the text that was found instead of an expected item.
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn span(&self) -> Span {
+        Span {
+            begin: self.begin,
+            end: self.end,
+            file: self.file,
+        }
+    }
+
+    /// The file parsing failed in, if the input was registered in a
+    /// [`SourceMap`](crate::SourceMap).
+    #[inline]
+    pub fn file(&self) -> Option<FileId> {
+        self.file
+    }
+
+    #[inline]
+    pub fn expected_items(&self) -> &[&'static str] {
+        &self.expected
+    }
+
+    /// The text that was found instead of an expected item, or `None` at the end of the input.
+    #[inline]
+    pub fn found(&self) -> Option<&str> {
+        self.found.as_deref()
+    }
+
+    #[inline]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The 1-based line and column parsing failed at, if the error has been located with [`ParseError::locate`].
+    #[inline]
+    pub fn location(&self) -> Option<(usize, usize)> {
+        self.location
+            .map(|(line, column)| (line as usize, column as usize))
+    }
+
+    /// The error that caused this one.
+    #[inline]
+    pub fn cause(&self) -> Option<&ParseError> {
+        self.cause.as_deref()
+    }
+
+    /// Replaces the expected items with `expected`, for reporting a failed item by its name
+    /// instead of by the token it starts with.
+    pub fn label(mut self, expected: &'static str) -> ParseError {
+        self.expected = vec![expected];
+        self.message = None;
+        self
+    }
+
+    /// Adds `expected` to the expected items.
+    pub fn or(mut self, expected: &'static str) -> ParseError {
+        if !self.expected.contains(&expected) {
+            self.expected.push(expected);
+        }
+        self
+    }
+
+    /// Marks this error and its causes as errors in `file`, unless they already belong to a file.
+    pub fn in_file(mut self, file: FileId) -> ParseError {
+        self.file = self.file.or(Some(file));
+        self.cause = self.cause.map(|cause| Box::new(cause.in_file(file)));
+        self
+    }
+
+    pub fn with_cause(mut self, cause: ParseError) -> ParseError {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Combines the errors of two alternatives. The one that got further wins, and errors at the
+    /// same index expect the items of both.
+    pub fn merge(self, other: ParseError) -> ParseError {
+        if other.begin > self.begin || (other.begin == self.begin && self.expected.is_empty()) {
+            return other;
+        }
+        if other.begin < self.begin || other.expected.is_empty() {
+            return self;
+        }
+        other.expected.into_iter().fold(self, ParseError::or)
+    }
+
+    /// The message of the error without its location, such as "Expected `;`, found `}`".
+    pub fn summary(&self) -> String {
+        let found = match &self.found {
+            Some(found) => format!("`{}`", found.escape_debug()),
+            None => String::from("end of input"),
+        };
+        match (&self.message, self.expected.as_slice()) {
+            (Some(message), _) => message.clone(),
+            (None, []) => format!("Unexpected {found}"),
+            (None, [only]) => format!("Expected {only}, found {found}"),
+            (None, [init @ .., last]) => {
+                format!("Expected {} or {last}, found {found}", init.join(", "))
+            }
+        }
+    }
+
+    /// Computes the line and column of this error and its causes in `source`, the input that was parsed.
+    pub fn locate(mut self, source: &str) -> ParseError {
+        let before = &source[..self.begin.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        self.location = Some((line as u32, column as u32));
+        self.cause = self.cause.map(|cause| Box::new(cause.locate(source)));
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary())?;
+        match self.location {
+            Some((line, column)) => write!(f, " at {line}:{column}."),
+            None => write!(f, " at {}.", self.begin),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|cause| cause as &(dyn Error + 'static))
+    }
+}
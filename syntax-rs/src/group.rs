@@ -0,0 +1,140 @@
+//! Groups of input surrounded by delimiters, parsed with [`parenthesized!`](crate::parenthesized),
+//! [`bracketed!`](crate::bracketed) and [`braced!`](crate::braced).
+
+#[cfg(feature = "span")]
+use crate::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    /// `( ... )`
+    Parenthesis,
+    /// `[ ... ]`
+    Bracket,
+    /// `{ ... }`
+    Brace,
+}
+
+impl Delimiter {
+    /// The delimiter that `c` opens, if any.
+    pub fn from_open(c: char) -> Option<Delimiter> {
+        match c {
+            '(' => Some(Delimiter::Parenthesis),
+            '[' => Some(Delimiter::Bracket),
+            '{' => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+
+    /// The delimiter that `c` closes, if any.
+    pub fn from_close(c: char) -> Option<Delimiter> {
+        match c {
+            ')' => Some(Delimiter::Parenthesis),
+            ']' => Some(Delimiter::Bracket),
+            '}' => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+
+    pub fn open(self) -> char {
+        match self {
+            Delimiter::Parenthesis => '(',
+            Delimiter::Bracket => '[',
+            Delimiter::Brace => '{',
+        }
+    }
+
+    pub fn close(self) -> char {
+        match self {
+            Delimiter::Parenthesis => ')',
+            Delimiter::Bracket => ']',
+            Delimiter::Brace => '}',
+        }
+    }
+
+    /// The opening delimiter as it is listed in the expected items of an error.
+    pub fn display_open(self) -> &'static str {
+        match self {
+            Delimiter::Parenthesis => "`(`",
+            Delimiter::Bracket => "`[`",
+            Delimiter::Brace => "`{`",
+        }
+    }
+
+    /// The closing delimiter as it is listed in the expected items of an error.
+    pub fn display_close(self) -> &'static str {
+        match self {
+            Delimiter::Parenthesis => "`)`",
+            Delimiter::Bracket => "`]`",
+            Delimiter::Brace => "`}`",
+        }
+    }
+}
+
+/// The delimiters around a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Delimiters {
+    pub delimiter: Delimiter,
+    #[cfg(feature = "span")]
+    pub open: Span,
+    #[cfg(feature = "span")]
+    pub close: Span,
+}
+
+impl Delimiters {
+    /// The span of the whole group, from the opening to the closing delimiter.
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn span(&self) -> Span {
+        Span {
+            end: self.close.end,
+            ..self.open
+        }
+    }
+}
+
+#[cfg(all(test, feature = "span"))]
+mod tests {
+    use super::*;
+    use crate::parse::ParseStream;
+    use crate::Result;
+
+    /// Parses a group of `delimiter` and returns its delimiters and content.
+    fn group(input: &str, delimiter: Delimiter) -> Result<(Delimiters, &str)> {
+        let mut stream = ParseStream::new(input);
+        let mut content;
+        let delimiters = match delimiter {
+            Delimiter::Parenthesis => crate::parenthesized!(content in &mut stream),
+            Delimiter::Bracket => crate::bracketed!(content in &mut stream),
+            Delimiter::Brace => crate::braced!(content in &mut stream),
+        };
+        Ok((delimiters, content.cur().rest()))
+    }
+
+    #[test]
+    fn delimiter_spans() {
+        for delimiter in [Delimiter::Parenthesis, Delimiter::Bracket, Delimiter::Brace] {
+            let input = format!("{}é, [x]{} y", delimiter.open(), delimiter.close());
+            let (delimiters, content) = group(&input, delimiter).unwrap();
+            assert_eq!(content, "é, [x]");
+            assert_eq!(delimiters.delimiter, delimiter);
+            assert_eq!(delimiters.open, Span::new(0, 1));
+            // `é` is two bytes long.
+            assert_eq!(delimiters.close, Span::new(8, 9));
+            assert_eq!(delimiters.span(), Span::new(0, 9));
+        }
+    }
+
+    #[test]
+    fn empty_groups() {
+        let (delimiters, content) = group("{}", Delimiter::Brace).unwrap();
+        assert_eq!(content, "");
+        assert_eq!(delimiters.close, Span::new(1, 2));
+    }
+
+    #[test]
+    fn missing_groups() {
+        let err = group("[x]", Delimiter::Parenthesis).unwrap_err();
+        assert_eq!(err.index(), 0);
+        assert_eq!(err.summary(), "Expected `(`, found `[`");
+    }
+}
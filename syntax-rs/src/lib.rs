@@ -2,31 +2,72 @@
 // TODO: Add no_std feature.
 // TODO: Add some benchmarks.
 
+use cursor::Cursor;
+pub use error::ParseError;
 use parse::{Parse, ParseStream};
+pub use source_map::{FileId, SourceMap};
 
+pub mod ast;
 pub mod compiler;
+pub mod cst;
 pub mod cursor;
 pub mod debug;
+#[cfg(feature = "span")]
+pub mod diagnostics;
+pub mod error;
+pub mod group;
+pub mod lookahead;
 pub mod macros;
 pub mod parse;
+#[cfg(feature = "span")]
+pub mod pratt;
+pub mod punctuated;
 pub mod snapshot;
+pub mod source_map;
 pub mod spec;
 mod utf8;
 
+/// A range of bytes in a source file.
 #[cfg(feature = "span")]
 #[derive(Debug, Default, PartialEq, Eq, Hash, Copy, Clone)]
 pub struct Span {
     pub begin: usize,
     pub end: usize,
+    /// The file the offsets are in, if the input was registered in a `SourceMap`.
+    pub file: Option<FileId>,
 }
 
+#[cfg(feature = "span")]
+impl Span {
+    #[inline]
+    pub fn new(begin: usize, end: usize) -> Span {
+        Span {
+            begin,
+            end,
+            file: None,
+        }
+    }
+
+    #[inline]
+    pub fn in_file(self, file: FileId) -> Span {
+        Span {
+            file: Some(file),
+            ..self
+        }
+    }
+}
+
+/// Derives `Spanned` from a field marked with `#[parse(span)]`, a field named `span` or the only field.
+#[cfg(all(feature = "span", feature = "derive"))]
+pub use syntax_rs_derive::Spanned;
+
 #[cfg(feature = "span")]
 pub trait Spanned {
     fn span(&self) -> Span;
     fn span_ref_mut(&mut self) -> &mut Span;
 }
 
-pub type Result<T> = std::result::Result<T, &'static str>;
+pub type Result<T> = std::result::Result<T, ParseError>;
 
 // TODO: Make the new method private.
 #[inline]
@@ -36,7 +77,7 @@ pub fn parse_stream(input: &str) -> ParseStream {
 
 #[inline]
 pub fn parse<T: Parse>(input: &str) -> Result<T> {
-    T::parse(&mut parse_stream(input))
+    T::parse(&mut parse_stream(input)).map_err(|err| err.locate(input))
 }
 
 /// Parses until the stream is empty or there are only whitespaces left.
@@ -44,7 +85,44 @@ pub fn exhaustive_parse<T: Parse>(input: &str) -> Result<Vec<T>> {
     let mut stream = parse_stream(input);
     let mut results = Vec::new();
     while !stream.is_empty() && !stream.is_only_whitespaces() {
-        results.push(stream.parse::<T>()?);
+        results.push(stream.parse::<T>().map_err(|err| err.locate(input))?);
     }
     Ok(results)
 }
+
+/// Parses a `T` and returns it together with the errors that were recovered from, see
+/// [`ParseStream::report`]. The `T` is `None` if parsing failed without recovering.
+pub fn parse_with_errors<T: Parse>(input: &str) -> (Option<T>, Vec<ParseError>) {
+    let mut stream = parse_stream(input);
+    let result = T::parse(&mut stream);
+    let mut errors = stream.take_errors();
+    let item = match result {
+        Ok(item) => Some(item),
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+    let errors = errors.into_iter().map(|err| err.locate(input)).collect();
+    (item, errors)
+}
+
+/// Like [`exhaustive_parse`], but instead of stopping at the first error it is reported, the input
+/// is skipped up to the next item that one of the `sync` functions recognizes, and parsing
+/// continues from there. Returns the items that were parsed and all the errors.
+pub fn exhaustive_parse_with_errors<T: Parse>(
+    input: &str,
+    sync: &[fn(Cursor) -> bool],
+) -> (Vec<T>, Vec<ParseError>) {
+    let mut stream = parse_stream(input);
+    let mut results = Vec::new();
+    while !stream.is_empty() && !stream.is_only_whitespaces() {
+        results.extend(stream.parse_or_skip(sync));
+    }
+    let errors = stream
+        .take_errors()
+        .into_iter()
+        .map(|err| err.locate(input))
+        .collect();
+    (results, errors)
+}
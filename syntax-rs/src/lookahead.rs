@@ -0,0 +1,117 @@
+//! Checking what comes next without parsing it, for choosing between alternatives without the
+//! backtracking of [`ParseStream::try_parse`](crate::parse::ParseStream::try_parse).
+//!
+//! ```ignore
+//! let lookahead = stream.lookahead1();
+//! if lookahead.peek::<KwLet>() {
+//!     stream.parse().map(Statement::Let)
+//! } else if lookahead.peek::<KwIf>() {
+//!     stream.parse().map(Statement::If)
+//! } else {
+//!     Err(lookahead.error())
+//! }
+//! ```
+
+use std::cell::RefCell;
+
+use crate::{cursor::Cursor, ParseError};
+
+/// Items that can be recognized from the input that follows them. The tokens declared with
+/// [`simple_tok!`](crate::simple_tok) implement it.
+pub trait Peek {
+    /// The name of the item in the expected items of an error, such as "`;`".
+    fn display() -> &'static str;
+
+    /// Whether the input at `cursor` starts with the item.
+    fn peek(cursor: Cursor) -> bool;
+}
+
+/// Tests what the next item is and remembers every item that was tested, so that the error of
+/// an unmatched input expects all of them. Created with [`ParseStream::lookahead1`](crate::parse::ParseStream::lookahead1).
+pub struct Lookahead1<'a> {
+    cursor: Cursor<'a>,
+    expected: RefCell<Vec<&'static str>>,
+}
+
+impl<'a> Lookahead1<'a> {
+    pub(crate) fn new(cursor: Cursor<'a>) -> Lookahead1<'a> {
+        Lookahead1 {
+            cursor,
+            expected: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn peek<T: Peek>(&self) -> bool {
+        if T::peek(self.cursor) {
+            return true;
+        }
+        let mut expected = self.expected.borrow_mut();
+        if !expected.contains(&T::display()) {
+            expected.push(T::display());
+        }
+        false
+    }
+
+    /// An error that expected any of the items that were peeked, such as "Expected `let` or `if`, found `x`".
+    pub fn error(self) -> ParseError {
+        let index = self.cursor.index();
+        let found = self.cursor.peek_n(1);
+        let mut expected = self.expected.into_inner().into_iter();
+        match expected.next() {
+            Some(first) => expected.fold(ParseError::expected(index, first, found), ParseError::or),
+            None => ParseError::unexpected(index, found),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::ParseStream;
+    use crate::simple_tok;
+
+    simple_tok!(KwLet, "let");
+    simple_tok!(KwIf, "if");
+    simple_tok!(Semi, ";");
+
+    #[test]
+    fn error_expects_every_peeked_item() {
+        let mut stream = ParseStream::new(";x");
+        stream.parse::<Semi>().unwrap();
+        let lookahead = stream.lookahead1();
+        assert!(!lookahead.peek::<KwLet>());
+        assert!(!lookahead.peek::<KwIf>());
+        assert!(!lookahead.peek::<KwLet>());
+        let err = lookahead.error();
+        assert_eq!(err.index(), 1);
+        assert_eq!(err.expected_items(), ["`let`", "`if`"]);
+        assert_eq!(err.summary(), "Expected `let` or `if`, found `x`");
+    }
+
+    #[test]
+    fn matched_items_are_not_expected() {
+        let mut stream = ParseStream::new("if");
+        let lookahead = stream.lookahead1();
+        assert!(!lookahead.peek::<KwLet>());
+        assert!(lookahead.peek::<KwIf>());
+        assert!(!lookahead.peek::<Semi>());
+        assert_eq!(lookahead.error().expected_items(), ["`let`", "`;`"]);
+        // Peeking doesn't consume anything.
+        assert_eq!(stream.cur().rest(), "if");
+    }
+
+    #[test]
+    fn error_without_peeked_items() {
+        let stream = ParseStream::new("");
+        let err = stream.lookahead1().error();
+        assert!(err.expected_items().is_empty());
+        assert_eq!(err.summary(), "Unexpected end of input");
+
+        let stream = ParseStream::new("");
+        let lookahead = stream.lookahead1();
+        assert!(!lookahead.peek::<Semi>());
+        assert_eq!(
+            lookahead.error().summary(),
+            "Expected `;`, found end of input"
+        );
+    }
+}
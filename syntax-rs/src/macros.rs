@@ -7,19 +7,33 @@ macro_rules! simple_tok {
         impl $crate::parse::Parse for $ident {
             fn parse(stream: &mut $crate::parse::ParseStream) -> $crate::Result<$ident> {
                 match stream.cur().peek_n($str.len()) {
-                    Some(array) => {
-                        if array == $str {
-                            // TODO: This can be optimized
-                            stream.cur().advance_n($str.len());
-                            Ok($ident)
-                        } else {
-                            Err($crate::concat_all!("Expected `", $str, "`."))
-                        }
+                    Some(array) if array == $str => {
+                        // TODO: This can be optimized
+                        stream.cur().advance_n($str.len());
+                        Ok($ident)
                     }
-                    None => Err($crate::concat_all!("Found EOF but expected `", $str, "`.")),
+                    _ => Err(stream.error($crate::concat_all!("`", $str, "`"))),
                 }
             }
         }
+
+        $crate::peek_tok!($ident, $str);
+    };
+}
+
+/// Implements [`Peek`](crate::lookahead::Peek) for a token that is the literal `$str`.
+#[macro_export]
+macro_rules! peek_tok {
+    ($ident:ident, $str:literal) => {
+        impl $crate::lookahead::Peek for $ident {
+            fn display() -> &'static str {
+                $crate::concat_all!("`", $str, "`")
+            }
+
+            fn peek(cursor: $crate::cursor::Cursor) -> bool {
+                cursor.peek_n($str.chars().count()) == Some($str)
+            }
+        }
     };
 }
 
@@ -43,7 +57,7 @@ macro_rules! simple_tok_spanned {
     ($ident:ident, $str:literal) => {
         cfg_if::cfg_if! {
             if #[cfg(feature = "span")] {
-                #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+                #[derive(Debug, PartialEq, Eq, Hash, Default, Clone, Copy)]
                 struct $ident {
                     span: $crate::Span
                 }
@@ -51,18 +65,18 @@ macro_rules! simple_tok_spanned {
 
                 impl $crate::parse::Parse for $ident {
                     fn parse(stream: &mut $crate::parse::ParseStream) -> $crate::Result<$ident> {
-                        let index = stream.cur().index();
+                        let snapshot = stream.snapshot();
                         match stream.cur().peek_n($str.len()) {
-                            Some(array) => if array == $str {
+                            Some(array) if array == $str => {
                                 stream.cur().advance_n($str.len());
-                                Ok($ident { span: $crate::Span { begin: index, end: stream.cur().index() } })
-                            } else {
-                                Err($crate::concat_all!("Expected `", $str, "`."))
-                            },
-                            None => Err($crate::concat_all!("Found EOF but expected `", $str, "`.")),
+                                Ok($ident { span: stream.since(snapshot) })
+                            }
+                            _ => Err(stream.error($crate::concat_all!("`", $str, "`"))),
                         }
                     }
                 }
+
+                $crate::peek_tok!($ident, $str);
             } else {
                 $crate::simple_tok!($ident,$str);
             }
@@ -79,3 +93,48 @@ macro_rules! concat_all {
         $single
     };
 }
+
+/// Parses a group in parentheses from `$stream`, assigns a stream over its content to `$content`
+/// and evaluates to its [`Delimiters`](crate::group::Delimiters). Returns the error if there is no group.
+///
+/// ```ignore
+/// let mut content;
+/// let parens = parenthesized!(content in stream);
+/// let args = Punctuated::<Expr, Comma>::parse_terminated(&mut content)?;
+/// ```
+#[macro_export]
+macro_rules! parenthesized {
+    ($content:ident in $stream:expr) => {
+        $crate::delimited!($content in $stream, $crate::group::Delimiter::Parenthesis)
+    };
+}
+
+/// Like [`parenthesized!`](crate::parenthesized) for a group in square brackets.
+#[macro_export]
+macro_rules! bracketed {
+    ($content:ident in $stream:expr) => {
+        $crate::delimited!($content in $stream, $crate::group::Delimiter::Bracket)
+    };
+}
+
+/// Like [`parenthesized!`](crate::parenthesized) for a group in curly braces.
+#[macro_export]
+macro_rules! braced {
+    ($content:ident in $stream:expr) => {
+        $crate::delimited!($content in $stream, $crate::group::Delimiter::Brace)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! delimited {
+    ($content:ident in $stream:expr, $delimiter:expr) => {
+        match $crate::parse::ParseStream::parse_delimited($stream, $delimiter) {
+            Ok((delimiters, content)) => {
+                $content = content;
+                delimiters
+            }
+            Err(err) => return Err(err),
+        }
+    };
+}
@@ -1,8 +1,17 @@
+use crate::cst::{Checkpoint, CstBuilder, SyntaxKind, SyntaxNode};
 #[cfg(feature = "debug")]
 use crate::debug::DebugTap;
+use crate::group::{Delimiter, Delimiters};
+use crate::lookahead::{Lookahead1, Peek};
+use crate::punctuated::Punctuated;
 #[cfg(feature = "span")]
 use crate::Span;
-use crate::{cursor::Cursor, snapshot::Snapshot, spec, Result};
+use crate::{cursor::Cursor, snapshot::Snapshot, spec, FileId, ParseError, Result};
+use std::{any::Any, any::TypeId, cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+/// Derives `Parse` for structs and enums, see the `syntax-rs-derive` crate for the attributes it takes.
+#[cfg(feature = "derive")]
+pub use syntax_rs_derive::Parse;
 
 // TODO:: Add a SkipWhitespaceParse trait. Also remove debugging.
 pub trait Parse: Sized {
@@ -11,21 +20,57 @@ pub trait Parse: Sized {
 
 pub struct ParseStream<'a> {
     cursor: Cursor<'a>,
+    /// The file spans created by this stream point into.
+    file: Option<FileId>,
+    /// The merged errors of the alternatives that failed, and the index they were tried at.
+    failed: Option<(usize, ParseError)>,
+    /// The errors that were recovered from, shared with the streams over the content of groups.
+    errors: Rc<RefCell<Vec<ParseError>>>,
+    /// The results of the rules parsed with [`ParseStream::memoize`], if memoization is enabled.
+    memo: Option<HashMap<(TypeId, usize), Memo<'a>>>,
+    /// Skips the trivia before and after the tokens of [`ParseStream::token`].
+    trivia: fn(&mut Cursor),
+    /// The tree of everything that was parsed, in tree mode.
+    cst: Option<Rc<RefCell<CstBuilder<'a>>>>,
+}
+
+/// A memoized result of a rule.
+struct Memo<'a> {
+    /// The `Result<T>` of the rule.
+    result: Box<dyn Any>,
+    /// Where the rule stopped.
+    end: Cursor<'a>,
+    /// The errors the rule reported.
+    errors: Vec<ParseError>,
+    /// The alternatives that failed in the rule, and the index they were tried at.
+    failed: Option<(usize, ParseError)>,
 }
 
 impl<'a> ParseStream<'a> {
     pub fn new(slice: &'a str) -> ParseStream<'a> {
         ParseStream {
             cursor: Cursor::new(slice),
+            file: None,
+            failed: None,
+            errors: Rc::default(),
+            memo: None,
+            trivia: skip_whitespace,
+            cst: None,
         }
     }
 
-    pub fn empty() -> ParseStream<'a> {
+    /// A stream over the source of `file`, whose spans carry the id of the file.
+    pub fn new_in_file(slice: &'a str, file: FileId) -> ParseStream<'a> {
         ParseStream {
-            cursor: Cursor::new(""),
+            file: Some(file),
+            ..ParseStream::new(slice)
         }
     }
 
+    pub fn empty() -> ParseStream<'a> {
+        ParseStream::new("")
+    }
+
     #[inline]
     pub fn snapshot(&self) -> Snapshot {
         Snapshot(self.cursor.index())
@@ -37,13 +82,15 @@ impl<'a> ParseStream<'a> {
         Span {
             begin: snapshot.index(),
             end: self.cursor.index(),
+            file: self.file,
         }
     }
 
     #[inline]
     #[cfg(feature = "debug")]
     pub fn parse<P: Parse>(&mut self) -> Result<P> {
-        P::parse(self).debug_tap(|result| {
+        let result = P::parse(self).map_err(|err| self.in_file(err));
+        result.debug_tap(|result| {
             if result.is_ok() {
                 eprintln!(
                     "[P] at {}:{}    | Sucessfully parsed item {}.",
@@ -65,7 +112,16 @@ impl<'a> ParseStream<'a> {
     #[inline]
     #[cfg(not(feature = "debug"))]
     pub fn parse<P: Parse>(&mut self) -> Result<P> {
-        P::parse(self)
+        P::parse(self).map_err(|err| self.in_file(err))
+    }
+
+    /// Marks `err` as an error in the file of this stream, if it has one.
+    #[inline]
+    fn in_file(&self, err: ParseError) -> ParseError {
+        match self.file {
+            Some(file) => err.in_file(file),
+            None => err,
+        }
     }
 
     #[inline]
@@ -78,6 +134,19 @@ impl<'a> ParseStream<'a> {
         self.cursor.is_empty()
     }
 
+    /// Whether the next item is a `T`, without consuming anything.
+    #[inline]
+    pub fn peek<T: Peek>(&self) -> bool {
+        T::peek(self.cursor)
+    }
+
+    /// A [`Lookahead1`] at the current index, for branching on the next item and reporting all
+    /// the items that were checked if none of them matched.
+    #[inline]
+    pub fn lookahead1(&self) -> Lookahead1<'a> {
+        Lookahead1::new(self.cursor)
+    }
+
     pub fn is_only_whitespaces(&self) -> bool {
         for c in self.cursor.iter() {
             if !spec::is_whitespace(c) {
@@ -87,6 +156,43 @@ impl<'a> ParseStream<'a> {
         true
     }
 
+    /// An error at the current index that expected `expected`, or any of the alternatives that
+    /// failed here, and found the next character.
+    pub fn error(&self, expected: &'static str) -> ParseError {
+        let error = ParseError::expected(self.cursor.index(), expected, self.cursor.peek_n(1));
+        let error = match self.failed_here() {
+            Some(failed) => failed.clone().merge(error),
+            None => error,
+        };
+        self.in_file(error)
+    }
+
+    /// An error at the current index that expected any of the alternatives that failed here.
+    pub fn unexpected(&self) -> ParseError {
+        let error = match self.failed_here() {
+            Some(failed) => failed.clone(),
+            None => ParseError::unexpected(self.cursor.index(), self.cursor.peek_n(1)),
+        };
+        self.in_file(error)
+    }
+
+    fn failed_here(&self) -> Option<&ParseError> {
+        match &self.failed {
+            Some((index, failed)) if *index == self.cursor.index() => Some(failed),
+            _ => None,
+        }
+    }
+
+    /// Remembers that an alternative failed with `error` at `index`, merged with the ones that
+    /// failed there before.
+    fn merge_failed(&mut self, index: usize, error: ParseError) {
+        let failed = match self.failed.take() {
+            Some((i, failed)) if i == index => failed.merge(error),
+            _ => error,
+        };
+        self.failed = Some((index, failed));
+    }
+
     pub fn skip_all(&mut self, mut pred: impl FnMut(char) -> bool) -> bool {
         let mut has_moved = false;
         while !self.is_empty() && pred(self.cursor.peek0().unwrap()) {
@@ -96,8 +202,387 @@ impl<'a> ParseStream<'a> {
         has_moved
     }
 
+    /// Parses a group surrounded by `delimiter` and returns its delimiters and a stream over its
+    /// content. Nested groups of any delimiter are part of the content, and a closing delimiter
+    /// that doesn't match the group it closes, like the `]` in `( ] )`, is an error. Usually used
+    /// through [`parenthesized!`](crate::parenthesized), [`bracketed!`](crate::bracketed) and
+    /// [`braced!`](crate::braced).
+    ///
+    /// The delimiters are matched character by character, so delimiters in string literals and
+    /// comments are counted as well. The content isn't checked for input that is left over after
+    /// parsing it, check [`ParseStream::is_empty`] on the content stream where that is an error.
+    pub fn parse_delimited(
+        &mut self,
+        delimiter: Delimiter,
+    ) -> Result<(Delimiters, ParseStream<'a>)> {
+        let snapshot = self.snapshot();
+        if !self.cursor.consume(delimiter.open()) {
+            return Err(self.error(delimiter.display_open()));
+        }
+
+        let rest = self.cursor.rest();
+        // The groups that are open inside the content, with the index of their opening delimiter.
+        let mut open: Vec<(Delimiter, usize)> = Vec::new();
+        let mut close = None;
+        for (chars, (i, c)) in rest.char_indices().enumerate() {
+            if let Some(inner) = Delimiter::from_open(c) {
+                open.push((inner, self.cursor.index() + i));
+                continue;
+            }
+            let Some(closed) = Delimiter::from_close(c) else {
+                continue;
+            };
+            let (expected, open_index) = open
+                .last()
+                .copied()
+                .unwrap_or((delimiter, snapshot.index()));
+            if closed != expected {
+                let unclosed = ParseError::custom(open_index, "Unclosed delimiter");
+                return Err(ParseError::expected(
+                    self.cursor.index() + i,
+                    expected.display_close(),
+                    Some(&rest[i..i + c.len_utf8()]),
+                )
+                .with_cause(unclosed));
+            }
+            if open.pop().is_none() {
+                close = Some((i, chars));
+                break;
+            }
+        }
+        let Some((len, chars)) = close else {
+            let (expected, open_index) = open
+                .last()
+                .copied()
+                .unwrap_or((delimiter, snapshot.index()));
+            let unclosed = ParseError::custom(open_index, "Unclosed delimiter");
+            return Err(ParseError::expected(
+                self.cursor.index() + rest.len(),
+                expected.display_close(),
+                None,
+            )
+            .with_cause(unclosed));
+        };
+
+        let content = ParseStream {
+            cursor: self.cursor.truncated(self.cursor.index() + len),
+            file: self.file,
+            failed: None,
+            errors: Rc::clone(&self.errors),
+            // The content ends earlier, so the results of the rules may differ from this stream.
+            memo: self.memo.as_ref().map(|_| HashMap::new()),
+            trivia: self.trivia,
+            cst: self.cst.clone(),
+        };
+        #[cfg(feature = "span")]
+        let open = self.since(snapshot);
+        self.cursor.advance_n(chars);
+        #[cfg(feature = "span")]
+        let close = self.snapshot();
+        self.cursor.advance();
+        let delimiters = Delimiters {
+            delimiter,
+            #[cfg(feature = "span")]
+            open,
+            #[cfg(feature = "span")]
+            close: self.since(close),
+        };
+        Ok((delimiters, content))
+    }
+
+    /// Parses `T`s until one of them fails. The failed item is backtracked out of and remembered
+    /// like the alternatives of [`ParseStream::try_parse`]. An item that doesn't consume anything,
+    /// like an `Option` that is `None`, is the last one, since it would be parsed again forever.
+    pub fn many0<T: Parse>(&mut self) -> Vec<T> {
+        let mut items = Vec::new();
+        loop {
+            let start = self.cursor.index();
+            let Ok(item) = self.try_parse(T::parse) else {
+                break;
+            };
+            items.push(item);
+            if self.cursor.index() == start {
+                break;
+            }
+        }
+        items
+    }
+
+    /// Like [`ParseStream::many0`], but fails if there isn't at least one `T`.
+    pub fn many1<T: Parse>(&mut self) -> Result<Vec<T>> {
+        let mut items = vec![self.parse()?];
+        items.append(&mut self.many0());
+        Ok(items)
+    }
+
+    /// Parses zero or more `T`s separated by `P`s, allowing a trailing `P`. Stops before the first
+    /// `T` or `P` that fails, which is backtracked out of, and after a `T` and `P` that didn't
+    /// consume anything.
+    pub fn separated<T: Parse, P: Parse>(&mut self) -> Punctuated<T, P> {
+        let mut punctuated = Punctuated::new();
+        loop {
+            let start = self.cursor.index();
+            let Ok(item) = self.try_parse(T::parse) else {
+                break;
+            };
+            punctuated.push_value(item);
+            match self.try_parse(P::parse) {
+                Ok(punct) => punctuated.push_punct(punct),
+                Err(_) => break,
+            }
+            if self.cursor.index() == start {
+                break;
+            }
+        }
+        punctuated
+    }
+
+    /// Reports an error that parsing recovered from. The errors are collected until they are
+    /// taken with [`ParseStream::take_errors`].
+    pub fn report(&self, err: ParseError) {
+        self.errors.borrow_mut().push(self.in_file(err));
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.borrow().is_empty()
+    }
+
+    /// Takes the reported errors, including those of the streams over the content of groups.
+    pub fn take_errors(&self) -> Vec<ParseError> {
+        mem::take(&mut self.errors.borrow_mut())
+    }
+
+    /// Skips input until it starts with an item that one of the `sync` functions, such as
+    /// [`Peek::peek`], recognizes, or until it is empty. Returns whether anything was skipped.
+    pub fn skip_until(&mut self, sync: &[fn(Cursor) -> bool]) -> bool {
+        let mut skipped = false;
+        while !self.is_empty() && !sync.iter().any(|peek| peek(self.cursor)) {
+            self.cursor.advance();
+            skipped = true;
+        }
+        if let (true, Some(cst)) = (skipped, &self.cst) {
+            cst.borrow_mut().skipped(self.cursor.index());
+        }
+        skipped
+    }
+
+    /// Parses a `T`, or reports the error, skips to the next item in `sync` and returns `None`.
+    /// At least one character is skipped if the `T` failed where it started, so that a loop over
+    /// `parse_or_skip` always makes progress.
+    pub fn parse_or_skip<T: Parse>(&mut self, sync: &[fn(Cursor) -> bool]) -> Option<T> {
+        let start = self.cursor.index();
+        match self.parse() {
+            Ok(item) => Some(item),
+            Err(err) => {
+                self.report(err);
+                if !self.skip_until(sync) && self.cursor.index() == start {
+                    self.cursor.advance();
+                    self.skip_until(sync);
+                }
+                if let Some(cst) = &self.cst {
+                    cst.borrow_mut().skipped(self.cursor.index());
+                }
+                None
+            }
+        }
+    }
+
+    /// Parses a `T`, or parses an error node: reports the error, skips to the next item in
+    /// `sync` and returns `error_node` of the span that was skipped.
+    #[cfg(feature = "span")]
+    pub fn parse_or_error_node<T: Parse>(
+        &mut self,
+        sync: &[fn(Cursor) -> bool],
+        error_node: impl FnOnce(Span) -> T,
+    ) -> T {
+        let snapshot = self.snapshot();
+        match self.parse_or_skip(sync) {
+            Some(item) => item,
+            None => error_node(self.since(snapshot)),
+        }
+    }
+
+    /// Parses a `T` if it comes next, or inserts it: reports that it was expected and returns
+    /// `T::default()` without consuming anything. Used for missing tokens like a forgotten `;`.
+    pub fn parse_or_insert<T: Parse + Peek + Default>(&mut self) -> T {
+        if !self.peek::<T>() {
+            self.report(self.error(T::display()));
+            return T::default();
+        }
+        self.parse().unwrap_or_else(|err| {
+            self.report(err);
+            T::default()
+        })
+    }
+
+    /// Enables the memo table of [`ParseStream::memoize`]. Memoizing the rules that alternatives
+    /// share makes a backtracking parser run in linear time, at the cost of storing every result.
+    pub fn enable_memo(&mut self) {
+        self.memo.get_or_insert_with(HashMap::new);
+    }
+
+    /// Parses the rule `T` with `parse_fn`, or returns the result it had the last time it was
+    /// parsed at the current index, and moves to where it stopped. Memoization has to be enabled
+    /// with [`ParseStream::enable_memo`], otherwise this just calls `parse_fn`. A type must only be
+    /// parsed by one rule, since the results are found by the type and the index. The alternatives
+    /// that failed in the rule are replayed too, so later errors expect the same things either way.
+    pub fn memoize<T: Clone + 'static>(
+        &mut self,
+        parse_fn: impl FnOnce(&mut ParseStream<'a>) -> Result<T>,
+    ) -> Result<T> {
+        let key = (TypeId::of::<T>(), self.cursor.index());
+        if let Some(memo) = self.memo.as_ref().and_then(|memo| memo.get(&key)) {
+            let result = memo.result.downcast_ref::<Result<T>>().unwrap().clone();
+            self.cursor = memo.end;
+            self.errors.borrow_mut().extend_from_slice(&memo.errors);
+            if let Some((index, failed)) = memo.failed.clone() {
+                self.merge_failed(index, failed);
+            }
+            return result;
+        }
+        // Results that are replayed from the memo table would be missing from the tree.
+        if self.memo.is_none() || self.cst.is_some() {
+            return parse_fn(self);
+        }
+
+        // The rule is parsed without the alternatives that failed before it, so that what it
+        // adds to them can be merged in the same way when it is replayed.
+        let reported = self.errors.borrow().len();
+        let outer = self.failed.take();
+        let result = parse_fn(self);
+        let failed = std::mem::replace(&mut self.failed, outer);
+        if let Some((index, failed)) = failed.clone() {
+            self.merge_failed(index, failed);
+        }
+        let memo = Memo {
+            result: Box::new(result.clone()),
+            end: self.cursor,
+            errors: self.errors.borrow()[reported..].to_vec(),
+            failed,
+        };
+        self.memo.as_mut().unwrap().insert(key, memo);
+        result
+    }
+
+    /// Parses a `T` with [`ParseStream::memoize`]. `T::parse` must not memoize itself, use
+    /// `#[parse(memo)]` of `#[derive(Parse)]` for that instead.
+    #[inline]
+    pub fn parse_memo<T: Parse + Clone + 'static>(&mut self) -> Result<T> {
+        self.memoize(T::parse)
+    }
+
+    /// Sets how [`ParseStream::token`] skips trivia, such as whitespaces and comments. `trivia`
+    /// has to advance the cursor past any trivia it starts at. Skips whitespaces by default.
+    pub fn set_trivia(&mut self, trivia: fn(&mut Cursor)) {
+        self.trivia = trivia;
+    }
+
+    /// Whether there is only trivia left.
+    pub fn is_only_trivia(&self) -> bool {
+        let mut cursor = self.cursor;
+        (self.trivia)(&mut cursor);
+        cursor.is_empty()
+    }
+
+    /// Enables tree mode, in which the tokens and nodes parsed with [`ParseStream::token`] and
+    /// [`ParseStream::node`] are recorded into a lossless [concrete syntax tree](crate::cst).
+    /// Input that is consumed outside of tokens, like input skipped while recovering from errors,
+    /// is recorded as [`SyntaxKind::ERROR`] tokens. That includes the delimiters of
+    /// [`ParseStream::parse_delimited`], so in tree mode they should be parsed as tokens instead.
+    pub fn enable_cst(&mut self) {
+        let cst = CstBuilder::new(self.cursor.source(), self.cursor.index());
+        self.cst = Some(Rc::new(RefCell::new(cst)));
+    }
+
+    /// Parses a token with `parse_fn`, skipping the trivia before it and the trivia after it up
+    /// to the end of the line. In tree mode the token and its trivia are added to the tree as a
+    /// token of kind `kind`. If `parse_fn` fails, the cursor is moved back before the trivia.
+    pub fn token<T>(
+        &mut self,
+        kind: SyntaxKind,
+        parse_fn: impl FnOnce(&mut ParseStream<'a>) -> Result<T>,
+    ) -> Result<T> {
+        let begin = self.cursor.index();
+        (self.trivia)(&mut self.cursor);
+        let text_begin = self.cursor.index();
+        let item = match parse_fn(self) {
+            Ok(item) => item,
+            Err(err) => {
+                // The trivia is skipped again by the next token.
+                self.cursor.seek(begin);
+                return Err(err);
+            }
+        };
+        let text_end = self.cursor.index();
+
+        let rest = self.cursor.rest();
+        let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let mut line = self.cursor.truncated(text_end + line_end);
+        (self.trivia)(&mut line);
+        self.cursor.seek(line.index());
+
+        if let Some(cst) = &self.cst {
+            let end = self.cursor.index();
+            cst.borrow_mut()
+                .token(kind, begin, text_begin..text_end, end);
+        }
+        Ok(item)
+    }
+
+    /// Parses something with `parse_fn` and in tree mode wraps the tokens and nodes it parsed
+    /// in a node of kind `kind`. The node is added even if `parse_fn` fails, so that the tree
+    /// keeps what was parsed when the error is recovered from.
+    pub fn node<T>(
+        &mut self,
+        kind: SyntaxKind,
+        parse_fn: impl FnOnce(&mut ParseStream<'a>) -> Result<T>,
+    ) -> Result<T> {
+        let checkpoint = self.checkpoint();
+        self.node_at(checkpoint, kind, parse_fn)
+    }
+
+    /// The position in the tree that [`ParseStream::node_at`] can start a node at later.
+    pub fn checkpoint(&self) -> Checkpoint {
+        match &self.cst {
+            Some(cst) => cst.borrow().checkpoint(),
+            None => Checkpoint(0),
+        }
+    }
+
+    /// Like [`ParseStream::node`], but the node starts at `checkpoint` and also contains what was
+    /// parsed since then, such as the left operand of a binary expression.
+    pub fn node_at<T>(
+        &mut self,
+        checkpoint: Checkpoint,
+        kind: SyntaxKind,
+        parse_fn: impl FnOnce(&mut ParseStream<'a>) -> Result<T>,
+    ) -> Result<T> {
+        let result = parse_fn(self);
+        if let Some(cst) = &self.cst {
+            cst.borrow_mut().wrap(checkpoint, kind);
+        }
+        result
+    }
+
+    /// Leaves tree mode and returns the tree of everything that was parsed, as a node of kind
+    /// `root`. The rest of the input is consumed: trivia becomes the leading trivia of a
+    /// [`SyntaxKind::EOF`] token, and anything else is added as a [`SyntaxKind::ERROR`] token before it.
+    /// Returns `None` if the stream is not in tree mode.
+    pub fn finish_cst(&mut self, root: SyntaxKind) -> Option<SyntaxNode> {
+        let cst = self.cst.take()?;
+        let cst = Rc::try_unwrap(cst)
+            .ok()
+            .expect(
+                "ParseStream::finish_cst: the stream over the content of a group is still alive",
+            )
+            .into_inner();
+        self.cursor.seek(self.cursor.source().len());
+        Some(SyntaxNode::new_root(cst.finish(root, self.trivia)))
+    }
+
     /// Tries to parse something using the `parse_fn` parameter.
-    /// On failure; the cursor is reset to it's original value.<br>
+    /// On failure; the cursor is reset to it's original value and the error is remembered, so
+    /// that the errors of later alternatives tried at the same index expect it as well.<br>
     /// **NOTE: This function is generally much more expensive than just doing a simple check before parsing.**
     pub fn try_parse<R>(
         &mut self,
@@ -106,6 +591,8 @@ impl<'a> ParseStream<'a> {
         // FIXME: Do i need to reset the spans on error?
         // TODO: Maybe we can do something clever here to avoid expensive cloning?
         let original = self.cursor;
+        let reported = self.errors.borrow().len();
+        let tree = self.cst.as_ref().map(|cst| cst.borrow().state());
         match parse_fn(self) {
             ok @ Ok(_) => {
                 #[cfg(feature = "debug")]
@@ -117,7 +604,7 @@ impl<'a> ParseStream<'a> {
                 );
                 ok
             }
-            e @ Err(_) => {
+            Err(e) => {
                 #[cfg(feature = "debug")]
                 eprintln!(
                     "[P&R] at {}:{} | Failed to parse item {}. Reversed the cursor to {:?}",
@@ -127,8 +614,182 @@ impl<'a> ParseStream<'a> {
                     self.cursor
                 );
                 self.cursor = original;
-                e
+                // Errors recovered from in an alternative that is backtracked out of are not errors.
+                self.errors.borrow_mut().truncate(reported);
+                if let (Some(cst), Some(tree)) = (&self.cst, tree) {
+                    cst.borrow_mut().rollback(tree);
+                }
+                self.merge_failed(original.index(), e.clone());
+                Err(e)
             }
         }
     }
 }
+
+fn skip_whitespace(cursor: &mut Cursor) {
+    cursor.advance_while(spec::is_whitespace);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_tok;
+
+    simple_tok!(A, "a");
+    simple_tok!(Comma, ",");
+    simple_tok!(B, "b");
+    simple_tok!(C, "c");
+
+    /// Matches without consuming anything.
+    struct Nothing;
+
+    impl Parse for Nothing {
+        fn parse(_: &mut ParseStream) -> Result<Nothing> {
+            Ok(Nothing)
+        }
+    }
+
+    #[test]
+    fn many0_stops_at_items_without_progress() {
+        let mut stream = ParseStream::new("aa,");
+        assert_eq!(stream.many0::<A>().len(), 2);
+        assert_eq!(stream.many0::<Nothing>().len(), 1);
+        assert_eq!(stream.cur().rest(), ",");
+    }
+
+    #[test]
+    fn separated_stops_at_items_without_progress() {
+        let mut stream = ParseStream::new("a,a,a");
+        assert_eq!(stream.separated::<A, Comma>().len(), 3);
+        assert!(stream.is_empty());
+
+        let mut stream = ParseStream::new(",");
+        assert_eq!(stream.separated::<Nothing, Nothing>().len(), 1);
+        // A `Nothing` followed by a `Comma` makes progress.
+        assert_eq!(stream.separated::<Nothing, Comma>().len(), 2);
+        assert!(stream.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "span")]
+    fn errors_carry_the_file_of_the_stream() {
+        let mut map = crate::SourceMap::new();
+        map.add_file("a.tpl", "");
+        let file = map.add_file("b.tpl", "ac");
+        let mut stream = ParseStream::new_in_file(map.file(file).source(), file);
+        stream.parse::<A>().ok().unwrap();
+        let err = stream.parse::<B>().err().unwrap();
+        assert_eq!(err.file(), Some(file));
+        assert_eq!(err.span(), Span::new(1, 2).in_file(file));
+
+        stream.report(ParseError::custom(1, "Unexpected `c`").with_cause(err));
+        let reported = stream.take_errors().pop().unwrap();
+        assert_eq!(reported.file(), Some(file));
+        assert_eq!(reported.cause().unwrap().file(), Some(file));
+        assert_eq!(map.file_of(reported.span()).unwrap().name(), "b.tpl");
+    }
+
+    #[test]
+    #[cfg(feature = "span")]
+    #[should_panic(expected = "The span 1..2 doesn't point into `a.tpl`.")]
+    fn diagnostics_reject_errors_of_other_files() {
+        let mut map = crate::SourceMap::new();
+        let other = map.add_file("a.tpl", "");
+        let file = map.add_file("b.tpl", "ac");
+        let mut stream = ParseStream::new_in_file(map.file(file).source(), file);
+        stream.parse::<A>().ok().unwrap();
+        let err = stream.parse::<B>().err().unwrap();
+        crate::diagnostics::Diagnostic::from(&err).render(map.file(other), false);
+    }
+
+    #[test]
+    fn parse_delimited_nests_all_delimiters() {
+        let mut stream = ParseStream::new("(a [b (c)] {()}) d");
+        let (_, mut content) = stream.parse_delimited(Delimiter::Parenthesis).ok().unwrap();
+        assert_eq!(content.cur().rest(), "a [b (c)] {()}");
+        assert_eq!(stream.cur().rest(), " d");
+    }
+
+    #[test]
+    fn parse_delimited_rejects_mismatched_delimiters() {
+        let mut stream = ParseStream::new("( ] )");
+        let err = stream
+            .parse_delimited(Delimiter::Parenthesis)
+            .err()
+            .unwrap();
+        assert_eq!(err.index(), 2);
+        assert_eq!(err.expected_items(), ["`)`"]);
+        assert_eq!(err.found(), Some("]"));
+        assert_eq!(err.cause().unwrap().index(), 0);
+
+        // The innermost group is the one that is closed by the wrong delimiter.
+        let mut stream = ParseStream::new("(a [b) c]");
+        let err = stream
+            .parse_delimited(Delimiter::Parenthesis)
+            .err()
+            .unwrap();
+        assert_eq!(err.index(), 5);
+        assert_eq!(err.expected_items(), ["`]`"]);
+        assert_eq!(err.cause().unwrap().index(), 3);
+    }
+
+    #[test]
+    fn parse_delimited_rejects_unclosed_groups() {
+        let mut stream = ParseStream::new("(a {b)");
+        let err = stream
+            .parse_delimited(Delimiter::Parenthesis)
+            .err()
+            .unwrap();
+        assert_eq!(err.index(), 5);
+        assert_eq!(err.expected_items(), ["`}`"]);
+
+        let mut stream = ParseStream::new("(a {b}");
+        let err = stream
+            .parse_delimited(Delimiter::Parenthesis)
+            .err()
+            .unwrap();
+        assert_eq!(err.index(), 6);
+        assert_eq!(err.expected_items(), ["`)`"]);
+        assert_eq!(err.found(), None);
+        assert_eq!(err.cause().unwrap().message(), Some("Unclosed delimiter"));
+    }
+
+    /// An `a` followed by an optional `,`.
+    #[derive(Clone)]
+    struct OptionalComma;
+
+    fn optional_comma(stream: &mut ParseStream) -> Result<OptionalComma> {
+        stream.parse::<A>()?;
+        let _ = stream.try_parse(|stream| stream.parse::<Comma>());
+        Ok(OptionalComma)
+    }
+
+    /// `a,? b` or `a,? c`, with the `a,?` parsed twice.
+    fn alternatives(stream: &mut ParseStream) -> Result<()> {
+        let b = stream.try_parse(|stream| {
+            stream.memoize(optional_comma)?;
+            stream.parse::<B>()
+        });
+        let e = match b {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        assert_eq!(e.summary(), "Expected `,` or `b`, found `;`");
+        stream.memoize(optional_comma)?;
+        stream.parse::<C>()?;
+        Ok(())
+    }
+
+    #[test]
+    fn memoize_replays_failed_alternatives() {
+        let mut stream = ParseStream::new("a;");
+        let fresh = alternatives(&mut stream).unwrap_err();
+
+        let mut stream = ParseStream::new("a;");
+        stream.enable_memo();
+        let replayed = alternatives(&mut stream).unwrap_err();
+
+        assert_eq!(fresh.summary(), "Expected `,` or `c`, found `;`");
+        assert_eq!(replayed.summary(), fresh.summary());
+    }
+}
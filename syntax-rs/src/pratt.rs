@@ -0,0 +1,282 @@
+//! Operator precedence parsing of expressions.
+//!
+//! A [`Pratt`] parser is built from a function that parses atoms, such as literals and
+//! parenthesized expressions, and the prefix, infix and postfix operators of the language. The
+//! operators are tokens that implement [`Peek`] and [`Parse`], like those declared with
+//! [`simple_tok!`](crate::simple_tok). Operators with a higher precedence bind tighter, and every
+//! node gets the span from the beginning of its first operand or operator to the end of its last.
+//!
+//! ```ignore
+//! let pratt = Pratt::new(|stream, _| stream.parse().map(Expr::Int))
+//!     .infix::<Plus>(1, Assoc::Left, |lhs, _, rhs, span| Expr::Add(Box::new(lhs), Box::new(rhs), span))
+//!     .infix::<Star>(2, Assoc::Left, |lhs, _, rhs, span| Expr::Mul(Box::new(lhs), Box::new(rhs), span))
+//!     .prefix::<Minus>(3, |_, expr, span| Expr::Neg(Box::new(expr), span));
+//! let expr = pratt.parse(&mut stream)?;
+//! ```
+
+use crate::cursor::Cursor;
+use crate::lookahead::Peek;
+use crate::parse::{Parse, ParseStream};
+use crate::snapshot::Snapshot;
+use crate::{spec, Result, Span};
+
+/// How operators with the same precedence group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `a = b = c` is `a = (b = c)`.
+    Right,
+}
+
+type AtomFn<'p, E> = dyn Fn(&mut ParseStream, &Pratt<'p, E>) -> Result<E> + 'p;
+/// Parses a prefix operator and its operand, given the snapshot before the operator.
+type PrefixFn<'p, E> = dyn Fn(&mut ParseStream, &Pratt<'p, E>, Snapshot) -> Result<E> + 'p;
+/// Parses an infix or postfix operator after `lhs`, given the snapshot before `lhs`.
+type SuffixFn<'p, E> = dyn Fn(&mut ParseStream, &Pratt<'p, E>, E, Snapshot) -> Result<E> + 'p;
+
+struct Operator<F: ?Sized> {
+    peek: fn(Cursor) -> bool,
+    display: &'static str,
+    /// The binding power on the left of an infix or postfix operator.
+    left_bp: u32,
+    parse: Box<F>,
+}
+
+pub struct Pratt<'p, E> {
+    atom: Box<AtomFn<'p, E>>,
+    prefix: Vec<Operator<PrefixFn<'p, E>>>,
+    suffix: Vec<Operator<SuffixFn<'p, E>>>,
+    skip_whitespace: bool,
+}
+
+impl<'p, E: 'p> Pratt<'p, E> {
+    /// A parser without operators that parses atoms with `atom`. `atom` gets the parser itself
+    /// as well, so that it can parse the expressions inside parentheses.
+    pub fn new(atom: impl Fn(&mut ParseStream, &Pratt<'p, E>) -> Result<E> + 'p) -> Pratt<'p, E> {
+        Pratt {
+            atom: Box::new(atom),
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            skip_whitespace: false,
+        }
+    }
+
+    /// Skips whitespaces before atoms and operators.
+    pub fn skip_whitespace(mut self, skip: bool) -> Pratt<'p, E> {
+        self.skip_whitespace = skip;
+        self
+    }
+
+    /// Adds a prefix operator. `build` gets the operator, the operand and the span of both.
+    pub fn prefix<O: Peek + Parse + 'p>(
+        mut self,
+        precedence: u32,
+        build: impl Fn(O, E, Span) -> E + 'p,
+    ) -> Pratt<'p, E> {
+        let right_bp = precedence * 2 + 1;
+        self.prefix.push(Operator {
+            peek: O::peek,
+            display: O::display(),
+            left_bp: 0,
+            parse: Box::new(move |stream, pratt, snapshot| {
+                let op = stream.parse::<O>()?;
+                let operand = pratt.parse_bp(stream, right_bp)?;
+                Ok(build(op, operand, stream.since(snapshot)))
+            }),
+        });
+        self
+    }
+
+    /// Adds an infix operator. `build` gets the left operand, the operator, the right operand
+    /// and the span of all three.
+    pub fn infix<O: Peek + Parse + 'p>(
+        mut self,
+        precedence: u32,
+        assoc: Assoc,
+        build: impl Fn(E, O, E, Span) -> E + 'p,
+    ) -> Pratt<'p, E> {
+        let (left_bp, right_bp) = match assoc {
+            Assoc::Left => (precedence * 2, precedence * 2 + 1),
+            Assoc::Right => (precedence * 2 + 1, precedence * 2),
+        };
+        self.suffix.push(Operator {
+            peek: O::peek,
+            display: O::display(),
+            left_bp,
+            parse: Box::new(move |stream, pratt, lhs, snapshot| {
+                let op = stream.parse::<O>()?;
+                let rhs = pratt.parse_bp(stream, right_bp)?;
+                Ok(build(lhs, op, rhs, stream.since(snapshot)))
+            }),
+        });
+        self
+    }
+
+    /// Adds a postfix operator. `build` gets the operand, the operator and the span of both.
+    pub fn postfix<O: Peek + Parse + 'p>(
+        mut self,
+        precedence: u32,
+        build: impl Fn(E, O, Span) -> E + 'p,
+    ) -> Pratt<'p, E> {
+        self.suffix.push(Operator {
+            peek: O::peek,
+            display: O::display(),
+            left_bp: precedence * 2,
+            parse: Box::new(move |stream, _, operand, snapshot| {
+                let op = stream.parse::<O>()?;
+                Ok(build(operand, op, stream.since(snapshot)))
+            }),
+        });
+        self
+    }
+
+    /// Parses an expression. Operators are checked in the order they were added, so an operator
+    /// that starts with another one, like `**` and `*`, has to be added first.
+    pub fn parse(&self, stream: &mut ParseStream) -> Result<E> {
+        self.parse_bp(stream, 0)
+    }
+
+    /// Finds the first operator in `operators` that comes next, after any whitespaces that are skipped.
+    fn peek<'o, F: ?Sized>(
+        &self,
+        stream: &mut ParseStream,
+        operators: &'o [Operator<F>],
+    ) -> Option<&'o Operator<F>> {
+        let mut cursor = *stream.cur();
+        if self.skip_whitespace {
+            cursor.advance_while(spec::is_whitespace);
+        }
+        operators.iter().find(|op| (op.peek)(cursor))
+    }
+
+    fn parse_bp(&self, stream: &mut ParseStream, min_bp: u32) -> Result<E> {
+        if self.skip_whitespace {
+            stream.skip_all(spec::is_whitespace);
+        }
+        let snapshot = stream.snapshot();
+        let mut lhs = match self.peek(stream, &self.prefix) {
+            Some(op) => (op.parse)(stream, self, snapshot)?,
+            // An atom was expected, or any of the prefix operators.
+            None => (self.atom)(stream, self).map_err(|err| {
+                self.prefix
+                    .iter()
+                    .fold(err, |err, op| err.merge(stream.error(op.display)))
+            })?,
+        };
+
+        while let Some(op) = self.peek(stream, &self.suffix) {
+            if op.left_bp < min_bp {
+                break;
+            }
+            if self.skip_whitespace {
+                stream.skip_all(spec::is_whitespace);
+            }
+            lhs = (op.parse)(stream, self, lhs, snapshot)?;
+        }
+        Ok(lhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_tok;
+
+    simple_tok!(Plus, "+");
+    simple_tok!(Minus, "-");
+    simple_tok!(StarStar, "**");
+    simple_tok!(Star, "*");
+    simple_tok!(Equals, "=");
+    simple_tok!(Tilde, "~");
+    simple_tok!(Bang, "!");
+
+    /// A letter or a digit.
+    fn atom(stream: &mut ParseStream, _: &Pratt<String>) -> Result<String> {
+        let error = stream.error("atom");
+        match stream.cur().advance_while(char::is_alphanumeric) {
+            "" => Err(error),
+            atom => Ok(atom.to_string()),
+        }
+    }
+
+    fn prefix<O>(op: &'static str) -> impl Fn(O, String, Span) -> String {
+        move |_, expr, span| format!("({op}{expr})@{}..{}", span.begin, span.end)
+    }
+
+    fn infix<O>(op: &'static str) -> impl Fn(String, O, String, Span) -> String {
+        move |lhs, _, rhs, span| format!("({lhs} {op} {rhs})@{}..{}", span.begin, span.end)
+    }
+
+    fn postfix<O>(op: &'static str) -> impl Fn(String, O, Span) -> String {
+        move |expr, _, span| format!("({expr}{op})@{}..{}", span.begin, span.end)
+    }
+
+    fn pratt() -> Pratt<'static, String> {
+        Pratt::new(atom)
+            .skip_whitespace(true)
+            .infix::<Equals>(0, Assoc::Right, infix("="))
+            .infix::<Plus>(1, Assoc::Left, infix("+"))
+            .infix::<Minus>(1, Assoc::Left, infix("-"))
+            .infix::<StarStar>(3, Assoc::Right, infix("**"))
+            .infix::<Star>(2, Assoc::Left, infix("*"))
+            .prefix::<Minus>(4, prefix("-"))
+            .prefix::<Tilde>(0, prefix("~"))
+            .postfix::<Bang>(5, postfix("!"))
+    }
+
+    fn parse(code: &str) -> String {
+        pratt().parse(&mut ParseStream::new(code)).ok().unwrap()
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(parse("1 - 2 - 3"), "((1 - 2)@0..5 - 3)@0..9");
+        assert_eq!(parse("a = b = c"), "(a = (b = c)@4..9)@0..9");
+        assert_eq!(parse("a = 1 + 2"), "(a = (1 + 2)@4..9)@0..9");
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(1 + (2 * 3)@4..9)@0..9");
+        assert_eq!(parse("1 * 2 + 3"), "((1 * 2)@0..5 + 3)@0..9");
+    }
+
+    #[test]
+    fn prefix_operators() {
+        assert_eq!(parse("-1 + 2"), "((-1)@0..2 + 2)@0..6");
+        // `~` binds looser than `+`, so it takes the whole sum.
+        assert_eq!(parse("~1 + 2"), "(~(1 + 2)@1..6)@0..6");
+        assert_eq!(parse("1 + ~2 + 3"), "(1 + (~(2 + 3)@5..10)@4..10)@0..10");
+        assert_eq!(parse("--1"), "(-(-1)@1..3)@0..3");
+    }
+
+    #[test]
+    fn postfix_operators() {
+        assert_eq!(parse("1 + 2!"), "(1 + (2!)@4..6)@0..6");
+        assert_eq!(parse("-1!"), "(-(1!)@1..3)@0..3");
+        assert_eq!(parse("1!!"), "((1!)@0..2!)@0..3");
+    }
+
+    #[test]
+    fn operators_that_start_with_others() {
+        assert_eq!(
+            parse("2 * 3 ** 2 ** 2"),
+            "(2 * (3 ** (2 ** 2)@9..15)@4..15)@0..15"
+        );
+
+        // With `*` added first, the `**` is read as two `*`s.
+        let pratt = Pratt::new(atom)
+            .skip_whitespace(true)
+            .infix::<Star>(2, Assoc::Left, infix("*"))
+            .infix::<StarStar>(3, Assoc::Right, infix("**"));
+        let err = pratt.parse(&mut ParseStream::new("2 ** 3")).err().unwrap();
+        assert_eq!(err.summary(), "Expected atom, found `*`");
+    }
+
+    #[test]
+    fn spans_skip_whitespaces() {
+        assert_eq!(parse("  1 +  2  "), "(1 + 2)@2..8");
+        assert_eq!(parse(" - 1"), "(-1)@1..4");
+    }
+}
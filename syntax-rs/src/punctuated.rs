@@ -0,0 +1,252 @@
+//! Sequences of items separated by punctuation, such as the comma separated arguments of a call.
+
+use std::{iter, option, slice, vec};
+
+use crate::lookahead::Peek;
+use crate::parse::{Parse, ParseStream};
+use crate::Result;
+
+/// A sequence of `T`s separated by `P`s, optionally followed by a trailing `P`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Punctuated<T, P> {
+    pairs: Vec<(T, P)>,
+    /// The last item if it isn't followed by punctuation.
+    last: Option<Box<T>>,
+}
+
+impl<T, P> Punctuated<T, P> {
+    pub fn new() -> Punctuated<T, P> {
+        Punctuated {
+            pairs: Vec::new(),
+            last: None,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pairs.len() + self.last.is_some() as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty() && self.last.is_none()
+    }
+
+    /// Whether the sequence ends with punctuation. False if it is empty.
+    #[inline]
+    pub fn trailing_punct(&self) -> bool {
+        self.last.is_none() && !self.pairs.is_empty()
+    }
+
+    /// Whether a value can be pushed, which is when the sequence is empty or ends with punctuation.
+    #[inline]
+    pub fn empty_or_trailing(&self) -> bool {
+        self.last.is_none()
+    }
+
+    /// # Panics
+    /// Panics if the last value isn't followed by punctuation.
+    pub fn push_value(&mut self, value: T) {
+        assert!(
+            self.empty_or_trailing(),
+            "Punctuated::push_value: the last value has no punctuation after it"
+        );
+        self.last = Some(Box::new(value));
+    }
+
+    /// # Panics
+    /// Panics if there is no value before the punctuation.
+    pub fn push_punct(&mut self, punct: P) {
+        let last = self
+            .last
+            .take()
+            .expect("Punctuated::push_punct: there is no value before the punctuation");
+        self.pairs.push((*last, punct));
+    }
+
+    /// Pushes `value`, with default punctuation before it if it is needed.
+    pub fn push(&mut self, value: T)
+    where
+        P: Default,
+    {
+        if !self.empty_or_trailing() {
+            self.push_punct(P::default());
+        }
+        self.push_value(value);
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        match &self.last {
+            Some(last) => Some(last),
+            None => self.pairs.last().map(|(value, _)| value),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, P> {
+        self.pairs
+            .iter()
+            .map(first as fn(&(T, P)) -> &T)
+            .chain(self.last.as_deref())
+    }
+
+    pub fn puncts(&self) -> impl Iterator<Item = &P> {
+        self.pairs.iter().map(|(_, punct)| punct)
+    }
+
+    /// Parses `T`s separated by `P`s until the stream is empty or there are only whitespaces left,
+    /// allowing a trailing `P`. Usually used on the content of a group.
+    pub fn parse_terminated(stream: &mut ParseStream) -> Result<Punctuated<T, P>>
+    where
+        T: Parse,
+        P: Parse,
+    {
+        let mut punctuated = Punctuated::new();
+        while !stream.is_only_whitespaces() {
+            punctuated.push_value(stream.parse()?);
+            if stream.is_only_whitespaces() {
+                break;
+            }
+            punctuated.push_punct(stream.parse()?);
+        }
+        Ok(punctuated)
+    }
+
+    /// Parses one or more `T`s separated by `P`s without a trailing `P`, for as long as the next
+    /// item is a `P`.
+    pub fn parse_separated_nonempty(stream: &mut ParseStream) -> Result<Punctuated<T, P>>
+    where
+        T: Parse,
+        P: Parse + Peek,
+    {
+        let mut punctuated = Punctuated::new();
+        punctuated.push_value(stream.parse()?);
+        while stream.peek::<P>() {
+            punctuated.push_punct(stream.parse()?);
+            punctuated.push_value(stream.parse()?);
+        }
+        Ok(punctuated)
+    }
+}
+
+impl<T, P> Default for Punctuated<T, P> {
+    fn default() -> Punctuated<T, P> {
+        Punctuated::new()
+    }
+}
+
+fn first<T, P>((value, _): &(T, P)) -> &T {
+    value
+}
+
+fn into_first<T, P>((value, _): (T, P)) -> T {
+    value
+}
+
+pub type Iter<'a, T, P> =
+    iter::Chain<iter::Map<slice::Iter<'a, (T, P)>, fn(&(T, P)) -> &T>, option::IntoIter<&'a T>>;
+
+pub type IntoIter<T, P> =
+    iter::Chain<iter::Map<vec::IntoIter<(T, P)>, fn((T, P)) -> T>, option::IntoIter<T>>;
+
+impl<'a, T, P> IntoIterator for &'a Punctuated<T, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, P>;
+
+    fn into_iter(self) -> Iter<'a, T, P> {
+        self.iter()
+    }
+}
+
+impl<T, P> IntoIterator for Punctuated<T, P> {
+    type Item = T;
+    type IntoIter = IntoIter<T, P>;
+
+    fn into_iter(self) -> IntoIter<T, P> {
+        self.pairs
+            .into_iter()
+            .map(into_first as fn((T, P)) -> T)
+            .chain(self.last.map(|last| *last))
+    }
+}
+
+impl<T, P: Default> FromIterator<T> for Punctuated<T, P> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Punctuated<T, P> {
+        let mut punctuated = Punctuated::new();
+        for value in iter {
+            punctuated.push(value);
+        }
+        punctuated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_tok;
+
+    simple_tok!(A, "a");
+    simple_tok!(Comma, ",");
+
+    fn terminated(input: &str) -> Result<Punctuated<A, Comma>> {
+        Punctuated::parse_terminated(&mut ParseStream::new(input))
+    }
+
+    #[test]
+    fn parse_terminated_allows_a_trailing_separator() {
+        let punctuated = terminated("a,a").unwrap();
+        assert_eq!(punctuated.len(), 2);
+        assert!(!punctuated.trailing_punct());
+
+        let punctuated = terminated("a,a, ").unwrap();
+        assert_eq!(punctuated.len(), 2);
+        assert!(punctuated.trailing_punct());
+        assert_eq!(punctuated.puncts().count(), 2);
+
+        assert!(terminated("  ").unwrap().is_empty());
+        assert_eq!(
+            terminated("a,,").unwrap_err().summary(),
+            "Expected `a`, found `,`"
+        );
+    }
+
+    #[test]
+    fn parse_separated_nonempty_rejects_a_trailing_separator() {
+        let mut stream = ParseStream::new("a,a;");
+        let punctuated = Punctuated::<A, Comma>::parse_separated_nonempty(&mut stream).unwrap();
+        assert_eq!(punctuated.len(), 2);
+        assert!(!punctuated.trailing_punct());
+        assert_eq!(stream.cur().rest(), ";");
+
+        let mut stream = ParseStream::new("a,a,");
+        let err = Punctuated::<A, Comma>::parse_separated_nonempty(&mut stream).unwrap_err();
+        assert_eq!(err.index(), 4);
+        assert_eq!(err.summary(), "Expected `a`, found end of input");
+
+        let mut stream = ParseStream::new("");
+        assert!(Punctuated::<A, Comma>::parse_separated_nonempty(&mut stream).is_err());
+    }
+
+    #[test]
+    fn push_inserts_separators() {
+        let mut punctuated: Punctuated<u32, Comma> = [1, 2].into_iter().collect();
+        assert_eq!(punctuated.iter().copied().collect::<Vec<_>>(), [1, 2]);
+        assert!(!punctuated.empty_or_trailing());
+        punctuated.push_punct(Comma);
+        assert!(punctuated.trailing_punct());
+        punctuated.push(3);
+        assert_eq!(punctuated.last(), Some(&3));
+        assert_eq!(punctuated.into_iter().collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the last value has no punctuation after it")]
+    fn push_value_needs_a_separator() {
+        let mut punctuated = Punctuated::<u32, Comma>::new();
+        punctuated.push_value(1);
+        punctuated.push_value(2);
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Snapshot(pub usize);
 
 impl From<usize> for Snapshot {
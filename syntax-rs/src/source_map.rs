@@ -0,0 +1,283 @@
+//! Source files and the conversion of byte offsets into lines and columns.
+
+use std::cell::OnceCell;
+
+/// Identifies a file in a [`SourceMap`].
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+impl FileId {
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A position in a source file. Lines and columns start at 1.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Location {
+    pub line: usize,
+    /// The column in characters, as shown to users.
+    pub column: usize,
+    /// The column in UTF-8 bytes.
+    pub utf8_column: usize,
+    /// The column in UTF-16 code units, as used by the language server protocol.
+    pub utf16_column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// The id of the file in its `SourceMap`, if it was added to one.
+    id: Option<FileId>,
+    name: String,
+    source: String,
+    /// The byte offset every line starts at, built the first time it is needed.
+    line_starts: OnceCell<Vec<usize>>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> SourceFile {
+        SourceFile {
+            id: None,
+            name: name.into(),
+            source: source.into(),
+            line_starts: OnceCell::new(),
+        }
+    }
+
+    #[inline]
+    pub fn id(&self) -> Option<FileId> {
+        self.id
+    }
+
+    /// Whether `span` may point into this file. Spans and files without an id always may.
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn is_file_of(&self, span: crate::Span) -> bool {
+        match (span.file, self.id) {
+            (Some(file), Some(id)) => file == id,
+            _ => true,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn line_starts(&self) -> &[usize] {
+        self.line_starts.get_or_init(|| {
+            std::iter::once(0)
+                .chain(self.source.match_indices('\n').map(|(i, _)| i + 1))
+                .collect()
+        })
+    }
+
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.line_starts().len()
+    }
+
+    /// The 1-based line `offset` is on. Offsets past the end are on the last line.
+    pub fn line(&self, offset: usize) -> usize {
+        let offset = offset.min(self.source.len());
+        self.line_starts().partition_point(|start| *start <= offset)
+    }
+
+    /// The byte offset the 1-based `line` starts at.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts()[line - 1]
+    }
+
+    /// The text of the 1-based `line`, without the line break.
+    pub fn line_text(&self, line: usize) -> &str {
+        let starts = self.line_starts();
+        let end = starts.get(line).map_or(self.source.len(), |end| end - 1);
+        self.source[starts[line - 1]..end].trim_end_matches('\r')
+    }
+
+    /// The location of the byte `offset`, which has to be at a character boundary.
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = offset.min(self.source.len());
+        let line = self.line(offset);
+        let before = &self.source[self.line_start(line)..offset];
+        Location {
+            line,
+            column: before.chars().count() + 1,
+            utf8_column: before.len() + 1,
+            utf16_column: before.encode_utf16().count() + 1,
+        }
+    }
+
+    /// The byte offset of a 1-based line and UTF-16 column, such as a position sent by a
+    /// language server client. Returns `None` if the position is outside the file or between
+    /// the two halves of a surrogate pair.
+    pub fn offset_of_utf16(&self, line: usize, utf16_column: usize) -> Option<usize> {
+        if line == 0 || line > self.line_count() || utf16_column == 0 {
+            return None;
+        }
+        let start = self.line_start(line);
+        let mut units = 0;
+        for (i, c) in self.line_text(line).char_indices() {
+            if units >= utf16_column - 1 {
+                return (units == utf16_column - 1).then_some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        (units == utf16_column - 1).then(|| start + self.line_text(line).len())
+    }
+}
+
+/// The source files of a program. Every file gets a [`FileId`] that spans into it can carry.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add_file(&mut self, name: impl Into<String>, source: impl Into<String>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        self.files.push(SourceFile {
+            id: Some(id),
+            ..SourceFile::new(name, source)
+        });
+        id
+    }
+
+    /// # Panics
+    /// Panics if `id` belongs to another `SourceMap`.
+    #[inline]
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.index()]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (FileId(i as u32), file))
+    }
+
+    /// The file a span points into, if it carries a file id.
+    #[cfg(feature = "span")]
+    pub fn file_of(&self, span: crate::Span) -> Option<&SourceFile> {
+        span.file.map(|id| self.file(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(line: usize, column: usize, utf8_column: usize, utf16_column: usize) -> Location {
+        Location {
+            line,
+            column,
+            utf8_column,
+            utf16_column,
+        }
+    }
+
+    #[test]
+    fn crlf_lines() {
+        let file = SourceFile::new("crlf", "ab\r\ncd\r\n");
+        assert_eq!(file.line_count(), 3);
+        assert_eq!(file.line_text(1), "ab");
+        assert_eq!(file.line_text(2), "cd");
+        assert_eq!(file.location(2), location(1, 3, 3, 3));
+        assert_eq!(file.location(4), location(2, 1, 1, 1));
+        assert_eq!(file.location(5), location(2, 2, 2, 2));
+        // The end of a line is before the `\r`.
+        assert_eq!(file.offset_of_utf16(1, 3), Some(2));
+        assert_eq!(file.offset_of_utf16(2, 3), Some(6));
+        assert_eq!(file.offset_of_utf16(2, 4), None);
+    }
+
+    #[test]
+    fn astral_plane_characters() {
+        // `😀` is 4 bytes in UTF-8 and a surrogate pair in UTF-16, `é` is 2 bytes and 1 unit.
+        let file = SourceFile::new("emoji", "é😀x\n😀");
+        assert_eq!(file.location(2), location(1, 2, 3, 2));
+        assert_eq!(file.location(6), location(1, 3, 7, 4));
+        assert_eq!(file.location(12), location(2, 2, 5, 3));
+
+        assert_eq!(file.offset_of_utf16(1, 1), Some(0));
+        assert_eq!(file.offset_of_utf16(1, 2), Some(2));
+        assert_eq!(file.offset_of_utf16(1, 4), Some(6));
+        assert_eq!(file.offset_of_utf16(1, 5), Some(7));
+        assert_eq!(file.offset_of_utf16(2, 3), Some(12));
+        // Between the two halves of a surrogate pair.
+        assert_eq!(file.offset_of_utf16(1, 3), None);
+        assert_eq!(file.offset_of_utf16(2, 2), None);
+    }
+
+    #[test]
+    fn end_of_file() {
+        let file = SourceFile::new("eof", "ab\ncd");
+        assert_eq!(file.location(5), location(2, 3, 3, 3));
+        // Offsets past the end are clamped to the end.
+        assert_eq!(file.location(100), location(2, 3, 3, 3));
+        assert_eq!(file.offset_of_utf16(2, 3), Some(5));
+
+        // After a trailing line break, the end is at the start of an empty last line.
+        let file = SourceFile::new("eof", "ab\n");
+        assert_eq!(file.line_count(), 2);
+        assert_eq!(file.line_text(2), "");
+        assert_eq!(file.location(3), location(2, 1, 1, 1));
+        assert_eq!(file.offset_of_utf16(2, 1), Some(3));
+
+        let file = SourceFile::new("empty", "");
+        assert_eq!(file.location(0), location(1, 1, 1, 1));
+        assert_eq!(file.offset_of_utf16(1, 1), Some(0));
+    }
+
+    #[test]
+    fn positions_outside_the_file() {
+        let file = SourceFile::new("outside", "ab\ncd\n");
+        // Past the end of the line.
+        assert_eq!(file.offset_of_utf16(1, 4), None);
+        assert_eq!(file.offset_of_utf16(3, 2), None);
+        // Past the last line.
+        assert_eq!(file.offset_of_utf16(4, 1), None);
+        // Lines and columns start at 1.
+        assert_eq!(file.offset_of_utf16(0, 1), None);
+        assert_eq!(file.offset_of_utf16(1, 0), None);
+    }
+
+    #[test]
+    fn source_map_ids() {
+        let mut map = SourceMap::new();
+        let a = map.add_file("a", "a");
+        let b = map.add_file("b", "b");
+        assert_ne!(a, b);
+        assert_eq!(map.file(b).name(), "b");
+        assert_eq!(map.files().map(|(id, _)| id).collect::<Vec<_>>(), [a, b]);
+        assert_eq!(map.file(a).id(), Some(a));
+    }
+
+    #[test]
+    fn spans_of_other_files() {
+        let mut map = SourceMap::new();
+        let a = map.add_file("a", "a");
+        let b = map.add_file("b", "b");
+        let span = crate::Span::new(0, 1);
+        assert!(map.file(a).is_file_of(span));
+        assert!(map.file(a).is_file_of(span.in_file(a)));
+        assert!(!map.file(a).is_file_of(span.in_file(b)));
+        assert!(SourceFile::new("c", "c").is_file_of(span.in_file(b)));
+        assert_eq!(
+            map.file_of(span.in_file(b)).map(SourceFile::name),
+            Some("b")
+        );
+        assert!(map.file_of(span).is_none());
+    }
+}
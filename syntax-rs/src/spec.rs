@@ -1,5 +1,7 @@
 // TODO: Warn when unicode combining characters are detected as they may be misinterpreted by the compiler.
 
+use crate::cursor::Cursor;
+use crate::lookahead::Peek;
 use crate::parse::{Parse, ParseStream};
 use crate::Result;
 use unicode_xid::UnicodeXID;
@@ -15,8 +17,9 @@ pub enum LineBreak {
 impl Parse for LineBreak {
     fn parse(stream: &mut ParseStream) -> Result<Self> {
         stream.try_parse(|stream| {
+            let error = stream.error("linebreak");
             let cur = stream.cur();
-            Ok(match cur.advance().ok_or("Expected linebreak.")? {
+            Ok(match cur.advance().ok_or(error.clone())? {
                 '\u{000D}' => {
                     if cur.consume('\u{000A}') {
                         LineBreak::CRLF
@@ -26,12 +29,22 @@ impl Parse for LineBreak {
                 }
                 '\u{000A}' => LineBreak::LF,
                 '\u{0085}' => LineBreak::NEL,
-                _ => return Err("Unrecognized linebreak. Expected CRLF, CR, LF or NEL."),
+                _ => return Err(error),
             })
         })
     }
 }
 
+impl Peek for LineBreak {
+    fn display() -> &'static str {
+        "linebreak"
+    }
+
+    fn peek(cursor: Cursor) -> bool {
+        matches!(cursor.peek0(), Some('\u{000D}' | '\u{000A}' | '\u{0085}'))
+    }
+}
+
 pub trait UnicodeSpec {
     fn is_xid_start(&self) -> bool;
     fn is_xid_continue(&self) -> bool;
@@ -0,0 +1,212 @@
+#![cfg(all(feature = "derive", feature = "span"))]
+
+use std::cell::Cell;
+use syntax_rs::parse::{Parse, ParseStream};
+use syntax_rs::{Span, Spanned};
+
+thread_local! {
+    /// How many times a `Digit` was parsed.
+    static DIGITS: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Spanned)]
+struct Digit {
+    value: u32,
+    span: Span,
+}
+
+impl Parse for Digit {
+    fn parse(stream: &mut ParseStream) -> syntax_rs::Result<Digit> {
+        DIGITS.with(|digits| digits.set(digits.get() + 1));
+        let begin = stream.snapshot();
+        match stream.cur().peek0().and_then(|c| c.to_digit(10)) {
+            Some(value) => {
+                stream.cur().advance();
+                Ok(Digit {
+                    value,
+                    span: stream.since(begin),
+                })
+            }
+            None => Err(stream.error("digit")),
+        }
+    }
+}
+
+fn digit(value: u32, begin: usize) -> Digit {
+    Digit {
+        value,
+        span: Span::new(begin, begin + 1),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Parse, Spanned)]
+struct Pair {
+    first: Digit,
+    #[parse(token = ",")]
+    second: Digit,
+    #[parse(span)]
+    whole: Span,
+}
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+#[parse(token = ")")]
+struct Close;
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+#[parse(token = "(")]
+struct Parenthesized(Digit, Close);
+
+#[derive(Debug, PartialEq, Eq, Parse, Spanned)]
+enum Value {
+    #[parse(token = "-")]
+    Neg(Digit),
+    Pair(Pair),
+    Digit(Digit),
+}
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+enum Statement {
+    #[parse(token = "let", peek)]
+    Let {
+        #[parse(token = " ")]
+        value: Digit,
+    },
+    #[parse(peek = "?")]
+    Query(Query),
+    Expr(Digit),
+}
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+#[parse(token = "?")]
+struct Query;
+
+#[derive(Debug, Clone, PartialEq, Eq, Parse, Spanned)]
+#[parse(memo)]
+struct Memoized(Digit);
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+#[parse(token = "!")]
+struct Bang;
+
+#[derive(Debug, PartialEq, Eq, Parse)]
+enum Suffixed {
+    Bang(Memoized, Bang),
+    Query(Memoized, Query),
+}
+
+fn parse<T: Parse>(input: &str) -> syntax_rs::Result<T> {
+    let mut stream = ParseStream::new(input);
+    let result = stream.parse::<T>()?;
+    assert!(stream.is_empty(), "`{}` was left over", stream.cur().rest());
+    Ok(result)
+}
+
+#[test]
+fn structs_parse_their_fields_in_order() {
+    assert_eq!(
+        parse::<Pair>("1,2").unwrap(),
+        Pair {
+            first: digit(1, 0),
+            second: digit(2, 2),
+            whole: Span::new(0, 3),
+        }
+    );
+    let err = parse::<Pair>("12").unwrap_err();
+    assert_eq!(err.index(), 1);
+    assert_eq!(err.summary(), "Expected `,`, found `2`");
+    let err = parse::<Pair>("1,").unwrap_err();
+    assert_eq!(err.summary(), "Expected digit, found end of input");
+
+    assert_eq!(
+        parse::<Parenthesized>("(4)").unwrap(),
+        Parenthesized(digit(4, 1), Close)
+    );
+    assert_eq!(
+        parse::<Parenthesized>("4)").unwrap_err().summary(),
+        "Expected `(`, found `4`"
+    );
+}
+
+#[test]
+fn enums_backtrack_out_of_variants() {
+    assert_eq!(parse::<Value>("-3").unwrap(), Value::Neg(digit(3, 1)));
+    // `Pair` fails after the first digit and is backtracked out of.
+    assert_eq!(parse::<Value>("3").unwrap(), Value::Digit(digit(3, 0)));
+    assert!(matches!(parse::<Value>("3,4").unwrap(), Value::Pair(_)));
+}
+
+#[test]
+fn enums_expect_every_variant() {
+    let err = parse::<Value>("x").unwrap_err();
+    assert_eq!(err.index(), 0);
+    assert_eq!(err.expected_items(), ["`-`", "digit"]);
+    assert_eq!(err.summary(), "Expected `-` or digit, found `x`");
+    assert_eq!(err.found(), Some("x"));
+}
+
+#[test]
+fn peeked_variants_are_not_backtracked_out_of() {
+    assert_eq!(
+        parse::<Statement>("let 5").unwrap(),
+        Statement::Let { value: digit(5, 4) }
+    );
+    assert_eq!(parse::<Statement>("?").unwrap(), Statement::Query(Query));
+    assert_eq!(
+        parse::<Statement>("5").unwrap(),
+        Statement::Expr(digit(5, 0))
+    );
+
+    // Once `let` is peeked, its error isn't replaced by the one of `Expr`.
+    let err = parse::<Statement>("let x").unwrap_err();
+    assert_eq!(err.index(), 4);
+    assert_eq!(err.expected_items(), ["digit"]);
+
+    // The peeked tokens are expected together with the variants that were tried.
+    let mut expected = parse::<Statement>("x")
+        .unwrap_err()
+        .expected_items()
+        .to_vec();
+    expected.sort_unstable();
+    assert_eq!(expected, ["`?`", "`let`", "digit"]);
+}
+
+#[test]
+fn memoized_items_are_parsed_once_per_index() {
+    let digits = |memo: bool| {
+        DIGITS.with(|digits| digits.set(0));
+        let mut stream = ParseStream::new("7?");
+        if memo {
+            stream.enable_memo();
+        }
+        let suffixed = stream.parse::<Suffixed>().unwrap();
+        assert_eq!(suffixed, Suffixed::Query(Memoized(digit(7, 0)), Query));
+        DIGITS.with(Cell::get)
+    };
+    assert_eq!(digits(false), 2);
+    assert_eq!(digits(true), 1);
+}
+
+#[test]
+fn spanned_uses_span_fields_and_single_fields() {
+    let mut pair = parse::<Pair>("1,2").unwrap();
+    assert_eq!(pair.span(), Span::new(0, 3));
+    *pair.span_ref_mut() = Span::new(1, 2);
+    assert_eq!(pair.whole, Span::new(1, 2));
+
+    // A single field is spanned itself.
+    let memoized = parse::<Memoized>("4").unwrap();
+    assert_eq!(memoized.span(), Span::new(0, 1));
+
+    // Every variant has a single field.
+    assert_eq!(parse::<Value>("-3").unwrap().span(), Span::new(1, 2));
+    assert_eq!(parse::<Value>("1,2").unwrap().span(), Span::new(0, 3));
+    let mut value = parse::<Value>("8").unwrap();
+    value.span_ref_mut().end = 5;
+    assert_eq!(
+        value,
+        Value::Digit(Digit {
+            value: 8,
+            span: Span::new(0, 5),
+        })
+    );
+}
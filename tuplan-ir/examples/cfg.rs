@@ -0,0 +1,46 @@
+use std::fs::File;
+
+use tuplan_ir::{cfg::Cfg, encode, Op};
+
+fn main() {
+    /*
+
+    i = 0;
+    while i < 10 {
+        peek i;
+        i += 1;
+    }
+
+     */
+
+    let bytecode = encode(&[
+        Op::PushU64(0),
+        Op::LocalCopy(0),
+        Op::PushU64(10),
+        Op::LtU64,
+        Op::GotoIfNot(55),
+        Op::LocalCopy(0),
+        Op::PeekU64,
+        Op::PushU64(1),
+        Op::AddU64,
+        Op::LocalSet(0),
+        Op::Goto(9),
+    ]);
+
+    let cfg = Cfg::build(&bytecode).unwrap();
+    let doms = cfg.dominators();
+    for (id, block) in cfg.blocks().iter().enumerate() {
+        let id = tuplan_ir::cfg::BlockId(id);
+        println!(
+            "{id}: {}..{} succs={:?} preds={:?} idom={:?}",
+            block.start,
+            block.end,
+            block.succs,
+            block.preds,
+            doms.idom(id)
+        );
+    }
+
+    cfg.write_dot(File::create("cfg.dot").unwrap()).unwrap();
+    println!("Wrote cfg.dot");
+}
@@ -0,0 +1,44 @@
+use tuplan_ir::{
+    disassemble, encode,
+    opt::{optimize, OptLevel},
+    Op,
+};
+
+fn main() {
+    /*
+
+    if 1 + 2 < 5 {
+        peek 3 - 1;
+    }
+
+     */
+
+    let bytecode = encode(&[
+        Op::PushU64(1),
+        Op::PushU64(2),
+        Op::AddU64,
+        Op::PushU64(5),
+        Op::LtU64,
+        Op::GotoIfNot(60),
+        Op::PushU64(3),
+        Op::PushU64(1),
+        Op::SubU64,
+        Op::PeekU64,
+        Op::Pop,
+        Op::Goto(70),
+        Op::PushU64(7),
+        Op::Pop,
+    ]);
+    println!(
+        "Before ({} bytes):\n{}",
+        bytecode.len(),
+        disassemble(&bytecode)
+    );
+
+    let optimized = optimize(&bytecode, OptLevel::O2).unwrap();
+    println!(
+        "After ({} bytes):\n{}",
+        optimized.len(),
+        disassemble(&optimized)
+    );
+}
@@ -0,0 +1,588 @@
+//! Control-flow graph construction for Tuplan bytecode.
+//!
+//! The stream is split into basic blocks at every `goto`, `gotoif`, `gotoifnot` and `ret`
+//! and at every jump target. A jump to the end of the stream (`loc == len`) ends the program,
+//! just like falling off the end does, so it doesn't produce an edge.
+//!
+//! `ret` jumps to an address that is only known at runtime, so blocks ending in a `ret` have no
+//! successors and blocks that are only entered through a `ret` are considered unreachable.
+
+use crate::{decode, ByteStream, DecodeError, Op};
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub usize);
+
+impl BlockId {
+    #[inline]
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Offset of the first instruction.
+    pub start: usize,
+    /// Offset one past the last byte of the block.
+    pub end: usize,
+    /// The instructions of the block together with their offsets.
+    pub insts: Vec<(usize, Op)>,
+    pub succs: Vec<BlockId>,
+    pub preds: Vec<BlockId>,
+}
+
+impl BasicBlock {
+    #[inline]
+    #[must_use]
+    pub fn last(&self) -> Option<Op> {
+        self.insts.last().map(|(_, op)| *op)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfgError {
+    Decode(DecodeError),
+    /// The jump at `offset` goes somewhere that isn't the start of an instruction.
+    InvalidJumpTarget {
+        offset: usize,
+        target: u32,
+    },
+}
+
+impl fmt::Display for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfgError::Decode(err) => err.fmt(f),
+            CfgError::InvalidJumpTarget { offset, target } => write!(
+                f,
+                "Invalid bytecode: Jump at {offset} targets {target}, which is not the start of an instruction."
+            ),
+        }
+    }
+}
+
+impl Error for CfgError {}
+
+impl From<DecodeError> for CfgError {
+    fn from(err: DecodeError) -> Self {
+        CfgError::Decode(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    len: usize,
+}
+
+impl Cfg {
+    /// Splits `bytes` into basic blocks. The entry block is always `BlockId(0)`, even for an empty stream.
+    pub fn build(bytes: &ByteStream) -> Result<Cfg, CfgError> {
+        let ops = decode(bytes)?;
+        let len = bytes.len();
+
+        // `is_start[i]` is true if an instruction starts at byte `i`.
+        let mut is_start = vec![false; len + 1];
+        for (offset, _) in &ops {
+            is_start[*offset] = true;
+        }
+        is_start[len] = true;
+
+        let mut is_leader = vec![false; len + 1];
+        is_leader[0] = true;
+        for (i, (offset, op)) in ops.iter().enumerate() {
+            if let Some(target) = op.jump_target() {
+                let target_idx = target as usize;
+                if target_idx > len || !is_start[target_idx] {
+                    return Err(CfgError::InvalidJumpTarget {
+                        offset: *offset,
+                        target,
+                    });
+                }
+                is_leader[target_idx] = true;
+            }
+            if op.ends_block() {
+                if let Some((next, _)) = ops.get(i + 1) {
+                    is_leader[*next] = true;
+                }
+            }
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut block_of = vec![None; len + 1];
+        for (offset, op) in ops {
+            if is_leader[offset] || blocks.is_empty() {
+                block_of[offset] = Some(BlockId(blocks.len()));
+                blocks.push(BasicBlock {
+                    start: offset,
+                    end: offset,
+                    insts: Vec::new(),
+                    succs: Vec::new(),
+                    preds: Vec::new(),
+                });
+            }
+            let block = blocks.last_mut().unwrap();
+            block.end = offset + op.encoded_len();
+            block.insts.push((offset, op));
+        }
+        if blocks.is_empty() {
+            blocks.push(BasicBlock {
+                start: 0,
+                end: 0,
+                insts: Vec::new(),
+                succs: Vec::new(),
+                preds: Vec::new(),
+            });
+        }
+
+        for id in 0..blocks.len() {
+            let block = &blocks[id];
+            let fallthrough = block_of.get(block.end).copied().flatten();
+            let mut succs = Vec::with_capacity(2);
+            match block.last() {
+                Some(Op::Ret) => (),
+                Some(Op::Goto(target)) => succs.extend(block_of[target as usize]),
+                Some(Op::GotoIf(target) | Op::GotoIfNot(target)) => {
+                    succs.extend(block_of[target as usize]);
+                    succs.extend(fallthrough);
+                }
+                _ => succs.extend(fallthrough),
+            }
+            succs.dedup();
+
+            for succ in &succs {
+                blocks[succ.0].preds.push(BlockId(id));
+            }
+            blocks[id].succs = succs;
+        }
+
+        Ok(Cfg { blocks, len })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn entry(&self) -> BlockId {
+        BlockId(0)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id.0]
+    }
+
+    /// Length in bytes of the stream this graph was built from.
+    #[inline]
+    #[must_use]
+    pub fn code_len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the block starting at `offset`, if there is one.
+    #[must_use]
+    pub fn block_at(&self, offset: usize) -> Option<BlockId> {
+        self.blocks
+            .binary_search_by_key(&offset, |block| block.start)
+            .ok()
+            .map(BlockId)
+    }
+
+    /// Returns the block containing the instruction at `offset`.
+    #[must_use]
+    pub fn block_containing(&self, offset: usize) -> Option<BlockId> {
+        let idx = self.blocks.partition_point(|block| block.start <= offset);
+        let block = self.blocks.get(idx.checked_sub(1)?)?;
+        (offset < block.end).then(|| BlockId(idx - 1))
+    }
+
+    /// The blocks reachable from the entry in reverse postorder.
+    #[must_use]
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        // (block, index of the next successor to visit)
+        let mut stack = vec![(self.entry(), 0)];
+        visited[self.entry().0] = true;
+        while let Some((block, next)) = stack.last_mut() {
+            match self.blocks[block.0].succs.get(*next) {
+                Some(succ) => {
+                    *next += 1;
+                    if !visited[succ.0] {
+                        visited[succ.0] = true;
+                        stack.push((*succ, 0));
+                    }
+                }
+                None => {
+                    postorder.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Returns a flag for every block telling whether it can be reached from the entry.
+    #[must_use]
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        for id in self.reverse_postorder() {
+            reachable[id.0] = true;
+        }
+        reachable
+    }
+
+    /// Computes the dominator tree using the algorithm by Cooper, Harvey and Kennedy.
+    #[must_use]
+    pub fn dominators(&self) -> DomTree {
+        let rpo = self.reverse_postorder();
+        let mut rpo_index = vec![usize::MAX; self.blocks.len()];
+        for (i, id) in rpo.iter().enumerate() {
+            rpo_index[id.0] = i;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[self.entry().0] = Some(self.entry());
+
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while rpo_index[a.0] > rpo_index[b.0] {
+                    a = idom[a.0].unwrap();
+                }
+                while rpo_index[b.0] > rpo_index[a.0] {
+                    b = idom[b.0].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &self.blocks[id.0].preds {
+                    if idom[pred.0].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => *pred,
+                        Some(cur) => intersect(&idom, *pred, cur),
+                    });
+                }
+                if new_idom.is_some() && idom[id.0] != new_idom {
+                    idom[id.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[self.entry().0] = None;
+
+        let mut children = vec![Vec::new(); self.blocks.len()];
+        for id in &rpo {
+            if let Some(parent) = idom[id.0] {
+                children[parent.0].push(*id);
+            }
+        }
+
+        DomTree {
+            idom,
+            children,
+            root: self.entry(),
+        }
+    }
+
+    /// Renders the graph in the Graphviz DOT format with the disassembled body of every block.
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut buf = String::new();
+        buf.push_str("digraph cfg {\n");
+        buf.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("bb{id}:\\l");
+            for (offset, op) in &block.insts {
+                let _ = write!(label, "{offset} | {op}\\l");
+            }
+            let _ = writeln!(buf, "    bb{id} [label=\"{label}\"];");
+        }
+        let mut has_exit = false;
+        for (id, block) in self.blocks.iter().enumerate() {
+            let target = block.last().and_then(|op| op.jump_target());
+            let target_block = target.and_then(|target| self.block_at(target as usize));
+            let edge_label = |to_target: bool| match block.last() {
+                Some(Op::GotoIf(_)) if to_target => " [label=\"true\"]",
+                Some(Op::GotoIf(_)) => " [label=\"false\"]",
+                Some(Op::GotoIfNot(_)) if to_target => " [label=\"false\"]",
+                Some(Op::GotoIfNot(_)) => " [label=\"true\"]",
+                _ => "",
+            };
+            for succ in &block.succs {
+                let label = edge_label(Some(*succ) == target_block);
+                let _ = writeln!(buf, "    bb{id} -> {succ}{label};");
+            }
+
+            // Edges leaving the program: `ret`, jumps to the end and falling off the end.
+            let jumps_to_end = target == Some(self.len as u32);
+            let falls_off_end =
+                block.end == self.len && !matches!(block.last(), Some(Op::Goto(_) | Op::Ret));
+            if matches!(block.last(), Some(Op::Ret)) || jumps_to_end || falls_off_end {
+                has_exit = true;
+                let label = edge_label(jumps_to_end);
+                let _ = writeln!(buf, "    bb{id} -> exit{label};");
+            }
+        }
+        if has_exit {
+            buf.push_str("    exit [shape=oval];\n");
+        }
+        buf.push_str("}\n");
+        buf
+    }
+
+    pub fn write_dot<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_dot().as_bytes())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DomTree {
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+    root: BlockId,
+}
+
+impl DomTree {
+    #[inline]
+    #[must_use]
+    pub fn root(&self) -> BlockId {
+        self.root
+    }
+
+    /// The immediate dominator of `block`. This is `None` for the entry and for unreachable blocks.
+    #[inline]
+    #[must_use]
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.0]
+    }
+
+    /// Returns true if every path from the entry to `b` goes through `a`.
+    #[must_use]
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        let mut cur = Some(b);
+        while let Some(block) = cur {
+            if block == a {
+                return true;
+            }
+            cur = self.idom[block.0];
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+
+    fn ids(ids: &[usize]) -> Vec<BlockId> {
+        ids.iter().copied().map(BlockId).collect()
+    }
+
+    /// i = 0; while i < 3 { i += 1 } peek i;
+    fn count() -> ByteStream {
+        encode(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::GotoIfNot(54),
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(9),
+            Op::PeekU64,
+        ])
+    }
+
+    /// peek if 0 < 1 { 3 } else { 2 };
+    fn diamond() -> ByteStream {
+        encode(&[
+            Op::PushU64(0),
+            Op::PushU64(1),
+            Op::LtU64,
+            Op::GotoIf(38),
+            Op::PushU64(2),
+            Op::Goto(47),
+            Op::PushU64(3),
+            Op::PeekU64,
+        ])
+    }
+
+    #[test]
+    fn splits_blocks_at_jumps_and_targets() {
+        let cfg = Cfg::build(&count()).unwrap();
+        let bounds: Vec<(usize, usize)> = cfg
+            .blocks()
+            .iter()
+            .map(|block| (block.start, block.end))
+            .collect();
+        assert_eq!(bounds, [(0, 9), (9, 29), (29, 54), (54, 55)]);
+        assert_eq!(cfg.block(BlockId(1)).last(), Some(Op::GotoIfNot(54)));
+        assert_eq!(cfg.code_len(), 55);
+
+        assert_eq!(cfg.block_at(29), Some(BlockId(2)));
+        assert_eq!(cfg.block_at(34), None);
+        assert_eq!(cfg.block_containing(34), Some(BlockId(2)));
+        assert_eq!(cfg.block_containing(55), None);
+    }
+
+    #[test]
+    fn connects_predecessors_and_successors() {
+        let cfg = Cfg::build(&count()).unwrap();
+        let succs: Vec<_> = cfg
+            .blocks()
+            .iter()
+            .map(|block| block.succs.clone())
+            .collect();
+        let preds: Vec<_> = cfg
+            .blocks()
+            .iter()
+            .map(|block| block.preds.clone())
+            .collect();
+        // The target of a conditional jump comes before the fallthrough.
+        assert_eq!(succs, [ids(&[1]), ids(&[3, 2]), ids(&[1]), ids(&[])]);
+        assert_eq!(preds, [ids(&[]), ids(&[0, 2]), ids(&[1]), ids(&[1])]);
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn jumps_to_the_end_have_no_edge() {
+        let bytes = encode(&[
+            Op::PushU64(0),
+            Op::PushU64(1),
+            Op::LtU64,
+            Op::GotoIf(34),
+            Op::PushU64(2),
+            Op::PeekU64,
+        ]);
+        let cfg = Cfg::build(&bytes).unwrap();
+        assert_eq!(cfg.blocks().len(), 2);
+        assert_eq!(cfg.block(BlockId(0)).succs, ids(&[1]));
+        assert!(cfg.block(BlockId(1)).succs.is_empty());
+
+        let cfg = Cfg::build(&encode(&[Op::Goto(5)])).unwrap();
+        assert_eq!(cfg.blocks().len(), 1);
+        assert!(cfg.block(cfg.entry()).succs.is_empty());
+    }
+
+    #[test]
+    fn blocks_after_ret_are_unreachable() {
+        let cfg = Cfg::build(&encode(&[Op::PushU64(0), Op::Ret, Op::PushU64(1)])).unwrap();
+        assert!(cfg.block(BlockId(0)).succs.is_empty());
+        assert_eq!(cfg.reachable(), [true, false]);
+        let dom = cfg.dominators();
+        assert_eq!(dom.idom(BlockId(1)), None);
+        assert!(!dom.dominates(BlockId(0), BlockId(1)));
+    }
+
+    #[test]
+    fn builds_an_entry_block_for_empty_streams() {
+        let cfg = Cfg::build(&encode(&[])).unwrap();
+        assert_eq!(cfg.blocks().len(), 1);
+        assert!(cfg.block(cfg.entry()).insts.is_empty());
+    }
+
+    #[test]
+    fn rejects_jumps_into_instructions() {
+        let bytes = encode(&[Op::PushU64(1), Op::Goto(4)]);
+        assert_eq!(
+            Cfg::build(&bytes).err(),
+            Some(CfgError::InvalidJumpTarget {
+                offset: 9,
+                target: 4
+            })
+        );
+    }
+
+    #[test]
+    fn dominators_of_loops() {
+        let dom = Cfg::build(&count()).unwrap().dominators();
+        assert_eq!(dom.root(), BlockId(0));
+        let idoms: Vec<_> = (0..4).map(|id| dom.idom(BlockId(id))).collect();
+        assert_eq!(
+            idoms,
+            [None, Some(BlockId(0)), Some(BlockId(1)), Some(BlockId(1))]
+        );
+        assert_eq!(dom.children(BlockId(1)), ids(&[2, 3]));
+        // The body doesn't dominate the exit, it can be skipped.
+        assert!(dom.dominates(BlockId(1), BlockId(3)));
+        assert!(!dom.dominates(BlockId(2), BlockId(3)));
+        assert!(!dom.dominates(BlockId(2), BlockId(1)));
+    }
+
+    #[test]
+    fn dominators_of_merges() {
+        let dom = Cfg::build(&diamond()).unwrap().dominators();
+        // Neither arm dominates the merge, so its immediate dominator is the branch.
+        assert_eq!(dom.idom(BlockId(3)), Some(BlockId(0)));
+        assert_eq!(dom.children(BlockId(0)), ids(&[1, 2, 3]));
+        assert!(!dom.dominates(BlockId(1), BlockId(3)));
+        assert!(!dom.dominates(BlockId(2), BlockId(3)));
+        assert!(dom.dominates(BlockId(3), BlockId(3)));
+    }
+
+    #[test]
+    fn dot_export() {
+        let bytes = encode(&[
+            Op::PushU64(0),
+            Op::PushU64(1),
+            Op::LtU64,
+            Op::GotoIf(34),
+            Op::PushU64(2),
+            Op::PeekU64,
+        ]);
+        let cfg = Cfg::build(&bytes).unwrap();
+        assert_eq!(
+            cfg.to_dot(),
+            r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    bb0 [label="bb0:\l0 | pushu64 0\l9 | pushu64 1\l18 | ltu64\l19 | gotoif 34\l"];
+    bb1 [label="bb1:\l24 | pushu64 2\l33 | peeku64\l"];
+    bb0 -> bb1 [label="false"];
+    bb0 -> exit [label="true"];
+    bb1 -> exit;
+    exit [shape=oval];
+}
+"#
+        );
+
+        let dot = Cfg::build(&count()).unwrap().to_dot();
+        assert!(
+            dot.contains("    bb1 -> bb3 [label=\"false\"];\n    bb1 -> bb2 [label=\"true\"];\n")
+        );
+        assert!(dot.contains("    bb2 -> bb1;\n"));
+        assert!(dot.contains("    bb3 -> exit;\n"));
+    }
+}
@@ -6,13 +6,25 @@
 //! `pop` - Pops any value off of the stack.
 //! `ret` - `(stack retptr: u32)` Returns to `retptr`.
 //! `goto` - `(inline loc: u32)` Moves the instruction pointer to `loc`.
+//! `gotoif` - `(stack cond: bool), (inline loc: u32)` Moves the instruction pointer to `loc` if `cond` is true.
+//! `gotoifnot` - `(stack cond: bool), (inline loc: u32)` Moves the instruction pointer to `loc` if `cond` is false.
 //! `addu64` - `(stack a: u64), (stack b: u64)` Pushes a new `u64` onto the stack which is the result of adding `a` and `b`.
+//! `subu64` - `(stack a: u64), (stack b: u64)` Pushes a new `u64` onto the stack which is the result of subtracting `b` from `a`.
+//! `ltu64` - `(stack a: u64), (stack b: u64)` Pushes `a < b` onto the stack.
+//! `gtu64` - `(stack a: u64), (stack b: u64)` Pushes `a > b` onto the stack.
 //! `peeku64` - `(stack val: u64)` Displays a u64 to stdout without popping it off the stack.
+//! `peekbool` - `(stack val: bool)` Displays a bool to stdout without popping it off the stack.
 
 use disc::{disc, FromDiscriminant};
+use std::error::Error;
+use std::fmt;
 use std::mem;
 use std::ops::Index;
 
+pub mod cfg;
+pub mod opt;
+pub mod verify;
+
 #[disc]
 pub enum Inst {
     LocalSet,
@@ -113,10 +125,28 @@ impl ByteStream {
         }
     }
 
+    #[inline]
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
     #[inline]
     pub fn jump_unchecked(&mut self, index: usize) {
         self.index = index;
     }
+
+    /// The index of the next byte that will be read.
+    #[inline]
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.index
+    }
 }
 
 impl Index<usize> for ByteStream {
@@ -153,58 +183,227 @@ pub fn disassemble(bytes: &ByteStream) -> String {
 }
 
 pub fn disassemble_one(bytes: &ByteStream, start: usize, buffer: &mut String) -> usize {
-    #[inline(always)]
-    fn simple(str: &str, index: usize, buf: &mut String) -> usize {
-        buf.push_str(&*format!("{index} | {str}"));
-        index + 1
+    let (op, index) =
+        decode_one(bytes, start).expect("Invalid bytecode: Expected instruction header.");
+    buffer.push_str(&format!("{start} | {op}"));
+    index
+}
+
+/// A decoded instruction together with its inline operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    LocalSet(u32),
+    LocalCopy(u32),
+    PushU64(u64),
+    Pop,
+    Ret,
+    Goto(u32),
+    GotoIf(u32),
+    GotoIfNot(u32),
+    AddU64,
+    SubU64,
+    LtU64,
+    GtU64,
+    PeekU64,
+    PeekBool,
+}
+
+impl Op {
+    #[must_use]
+    pub fn inst(&self) -> Inst {
+        match self {
+            Op::LocalSet(_) => Inst::LocalSet,
+            Op::LocalCopy(_) => Inst::LocalCopy,
+            Op::PushU64(_) => Inst::PushU64,
+            Op::Pop => Inst::Pop,
+            Op::Ret => Inst::Ret,
+            Op::Goto(_) => Inst::Goto,
+            Op::GotoIf(_) => Inst::GotoIf,
+            Op::GotoIfNot(_) => Inst::GotoIfNot,
+            Op::AddU64 => Inst::AddU64,
+            Op::SubU64 => Inst::SubU64,
+            Op::LtU64 => Inst::LtU64,
+            Op::GtU64 => Inst::GtU64,
+            Op::PeekU64 => Inst::PeekU64,
+            Op::PeekBool => Inst::PeekBool,
+        }
+    }
+
+    /// The location this instruction may jump to, if it is a `goto`, `gotoif` or `gotoifnot`.
+    #[inline]
+    #[must_use]
+    pub fn jump_target(&self) -> Option<u32> {
+        match self {
+            Op::Goto(loc) | Op::GotoIf(loc) | Op::GotoIfNot(loc) => Some(*loc),
+            _ => None,
+        }
+    }
+
+    /// Returns true if execution never continues with the next instruction.
+    #[inline]
+    #[must_use]
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Op::Goto(_) | Op::Ret)
+    }
+
+    /// Returns true if this instruction ends a basic block.
+    #[inline]
+    #[must_use]
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            Op::Goto(_) | Op::GotoIf(_) | Op::GotoIfNot(_) | Op::Ret
+        )
+    }
+
+    /// The number of bytes this instruction occupies, including the header.
+    #[must_use]
+    pub fn encoded_len(&self) -> usize {
+        1 + match self {
+            Op::LocalSet(_) | Op::LocalCopy(_) | Op::Goto(_) | Op::GotoIf(_) | Op::GotoIfNot(_) => {
+                mem::size_of::<u32>()
+            }
+            Op::PushU64(_) => mem::size_of::<u64>(),
+            _ => 0,
+        }
+    }
+
+    pub fn encode(&self, stream: &mut ByteStream) {
+        stream.push(self.inst() as u8);
+        match self {
+            Op::LocalSet(val)
+            | Op::LocalCopy(val)
+            | Op::Goto(val)
+            | Op::GotoIf(val)
+            | Op::GotoIfNot(val) => stream.extend(&val.to_le_bytes()),
+            Op::PushU64(val) => stream.extend(&val.to_le_bytes()),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::LocalSet(slot) => write!(f, "localset {slot}"),
+            Op::LocalCopy(slot) => write!(f, "localcopy {slot}"),
+            Op::PushU64(val) => write!(f, "pushu64 {val}"),
+            Op::Pop => f.write_str("pop"),
+            Op::Ret => f.write_str("ret"),
+            Op::Goto(loc) => write!(f, "goto {loc}"),
+            Op::GotoIf(loc) => write!(f, "gotoif {loc}"),
+            Op::GotoIfNot(loc) => write!(f, "gotoifnot {loc}"),
+            Op::AddU64 => f.write_str("addu64"),
+            Op::SubU64 => f.write_str("subu64"),
+            Op::LtU64 => f.write_str("ltu64"),
+            Op::GtU64 => f.write_str("gtu64"),
+            Op::PeekU64 => f.write_str("peeku64"),
+            Op::PeekBool => f.write_str("peekbool"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The byte at `offset` is not a valid instruction header.
+    InvalidHeader { offset: usize, byte: u8 },
+    /// The instruction at `offset` is missing (part of) its inline operand.
+    UnexpectedEnd { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidHeader { offset, byte } => write!(
+                f,
+                "Invalid bytecode: Expected instruction header at {offset}, found {byte}."
+            ),
+            DecodeError::UnexpectedEnd { offset } => write!(
+                f,
+                "Invalid bytecode: Instruction at {offset} is missing its operand."
+            ),
+        }
     }
+}
+
+impl Error for DecodeError {}
 
+/// Decodes the instruction starting at `start`, returning it together with the index of the next instruction.
+pub fn decode_one(bytes: &ByteStream, start: usize) -> Result<(Op, usize), DecodeError> {
     let header = bytes
         .get(start)
-        .expect("Invalid bytecode: Expected instruction header.");
-    let inst: Inst =
-        Inst::from_discriminant(header).expect("Invalid bytecode: Expected instruction header.");
+        .ok_or(DecodeError::UnexpectedEnd { offset: start })?;
+    let inst: Inst = Inst::from_discriminant(header).ok_or(DecodeError::InvalidHeader {
+        offset: start,
+        byte: header,
+    })?;
+
+    let u32_operand = || {
+        if start + 1 + mem::size_of::<u32>() > bytes.len() {
+            return Err(DecodeError::UnexpectedEnd { offset: start });
+        }
+        Ok(get_u32(bytes, start + 1))
+    };
 
-    match inst {
+    Ok(match inst {
         Inst::LocalSet => {
-            let (slot, index) = get_u32(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | localset {slot}"));
-            index
+            let (slot, index) = u32_operand()?;
+            (Op::LocalSet(slot), index)
         }
         Inst::LocalCopy => {
-            let (slot, index) = get_u32(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | localcopy {slot}"));
-            index
+            let (slot, index) = u32_operand()?;
+            (Op::LocalCopy(slot), index)
         }
         Inst::PushU64 => {
+            if start + 1 + mem::size_of::<u64>() > bytes.len() {
+                return Err(DecodeError::UnexpectedEnd { offset: start });
+            }
             let (val, index) = get_u64(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | pushu64 {val}"));
-            index
+            (Op::PushU64(val), index)
         }
-        Inst::Pop => simple("pop", start, buffer),
-        Inst::Ret => simple("ret", start, buffer),
+        Inst::Pop => (Op::Pop, start + 1),
+        Inst::Ret => (Op::Ret, start + 1),
         Inst::Goto => {
-            let (val, index) = get_u32(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | goto {val}"));
-            index
+            let (loc, index) = u32_operand()?;
+            (Op::Goto(loc), index)
         }
         Inst::GotoIf => {
-            let (val, index) = get_u32(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | gotoif {val}"));
-            index
+            let (loc, index) = u32_operand()?;
+            (Op::GotoIf(loc), index)
         }
         Inst::GotoIfNot => {
-            let (val, index) = get_u32(bytes, start + 1);
-            buffer.push_str(&*format!("{start} | gotoif {val}"));
-            index
+            let (loc, index) = u32_operand()?;
+            (Op::GotoIfNot(loc), index)
         }
-        Inst::AddU64 => simple("addu64", start, buffer),
-        Inst::SubU64 => simple("subu64", start, buffer),
-        Inst::LtU64 => simple("ltu64", start, buffer),
-        Inst::GtU64 => simple("gtu64", start, buffer),
-        Inst::PeekU64 => simple("peeku64", start, buffer),
-        Inst::PeekBool => simple("peekbool", start, buffer),
+        Inst::AddU64 => (Op::AddU64, start + 1),
+        Inst::SubU64 => (Op::SubU64, start + 1),
+        Inst::LtU64 => (Op::LtU64, start + 1),
+        Inst::GtU64 => (Op::GtU64, start + 1),
+        Inst::PeekU64 => (Op::PeekU64, start + 1),
+        Inst::PeekBool => (Op::PeekBool, start + 1),
+    })
+}
+
+/// Decodes the whole stream into a list of `(offset, instruction)` pairs.
+pub fn decode(bytes: &ByteStream) -> Result<Vec<(usize, Op)>, DecodeError> {
+    let mut ops = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let (op, next) = decode_one(bytes, index)?;
+        ops.push((index, op));
+        index = next;
+    }
+    Ok(ops)
+}
+
+/// Encodes a list of instructions into a new stream.
+#[must_use]
+pub fn encode<'a, I: IntoIterator<Item = &'a Op>>(ops: I) -> ByteStream {
+    let mut stream = ByteStream::new();
+    for op in ops {
+        op.encode(&mut stream);
     }
+    stream
 }
 
 fn get_u32(bytes: &ByteStream, start: usize) -> (u32, usize) {
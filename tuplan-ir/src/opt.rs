@@ -0,0 +1,539 @@
+//! Bytecode optimizer.
+//!
+//! The optimizer works on a decoded listing where jump operands refer to instruction indices
+//! instead of byte offsets, so instructions can be removed freely and the jumps are patched
+//! when the listing is encoded again. Both the input and the output are verified.
+//!
+//! `ret` jumps to an address that was pushed as an ordinary `u64`, so there is no way to tell
+//! return addresses apart from other constants. Streams containing a `ret` are therefore
+//! verified but never rewritten.
+
+use crate::verify::{verify, VerifyError};
+use crate::{decode, ByteStream, Op};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum OptLevel {
+    /// Only verifies the input.
+    O0,
+    /// Constant folding and removal of `pushu64; pop` pairs.
+    #[default]
+    O1,
+    /// Everything in `O1` plus jump threading and dead code elimination.
+    O2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptError {
+    /// The input didn't pass verification.
+    Input(VerifyError),
+    /// The optimized output didn't pass verification. This is a bug in the optimizer.
+    Output(VerifyError),
+}
+
+impl fmt::Display for OptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptError::Input(err) => err.fmt(f),
+            OptError::Output(err) => write!(f, "Optimizer produced invalid bytecode: {err}"),
+        }
+    }
+}
+
+impl Error for OptError {}
+
+/// Optimizes `bytes` and returns the new stream.
+pub fn optimize(bytes: &ByteStream, level: OptLevel) -> Result<ByteStream, OptError> {
+    verify(bytes).map_err(OptError::Input)?;
+    let mut listing = Listing::decode(bytes).map_err(OptError::Input)?;
+    if level == OptLevel::O0 || listing.ops.contains(&Op::Ret) {
+        return Ok(ByteStream::new_with_bytes(bytes.as_slice().to_vec()));
+    }
+
+    loop {
+        let mut changed = false;
+        changed |= listing.fold_constants();
+        changed |= listing.remove_push_pop();
+        if level >= OptLevel::O2 {
+            changed |= listing.thread_jumps();
+            changed |= listing.remove_dead_code();
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let output = listing.encode();
+    verify(&output).map_err(OptError::Output)?;
+    Ok(output)
+}
+
+/// A list of instructions whose jump operands are instruction indices.
+/// A jump to `ops.len()` ends the program.
+struct Listing {
+    ops: Vec<Op>,
+}
+
+impl Listing {
+    fn decode(bytes: &ByteStream) -> Result<Listing, VerifyError> {
+        let decoded = decode(bytes)?;
+        let mut index_of = vec![usize::MAX; bytes.len() + 1];
+        for (i, (offset, _)) in decoded.iter().enumerate() {
+            index_of[*offset] = i;
+        }
+        index_of[bytes.len()] = decoded.len();
+
+        let ops = decoded
+            .into_iter()
+            .map(|(offset, op)| {
+                retarget(op, |target| match index_of.get(target as usize) {
+                    Some(index) if *index != usize::MAX => Ok(*index as u32),
+                    _ => Err(VerifyError::InvalidJumpTarget { offset, target }),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Listing { ops })
+    }
+
+    fn encode(&self) -> ByteStream {
+        let mut offsets = Vec::with_capacity(self.ops.len() + 1);
+        let mut offset = 0;
+        for op in &self.ops {
+            offsets.push(offset as u32);
+            offset += op.encoded_len();
+        }
+        offsets.push(offset as u32);
+
+        let mut stream = ByteStream::new();
+        for op in &self.ops {
+            let op = retarget::<()>(*op, |target| Ok(offsets[target as usize])).unwrap();
+            op.encode(&mut stream);
+        }
+        stream
+    }
+
+    /// Returns a flag for every index telling whether some jump lands on it.
+    fn jump_targets(&self) -> Vec<bool> {
+        let mut targets = vec![false; self.ops.len() + 1];
+        for op in &self.ops {
+            if let Some(target) = op.jump_target() {
+                targets[target as usize] = true;
+            }
+        }
+        targets
+    }
+
+    /// Removes every instruction with `dead[i] == true`. Jumps to a removed instruction are
+    /// moved to the next instruction that is kept, so only instructions without any effect
+    /// on a path through them may be removed.
+    fn remove(&mut self, dead: &[bool]) -> bool {
+        if !dead.contains(&true) {
+            return false;
+        }
+
+        let mut new_index = vec![0; self.ops.len() + 1];
+        let mut kept = 0;
+        for (i, is_dead) in dead.iter().enumerate() {
+            new_index[i] = kept;
+            if !is_dead {
+                kept += 1;
+            }
+        }
+        new_index[self.ops.len()] = kept;
+
+        self.ops = self
+            .ops
+            .iter()
+            .zip(dead)
+            .filter(|(_, is_dead)| !**is_dead)
+            .map(|(op, _)| {
+                retarget::<()>(*op, |target| Ok(new_index[target as usize] as u32)).unwrap()
+            })
+            .collect();
+        true
+    }
+
+    /// Folds arithmetic on constants, and branches on constant comparisons.
+    fn fold_constants(&mut self) -> bool {
+        let targets = self.jump_targets();
+        let mut dead = vec![false; self.ops.len()];
+        let mut changed = false;
+
+        let mut i = 0;
+        while i + 2 < self.ops.len() {
+            let (a, b) = match (self.ops[i], self.ops[i + 1]) {
+                (Op::PushU64(a), Op::PushU64(b)) if !targets[i + 1] && !targets[i + 2] => (a, b),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let folded = match self.ops[i + 2] {
+                Op::AddU64 => a.checked_add(b),
+                Op::SubU64 => a.checked_sub(b),
+                _ => None,
+            };
+            if let Some(val) = folded {
+                self.ops[i + 2] = Op::PushU64(val);
+                dead[i] = true;
+                dead[i + 1] = true;
+                changed = true;
+                i += 3;
+                continue;
+            }
+
+            let cond = match self.ops[i + 2] {
+                Op::LtU64 => a < b,
+                Op::GtU64 => a > b,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let branch = match self.ops.get(i + 3) {
+                Some(op) if !targets[i + 3] => *op,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let taken = match branch {
+                Op::GotoIf(target) => cond.then_some(target),
+                Op::GotoIfNot(target) => (!cond).then_some(target),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            match taken {
+                Some(target) => self.ops[i + 3] = Op::Goto(target),
+                None => dead[i + 3] = true,
+            }
+            dead[i] = true;
+            dead[i + 1] = true;
+            dead[i + 2] = true;
+            changed = true;
+            i += 4;
+        }
+
+        self.remove(&dead) || changed
+    }
+
+    /// Removes values that are pushed and immediately popped again.
+    fn remove_push_pop(&mut self) -> bool {
+        let targets = self.jump_targets();
+        let mut dead = vec![false; self.ops.len()];
+
+        let mut i = 0;
+        while i + 1 < self.ops.len() {
+            if matches!(self.ops[i], Op::PushU64(_) | Op::LocalCopy(_))
+                && self.ops[i + 1] == Op::Pop
+                && !targets[i + 1]
+            {
+                dead[i] = true;
+                dead[i + 1] = true;
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+
+        self.remove(&dead)
+    }
+
+    /// Points jumps that land on a `goto` straight at its final destination and removes
+    /// jumps to the next instruction.
+    fn thread_jumps(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.ops.len() {
+            let target = match self.ops[i].jump_target() {
+                Some(target) => target,
+                None => continue,
+            };
+
+            let mut dest = target;
+            // Bounded so that `goto` cycles don't loop forever.
+            for _ in 0..self.ops.len() {
+                match self.ops.get(dest as usize) {
+                    Some(Op::Goto(next)) if *next != dest => dest = *next,
+                    _ => break,
+                }
+            }
+
+            if dest != target {
+                self.ops[i] = retarget::<()>(self.ops[i], |_| Ok(dest)).unwrap();
+                changed = true;
+            }
+        }
+
+        let mut dead = vec![false; self.ops.len()];
+        for (i, op) in self.ops.iter().enumerate() {
+            if *op == Op::Goto(i as u32 + 1) {
+                dead[i] = true;
+            }
+        }
+
+        self.remove(&dead) || changed
+    }
+
+    /// Removes instructions that can't be reached from the start of the program.
+    fn remove_dead_code(&mut self) -> bool {
+        if self.ops.is_empty() {
+            return false;
+        }
+
+        let mut reachable = vec![false; self.ops.len()];
+        let mut worklist = vec![0];
+        reachable[0] = true;
+        while let Some(i) = worklist.pop() {
+            let op = self.ops[i];
+            let mut visit = |next: usize| {
+                if next < reachable.len() && !reachable[next] {
+                    reachable[next] = true;
+                    worklist.push(next);
+                }
+            };
+            if let Some(target) = op.jump_target() {
+                visit(target as usize);
+            }
+            if !op.is_terminator() {
+                visit(i + 1);
+            }
+        }
+
+        let dead: Vec<bool> = reachable.iter().map(|reachable| !reachable).collect();
+        self.remove(&dead)
+    }
+}
+
+/// Replaces the jump operand of `op`, if it has one.
+fn retarget<E>(op: Op, mut f: impl FnMut(u32) -> Result<u32, E>) -> Result<Op, E> {
+    Ok(match op {
+        Op::Goto(target) => Op::Goto(f(target)?),
+        Op::GotoIf(target) => Op::GotoIf(f(target)?),
+        Op::GotoIfNot(target) => Op::GotoIfNot(f(target)?),
+        op => op,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `ops`, whose jump operands are instruction indices.
+    fn program(ops: &[Op]) -> ByteStream {
+        Listing { ops: ops.to_vec() }.encode()
+    }
+
+    /// Decodes `bytes` back into instructions whose jump operands are instruction indices.
+    fn listing(bytes: &ByteStream) -> Vec<Op> {
+        Listing::decode(bytes).unwrap().ops
+    }
+
+    /// Runs `bytes` and returns everything it peeks.
+    fn run(bytes: &ByteStream) -> Vec<String> {
+        let ops = listing(bytes);
+        let mut stack: Vec<u64> = Vec::new();
+        let mut output = Vec::new();
+        let mut pc = 0;
+        while pc < ops.len() {
+            let mut next = pc + 1;
+            match ops[pc] {
+                Op::LocalSet(slot) => {
+                    let val = stack.pop().unwrap();
+                    stack[slot as usize] = val;
+                }
+                Op::LocalCopy(slot) => stack.push(stack[slot as usize]),
+                Op::PushU64(val) => stack.push(val),
+                Op::Pop => {
+                    stack.pop().unwrap();
+                }
+                Op::Ret => unreachable!(),
+                Op::Goto(target) => next = target as usize,
+                Op::GotoIf(target) => {
+                    if stack.pop().unwrap() != 0 {
+                        next = target as usize;
+                    }
+                }
+                Op::GotoIfNot(target) => {
+                    if stack.pop().unwrap() == 0 {
+                        next = target as usize;
+                    }
+                }
+                Op::AddU64 | Op::SubU64 | Op::LtU64 | Op::GtU64 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(match ops[pc] {
+                        Op::AddU64 => a + b,
+                        Op::SubU64 => a - b,
+                        Op::LtU64 => (a < b) as u64,
+                        _ => (a > b) as u64,
+                    });
+                }
+                Op::PeekU64 => output.push(stack.last().unwrap().to_string()),
+                Op::PeekBool => output.push((*stack.last().unwrap() != 0).to_string()),
+            }
+            pc = next;
+        }
+        output
+    }
+
+    /// Optimizes `ops` and checks that the result still does the same.
+    fn optimized(ops: &[Op], level: OptLevel) -> Vec<Op> {
+        let input = program(ops);
+        let output = optimize(&input, level).unwrap();
+        assert_eq!(run(&output), run(&input));
+        listing(&output)
+    }
+
+    #[test]
+    fn folds_arithmetic() {
+        let ops = [
+            Op::PushU64(2),
+            Op::PushU64(3),
+            Op::AddU64,
+            Op::PushU64(1),
+            Op::SubU64,
+            Op::PeekU64,
+        ];
+        assert_eq!(optimized(&ops, OptLevel::O1), [Op::PushU64(4), Op::PeekU64]);
+    }
+
+    #[test]
+    fn does_not_fold_overflow() {
+        let ops = [Op::PushU64(1), Op::PushU64(2), Op::SubU64, Op::Pop];
+        assert_eq!(
+            listing(&optimize(&program(&ops), OptLevel::O1).unwrap()),
+            ops
+        );
+    }
+
+    #[test]
+    fn folds_constant_branches() {
+        let ops = [
+            Op::PushU64(1),
+            Op::PushU64(2),
+            Op::LtU64,
+            Op::GotoIfNot(7),
+            Op::PushU64(10),
+            Op::PeekU64,
+            Op::Goto(9),
+            Op::PushU64(20),
+            Op::PeekU64,
+        ];
+        assert_eq!(
+            optimized(&ops, OptLevel::O1),
+            [
+                Op::PushU64(10),
+                Op::PeekU64,
+                Op::Goto(5),
+                Op::PushU64(20),
+                Op::PeekU64,
+            ]
+        );
+        assert_eq!(
+            optimized(&ops, OptLevel::O2),
+            [Op::PushU64(10), Op::PeekU64]
+        );
+    }
+
+    #[test]
+    fn keeps_folded_instructions_that_are_jump_targets() {
+        // The loop jumps back to the second push, so the pair must not be folded.
+        let ops = [
+            Op::PushU64(7),
+            Op::PushU64(2),
+            Op::AddU64,
+            Op::PeekU64,
+            Op::LocalCopy(0),
+            Op::PushU64(100),
+            Op::LtU64,
+            Op::GotoIf(1),
+        ];
+        assert_eq!(optimized(&ops, OptLevel::O2), ops);
+    }
+
+    #[test]
+    fn remaps_jumps_after_dead_code_elimination() {
+        let ops = [
+            Op::Goto(3),
+            // Dead.
+            Op::PushU64(1),
+            Op::PeekU64,
+            // i = 0; while i < 3 { i += 1 } peek i;
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::GotoIfNot(13),
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(4),
+            Op::PeekU64,
+        ];
+        assert_eq!(
+            optimized(&ops, OptLevel::O2),
+            [
+                Op::PushU64(0),
+                Op::LocalCopy(0),
+                Op::PushU64(3),
+                Op::LtU64,
+                Op::GotoIfNot(10),
+                Op::LocalCopy(0),
+                Op::PushU64(1),
+                Op::AddU64,
+                Op::LocalSet(0),
+                Op::Goto(1),
+                Op::PeekU64,
+            ]
+        );
+    }
+
+    #[test]
+    fn threads_jumps() {
+        let ops = [
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::GtU64,
+            Op::GotoIf(7),
+            Op::Goto(6),
+            Op::Goto(8),
+            Op::Goto(8),
+            Op::PeekU64,
+        ];
+        assert_eq!(
+            optimized(&ops, OptLevel::O2),
+            [
+                Op::PushU64(0),
+                Op::LocalCopy(0),
+                Op::PushU64(1),
+                Op::GtU64,
+                Op::GotoIf(5),
+                Op::PeekU64,
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_rewrite_ret() {
+        let ops = [Op::PushU64(0), Op::PushU64(1), Op::Pop, Op::Ret];
+        let input = program(&ops);
+        let output = optimize(&input, OptLevel::O2).unwrap();
+        assert_eq!(output.as_slice(), input.as_slice());
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let ops = [Op::PushU64(1), Op::AddU64];
+        assert_eq!(
+            optimize(&program(&ops), OptLevel::O2).err(),
+            Some(OptError::Input(VerifyError::StackUnderflow { offset: 9 }))
+        );
+    }
+}
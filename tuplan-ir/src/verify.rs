@@ -0,0 +1,397 @@
+//! Static verification of bytecode.
+//!
+//! Every instruction reachable from the start of the stream is checked for stack underflow,
+//! operand types and valid local slots. Wherever control flow merges, the stack must have the
+//! same shape on every incoming path.
+//!
+//! `ret` jumps to an address that is only known at runtime, so code that can only be reached
+//! through a `ret` is decoded but not type checked.
+
+use crate::{decode, decode_one, ByteStream, DecodeError, Op};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// The type of a value on the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    U64,
+    Bool,
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::U64 => f.write_str("u64"),
+            Ty::Bool => f.write_str("bool"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    Decode(DecodeError),
+    /// The jump at `offset` goes somewhere that isn't the start of an instruction.
+    InvalidJumpTarget {
+        offset: usize,
+        target: u32,
+    },
+    /// The instruction at `offset` pops more values than there are on the stack.
+    StackUnderflow {
+        offset: usize,
+    },
+    /// The instruction at `offset` expected a value of type `expected` but found `found`.
+    TypeMismatch {
+        offset: usize,
+        expected: Ty,
+        found: Ty,
+    },
+    /// The instruction at `offset` accesses a slot past the top of the stack.
+    InvalidSlot {
+        offset: usize,
+        slot: u32,
+    },
+    /// Two paths reach the instruction at `offset` with differently shaped stacks.
+    StackMismatch {
+        offset: usize,
+        first: Vec<Ty>,
+        second: Vec<Ty>,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(tys: &[Ty]) -> String {
+            let tys: Vec<String> = tys.iter().map(|ty| ty.to_string()).collect();
+            format!("[{}]", tys.join(", "))
+        }
+
+        match self {
+            VerifyError::Decode(err) => err.fmt(f),
+            VerifyError::InvalidJumpTarget { offset, target } => write!(
+                f,
+                "Invalid bytecode: Jump at {offset} targets {target}, which is not the start of an instruction."
+            ),
+            VerifyError::StackUnderflow { offset } => {
+                write!(f, "Invalid bytecode: Stack underflow at {offset}.")
+            }
+            VerifyError::TypeMismatch {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Invalid bytecode: Expected `{expected}` at {offset}, found `{found}`."
+            ),
+            VerifyError::InvalidSlot { offset, slot } => write!(
+                f,
+                "Invalid bytecode: Slot {slot} at {offset} is past the top of the stack."
+            ),
+            VerifyError::StackMismatch {
+                offset,
+                first,
+                second,
+            } => write!(
+                f,
+                "Invalid bytecode: Stack at {offset} is both {} and {}.",
+                list(first),
+                list(second)
+            ),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+impl From<DecodeError> for VerifyError {
+    fn from(err: DecodeError) -> Self {
+        VerifyError::Decode(err)
+    }
+}
+
+/// The shape of the stack before every verified instruction.
+#[derive(Debug, Clone, Default)]
+pub struct StackMap {
+    entries: HashMap<usize, Vec<Ty>>,
+    max_depth: usize,
+}
+
+impl StackMap {
+    /// The types on the stack before the instruction at `offset`, bottom first.
+    /// Returns `None` if the instruction was never reached.
+    #[inline]
+    #[must_use]
+    pub fn at(&self, offset: usize) -> Option<&[Ty]> {
+        self.entries.get(&offset).map(|tys| tys.as_slice())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_reachable(&self, offset: usize) -> bool {
+        self.entries.contains_key(&offset)
+    }
+
+    /// The highest number of values that are ever on the stack at once.
+    #[inline]
+    #[must_use]
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+}
+
+/// Checks that `bytes` is well formed and returns the shape of the stack before every reachable instruction.
+pub fn verify(bytes: &ByteStream) -> Result<StackMap, VerifyError> {
+    let len = bytes.len();
+    let mut is_start = vec![false; len + 1];
+    for (offset, _) in decode(bytes)? {
+        is_start[offset] = true;
+    }
+    is_start[len] = true;
+
+    let mut map = StackMap::default();
+    let mut worklist = Vec::new();
+    if len > 0 {
+        map.entries.insert(0, Vec::new());
+        worklist.push(0);
+    }
+
+    while let Some(offset) = worklist.pop() {
+        let (op, next) = decode_one(bytes, offset)?;
+        let mut stack = map.entries[&offset].clone();
+        step(&op, offset, &mut stack)?;
+        map.max_depth = map.max_depth.max(stack.len());
+
+        let mut succs = Vec::with_capacity(2);
+        if let Some(target) = op.jump_target() {
+            if target as usize > len || !is_start[target as usize] {
+                return Err(VerifyError::InvalidJumpTarget { offset, target });
+            }
+            succs.push(target as usize);
+        }
+        if !op.is_terminator() {
+            succs.push(next);
+        }
+
+        for succ in succs {
+            if succ >= len {
+                continue;
+            }
+            match map.entries.get(&succ) {
+                Some(existing) if *existing != stack => {
+                    return Err(VerifyError::StackMismatch {
+                        offset: succ,
+                        first: existing.clone(),
+                        second: stack,
+                    })
+                }
+                Some(_) => (),
+                None => {
+                    map.entries.insert(succ, stack.clone());
+                    worklist.push(succ);
+                }
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+/// Applies the stack effect of `op` to `stack`.
+fn step(op: &Op, offset: usize, stack: &mut Vec<Ty>) -> Result<(), VerifyError> {
+    let pop = |stack: &mut Vec<Ty>, expected: Option<Ty>| {
+        let found = stack.pop().ok_or(VerifyError::StackUnderflow { offset })?;
+        match expected {
+            Some(expected) if expected != found => Err(VerifyError::TypeMismatch {
+                offset,
+                expected,
+                found,
+            }),
+            _ => Ok(found),
+        }
+    };
+
+    match op {
+        Op::LocalSet(slot) => {
+            let val = pop(stack, None)?;
+            *stack
+                .get_mut(*slot as usize)
+                .ok_or(VerifyError::InvalidSlot {
+                    offset,
+                    slot: *slot,
+                })? = val;
+        }
+        Op::LocalCopy(slot) => {
+            let val = *stack.get(*slot as usize).ok_or(VerifyError::InvalidSlot {
+                offset,
+                slot: *slot,
+            })?;
+            stack.push(val);
+        }
+        Op::PushU64(_) => stack.push(Ty::U64),
+        Op::Pop => {
+            pop(stack, None)?;
+        }
+        Op::Ret => {
+            pop(stack, Some(Ty::U64))?;
+        }
+        Op::Goto(_) => (),
+        Op::GotoIf(_) | Op::GotoIfNot(_) => {
+            pop(stack, Some(Ty::Bool))?;
+        }
+        Op::AddU64 | Op::SubU64 => {
+            pop(stack, Some(Ty::U64))?;
+            pop(stack, Some(Ty::U64))?;
+            stack.push(Ty::U64);
+        }
+        Op::LtU64 | Op::GtU64 => {
+            pop(stack, Some(Ty::U64))?;
+            pop(stack, Some(Ty::U64))?;
+            stack.push(Ty::Bool);
+        }
+        Op::PeekU64 => {
+            if *stack.last().ok_or(VerifyError::StackUnderflow { offset })? != Ty::U64 {
+                return Err(VerifyError::TypeMismatch {
+                    offset,
+                    expected: Ty::U64,
+                    found: Ty::Bool,
+                });
+            }
+        }
+        Op::PeekBool => {
+            if *stack.last().ok_or(VerifyError::StackUnderflow { offset })? != Ty::Bool {
+                return Err(VerifyError::TypeMismatch {
+                    offset,
+                    expected: Ty::Bool,
+                    found: Ty::U64,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+
+    #[test]
+    fn accepts_loops() {
+        // i = 0; while i < 3 { i += 1 } peek i;
+        let bytes = encode(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::GotoIfNot(49),
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(9),
+            Op::PeekU64,
+        ]);
+        let map = verify(&bytes).unwrap();
+        assert_eq!(map.at(9), Some(&[Ty::U64][..]));
+        assert_eq!(map.at(24), Some(&[Ty::U64, Ty::Bool][..]));
+        assert_eq!(map.max_depth(), 3);
+    }
+
+    #[test]
+    fn skips_unreachable_code() {
+        let bytes = encode(&[Op::Goto(6), Op::AddU64, Op::PushU64(1)]);
+        let map = verify(&bytes).unwrap();
+        assert!(!map.is_reachable(5));
+        assert!(map.is_reachable(6));
+    }
+
+    #[test]
+    fn rejects_stack_underflow() {
+        assert_eq!(
+            verify(&encode(&[Op::Pop])).err(),
+            Some(VerifyError::StackUnderflow { offset: 0 })
+        );
+        assert_eq!(
+            verify(&encode(&[Op::PushU64(1), Op::AddU64])).err(),
+            Some(VerifyError::StackUnderflow { offset: 9 })
+        );
+        assert_eq!(
+            verify(&encode(&[Op::PeekBool])).err(),
+            Some(VerifyError::StackUnderflow { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn rejects_jumps_into_instructions() {
+        let bytes = encode(&[Op::PushU64(1), Op::Goto(4), Op::Pop]);
+        assert_eq!(
+            verify(&bytes).err(),
+            Some(VerifyError::InvalidJumpTarget {
+                offset: 9,
+                target: 4
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_jumps_past_the_end() {
+        // Jumping to the end of the stream ends the program.
+        assert!(verify(&encode(&[Op::Goto(5)])).is_ok());
+        assert_eq!(
+            verify(&encode(&[Op::Goto(6)])).err(),
+            Some(VerifyError::InvalidJumpTarget {
+                offset: 0,
+                target: 6
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_type_mismatches() {
+        let bytes = encode(&[Op::PushU64(1), Op::GotoIf(14)]);
+        assert_eq!(
+            verify(&bytes).err(),
+            Some(VerifyError::TypeMismatch {
+                offset: 9,
+                expected: Ty::Bool,
+                found: Ty::U64
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_slots() {
+        let bytes = encode(&[Op::PushU64(1), Op::LocalCopy(1)]);
+        assert_eq!(
+            verify(&bytes).err(),
+            Some(VerifyError::InvalidSlot { offset: 9, slot: 1 })
+        );
+    }
+
+    #[test]
+    fn rejects_stack_mismatches() {
+        // Only one of the branches pushes a value before they merge at 33.
+        let bytes = encode(&[
+            Op::PushU64(1),
+            Op::PushU64(2),
+            Op::LtU64,
+            Op::GotoIf(33),
+            Op::PushU64(3),
+            Op::PeekU64,
+        ]);
+        assert_eq!(
+            verify(&bytes).err(),
+            Some(VerifyError::StackMismatch {
+                offset: 33,
+                first: Vec::new(),
+                second: vec![Ty::U64],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_instructions() {
+        let bytes = ByteStream::new_with_bytes(vec![Op::PushU64(0).inst() as u8, 1, 2]);
+        assert!(matches!(verify(&bytes), Err(VerifyError::Decode(_))));
+    }
+}
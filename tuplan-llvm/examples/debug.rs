@@ -0,0 +1,76 @@
+use syntax_rs::{source_map::SourceFile, Span};
+use tuplan_llvm::{llvm_get_function_ty, DwarfEncoding, LLVMContext, LLVMDIBuilder};
+
+const SOURCE: &str = "\
+fn add(a, b) {
+    let sum = a + b;
+    sum
+}
+";
+
+/// The span of the first occurrence of `text` in `SOURCE`.
+fn span(text: &str) -> Span {
+    let begin = SOURCE.find(text).unwrap();
+    Span::new(begin, begin + text.len())
+}
+
+fn main() {
+    let ctx = LLVMContext::new();
+    let mut module = ctx.create_module("add");
+    let mut di = LLVMDIBuilder::new(&mut module);
+    let source = SourceFile::new("add.tpl", SOURCE);
+    let file = di.create_file(&source, ".");
+    let unit = di.create_compile_unit(&file, "tuplan", false);
+    let u64_di = di.create_basic_type("u64", 64, DwarfEncoding::Unsigned);
+
+    let i64_t = ctx.i64_t();
+    let function = di
+        .module()
+        .add_function("add", llvm_get_function_ty(&i64_t, &[i64_t, i64_t]));
+    let ty = di.create_subroutine_type(&file, Some(&u64_di), &[u64_di, u64_di]);
+    let subprogram = di.create_function(&unit, "add", &file, span("fn add"), &ty, false);
+    function.set_subprogram(&subprogram);
+
+    let entry = function.append_basic_block("entry");
+    let mut builder = ctx.create_builder();
+    builder.position_at_end(&entry);
+
+    // Parameters and locals live in allocas so that the debugger can find them.
+    let mut slots = Vec::new();
+    for (i, name) in ["a", "b"].into_iter().enumerate() {
+        let param_span = span(&format!("{name}{}", [",", ")"][i]));
+        let location = di.location(&subprogram, &file, param_span);
+        builder.set_debug_location(Some(&location));
+        let slot = builder.build_alloca(name, &i64_t);
+        builder.build_store(&function.get_param(i), &slot);
+        let var = di.create_parameter_variable(
+            &subprogram,
+            name,
+            i as u32 + 1,
+            &file,
+            param_span,
+            &u64_di,
+        );
+        di.insert_declare_at_end(&slot, &var, &location, &entry);
+        slots.push(slot);
+    }
+
+    let location = di.location(&subprogram, &file, span("a + b"));
+    builder.set_debug_location(Some(&location));
+    let a = builder.build_load("", &i64_t, &slots[0]);
+    let b = builder.build_load("", &i64_t, &slots[1]);
+    let sum = builder.build_add("", &a, &b);
+    let sum_slot = builder.build_alloca("sum", &i64_t);
+    builder.build_store(&sum, &sum_slot);
+    let var = di.create_auto_variable(&subprogram, "sum", &file, span("let sum"), &u64_di);
+    di.insert_declare_at_end(&sum_slot, &var, &location, &entry);
+
+    let location = di.location(&subprogram, &file, span("sum\n"));
+    builder.set_debug_location(Some(&location));
+    let result = builder.build_load("", &i64_t, &sum_slot);
+    builder.build_ret(&result);
+
+    di.finalize();
+    module.verify().unwrap();
+    print!("{}", module.to_ir_string());
+}
@@ -0,0 +1,43 @@
+use tuplan_llvm::{
+    llvm_get_function_ty, llvm_init_jit_with_printer, IntPredicate, LLVMBuilder, LLVMContext,
+    LLVMExecutionEngine, LLVMModule,
+};
+
+fn main() {
+    let ctx = LLVMContext::new();
+    let mut module = LLVMModule::create_with_name_in_ctx("max", &ctx);
+
+    let i64_t = ctx.i64_t();
+    // fn max(a: i64, b: i64) -> i64 { if a > b { a } else { b } }
+    let function_ty = llvm_get_function_ty(&i64_t, &[i64_t, i64_t]);
+    let max = module.add_function("max", function_ty);
+    let (a, b) = (max.get_param(0), max.get_param(1));
+    let entry = max.append_basic_block("entry");
+    let then_block = max.append_basic_block("then");
+    let else_block = max.append_basic_block("else");
+    let end = max.append_basic_block("end");
+
+    let mut builder = LLVMBuilder::new(&ctx);
+    builder.position_at_end(&entry);
+    let cond = builder.build_icmp("cond", IntPredicate::Sgt, &a, &b);
+    builder.build_cond_br(&cond, &then_block, &else_block);
+
+    builder.position_at_end(&then_block);
+    builder.build_br(&end);
+    builder.position_at_end(&else_block);
+    builder.build_br(&end);
+
+    builder.position_at_end(&end);
+    let result = builder.build_phi("result", &i64_t);
+    result.add_incoming(&[(&a, &then_block), (&b, &else_block)]);
+    builder.build_ret(&result);
+
+    module.dump_ir_to_stdout();
+
+    llvm_init_jit_with_printer();
+    let ee = LLVMExecutionEngine::new_for_module(module).unwrap();
+    let function = ee
+        .get_function::<extern "C" fn(i64, i64) -> i64>("max")
+        .unwrap();
+    println!("max({}, {}) = {}", 3, -7, unsafe { function.call(3, -7) });
+}
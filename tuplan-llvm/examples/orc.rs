@@ -0,0 +1,52 @@
+use std::ffi::c_void;
+use tuplan_llvm::{llvm_get_function_ty, LLVMOrcJit};
+
+extern "C" fn print_i64(value: i64) {
+    println!("{value}");
+}
+
+fn main() {
+    let jit = LLVMOrcJit::new().unwrap();
+    unsafe {
+        jit.define_host_functions(&[("print_i64", print_i64 as *const c_void)])
+            .unwrap();
+    }
+
+    // Every module is added on its own, like the tiered JIT does with hot loops.
+    for (name, n) in [("square", 2), ("cube", 3)] {
+        let ctx = jit.context();
+        let mut module = ctx.create_module(name);
+        let i64_t = ctx.i64_t();
+        let print_ty = llvm_get_function_ty(&ctx.void_t(), &[i64_t]);
+        let print = module.add_function("print_i64", print_ty);
+
+        // fn name(x: i64) -> i64 { let y = x * ... * x; print_i64(y); y }
+        let function = module.add_function(name, llvm_get_function_ty(&i64_t, &[i64_t]));
+        let x = function.get_param(0);
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&function.append_basic_block("entry"));
+        let mut y = x;
+        for _ in 1..n {
+            y = builder.build_mul("y", &y, &x);
+        }
+        builder.build_call("", &print_ty, &print, &[&y]);
+        builder.build_ret(&y);
+
+        print!("{}", module.to_ir_string());
+        jit.add_module(module).unwrap();
+    }
+
+    let square = jit
+        .get_function::<extern "C" fn(i64) -> i64>("square")
+        .unwrap();
+    let cube = jit
+        .get_function::<extern "C" fn(i64) -> i64>("cube")
+        .unwrap();
+    unsafe {
+        square.call(7);
+        cube.call(7);
+    }
+    assert!(jit
+        .get_function::<extern "C" fn(f64) -> f64>("cube")
+        .is_err());
+}
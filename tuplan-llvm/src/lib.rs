@@ -1,40 +1,184 @@
+//! Wrappers around the LLVM C API for building, optimizing, emitting and JIT compiling modules.
+//!
+//! # Handles
+//!
+//! `LLVMType`, `LLVMValue`, `LLVMBasicBlock` and `LLVMMetadata` are `Copy` references into LLVM
+//! whose `'ctx` lifetime only ties them to their `LLVMContext`. Types live as long as the
+//! context, but values, basic blocks and metadata belong to the module they were created in,
+//! and nothing ties them to that module. They still compile after the `LLVMModule` is dropped or
+//! handed to `LLVMExecutionEngine::new_for_module` or `LLVMOrcJit::add_module`, and using them
+//! then is a use after free. Don't keep handles past their module, look functions up again with
+//! `LLVMModule::get_function` or `get_function` of the engine or JIT instead.
+
 use llvm::{
-    analysis::{LLVMVerifyModule, LLVMVerifierFailureAction::LLVMAbortProcessAction},
+    analysis::{LLVMVerifyFunction, LLVMVerifyModule, LLVMVerifierFailureAction::LLVMReturnStatusAction},
     core::{
         LLVMAddFunction, LLVMAppendBasicBlock, LLVMBuildAdd, LLVMBuildRet, LLVMContextCreate,
-        LLVMCreateBuilder, LLVMFloatTypeInContext, LLVMFunctionType, LLVMGetBasicBlockName,
+        LLVMCreateBuilderInContext, LLVMFloatTypeInContext, LLVMFunctionType, LLVMGetBasicBlockName,
         LLVMGetModuleIdentifier, LLVMGetParam, LLVMGetValueName, LLVMGetValueName2, LLVMInt64Type,
         LLVMInt64TypeInContext, LLVMModuleCreateWithNameInContext, LLVMPositionBuilderAtEnd, LLVMDisposeMessage, LLVMDisposeBuilder, LLVMDumpModule, LLVMDisposeModule, LLVMContextDispose,
+        LLVMBuildSub, LLVMBuildICmp, LLVMBuildBr, LLVMBuildCondBr, LLVMBuildPhi, LLVMAddIncoming, LLVMBuildCall2, LLVMBuildRetVoid,
+        LLVMConstInt, LLVMInt1TypeInContext, LLVMVoidTypeInContext, LLVMInt32TypeInContext, LLVMSetTarget,
+        LLVMGetNamedFunction, LLVMPointerType, LLVMBuildInBoundsGEP2, LLVMBuildLoad2, LLVMBuildStore, LLVMBuildZExt,
+        LLVMBuildMul, LLVMBuildUDiv, LLVMBuildSDiv, LLVMBuildURem, LLVMBuildSRem, LLVMBuildAnd, LLVMBuildOr, LLVMBuildXor,
+        LLVMBuildShl, LLVMBuildLShr, LLVMBuildAShr, LLVMBuildFAdd, LLVMBuildFSub, LLVMBuildFMul, LLVMBuildFDiv, LLVMBuildFRem,
+        LLVMBuildNeg, LLVMBuildNot, LLVMBuildFNeg, LLVMBuildFCmp, LLVMBuildSelect, LLVMBuildCast, LLVMBuildSwitch, LLVMAddCase,
+        LLVMInt8TypeInContext, LLVMIntTypeInContext, LLVMDoubleTypeInContext, LLVMArrayType, LLVMVectorType, LLVMStructTypeInContext,
+        LLVMStructCreateNamed, LLVMStructSetBody, LLVMConstReal, LLVMConstNull, LLVMConstArray, LLVMConstNamedStruct,
+        LLVMConstStructInContext, LLVMConstStringInContext, LLVMBuildStructGEP2, LLVMBuildAlloca, LLVMBuildMemCpy,
+        LLVMBuildGlobalStringPtr, LLVMPrintValueToString,
+        LLVMCreateMemoryBufferWithContentsOfFile, LLVMDisposeMemoryBuffer, LLVMCreateMemoryBufferWithMemoryRangeCopy,
+        LLVMPrintModuleToString, LLVMPrintModuleToFile, LLVMGlobalGetValueType, LLVMPrintTypeToString, LLVMGetModuleContext,
+        LLVMGetFirstFunction, LLVMGetNextFunction, LLVMIsDeclaration, LLVMAddModuleFlag, LLVMValueAsMetadata,
+        LLVMSetCurrentDebugLocation2,
+    },
+    debuginfo::{
+        LLVMCreateDIBuilder, LLVMDisposeDIBuilder, LLVMDIBuilderFinalize, LLVMDebugMetadataVersion, LLVMDIBuilderCreateFile,
+        LLVMDIBuilderCreateCompileUnit, LLVMDIBuilderCreateSubroutineType, LLVMDIBuilderCreateBasicType,
+        LLVMDIBuilderCreateFunction, LLVMDIBuilderCreateLexicalBlock, LLVMDIBuilderCreateAutoVariable,
+        LLVMDIBuilderCreateParameterVariable, LLVMDIBuilderCreateDebugLocation, LLVMDIBuilderCreateExpression,
+        LLVMDIBuilderInsertDeclareAtEnd, LLVMSetSubprogram, LLVMDWARFSourceLanguage, LLVMDWARFEmissionKind,
+        LLVMDIFlagZero,
     },
+    LLVMModuleFlagBehavior,
+    ir_reader::LLVMParseIRInContext,
     prelude::{
-        LLVMBasicBlockRef, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef,
+        LLVMBasicBlockRef, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef, LLVMMetadataRef,
+        LLVMDIBuilderRef,
+    },
+    LLVMIntPredicate, LLVMRealPredicate, LLVMOpcode, LLVMValueKind, execution_engine::{LLVMExecutionEngineRef, LLVMLinkInMCJIT, LLVMCreateExecutionEngineForModule, LLVMDisposeExecutionEngine, LLVMGetFunctionAddress, LLVMAddGlobalMapping}, target::{LLVM_InitializeNativeTarget, LLVM_InitializeNativeAsmPrinter, LLVM_InitializeAllTargetInfos, LLVM_InitializeAllTargets, LLVM_InitializeAllTargetMCs, LLVM_InitializeAllAsmPrinters, LLVMSetModuleDataLayout, LLVMDisposeTargetData},
+    target_machine::{
+        LLVMCodeGenFileType, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMCreateTargetDataLayout, LLVMCreateTargetMachine,
+        LLVMDisposeTargetMachine, LLVMGetDefaultTargetTriple, LLVMGetTargetFromTriple, LLVMRelocMode, LLVMTargetMachineEmitToFile,
+        LLVMTargetMachineRef, LLVMGetTargetMachineTriple, LLVMGetHostCPUName, LLVMGetHostCPUFeatures,
+    },
+    bit_writer::LLVMWriteBitcodeToFile,
+    error::{LLVMErrorRef, LLVMGetErrorMessage, LLVMDisposeErrorMessage},
+    orc2::{
+        lljit::{
+            LLVMOrcLLJITRef, LLVMOrcCreateLLJIT, LLVMOrcDisposeLLJIT, LLVMOrcLLJITGetMainJITDylib, LLVMOrcLLJITGetGlobalPrefix,
+            LLVMOrcLLJITMangleAndIntern, LLVMOrcLLJITAddLLVMIRModule, LLVMOrcLLJITLookup,
+        },
+        LLVMOrcThreadSafeContextRef, LLVMOrcCreateNewThreadSafeContext, LLVMOrcThreadSafeContextGetContext,
+        LLVMOrcDisposeThreadSafeContext, LLVMOrcCreateNewThreadSafeModule, LLVMOrcAbsoluteSymbols, LLVMOrcJITDylibDefine,
+        LLVMOrcDisposeMaterializationUnit, LLVMOrcCSymbolMapPair, LLVMJITEvaluatedSymbol, LLVMJITSymbolFlags,
+        LLVMJITSymbolGenericFlags, LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess, LLVMOrcJITDylibAddGenerator,
     },
-    LLVMValueKind, execution_engine::{LLVMExecutionEngineRef, LLVMLinkInMCJIT, LLVMCreateExecutionEngineForModule, LLVMDisposeExecutionEngine, LLVMGetFunctionAddress}, target::{LLVM_InitializeNativeTarget, LLVM_InitializeNativeAsmPrinter},
+    transforms::pass_builder::{
+        LLVMCreatePassBuilderOptions, LLVMDisposePassBuilderOptions, LLVMPassBuilderOptionsSetVerifyEach, LLVMRunPasses,
+    },
+    bit_reader::LLVMParseBitcodeInContext2,
 };
 use llvm_sys as llvm;
+use syntax_rs::{source_map::SourceFile, Span};
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     ffi::{CStr, CString},
     mem,
-    ptr, marker::PhantomData,
+    ptr, marker::PhantomData, path::Path, mem::ManuallyDrop,
 };
 
+// Types are owned by their context, and values and basic blocks by their module. The handles
+// are only references to them, so they are `Copy`. See the crate docs for what `'ctx` doesn't catch.
+
+// Types are uniqued by their context, so comparing the references compares the types.
 #[repr(C)]
-pub struct LLVMType {
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LLVMType<'ctx> {
     pub(crate) inner: LLVMTypeRef,
+    _marker: PhantomData<&'ctx LLVMContext>,
 }
 
-impl LLVMType {
-    pub unsafe fn copy_ref(&self) -> LLVMType {
-        LLVMType { inner: self.inner }
+impl<'ctx> LLVMType<'ctx> {
+    pub(crate) fn new(inner: LLVMTypeRef) -> LLVMType<'ctx> {
+        LLVMType { inner, _marker: PhantomData }
+    }
+
+    pub fn const_int(&self, value: u64, sign_extend: bool) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMConstInt(self.inner, value, sign_extend as llvm::prelude::LLVMBool))
+        }
+    }
+
+    pub fn const_real(&self, value: f64) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMConstReal(self.inner, value))
+        }
+    }
+
+    /// The zero value of this type, which is `null` for pointers.
+    pub fn const_null(&self) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMConstNull(self.inner))
+        }
+    }
+
+    /// A constant array with elements of this type.
+    pub fn const_array(&self, values: &[&LLVMValue<'ctx>]) -> LLVMValue<'ctx> {
+        let mut values: Vec<LLVMValueRef> = values.iter().map(|value| value.inner).collect();
+        unsafe {
+            LLVMValue::new(LLVMConstArray(self.inner, values.as_mut_ptr(), values.len() as libc::c_uint))
+        }
+    }
+
+    /// A constant of this named struct type.
+    pub fn const_named_struct(&self, values: &[&LLVMValue<'ctx>]) -> LLVMValue<'ctx> {
+        let mut values: Vec<LLVMValueRef> = values.iter().map(|value| value.inner).collect();
+        unsafe {
+            LLVMValue::new(LLVMConstNamedStruct(self.inner, values.as_mut_ptr(), values.len() as libc::c_uint))
+        }
+    }
+
+    pub fn print_to_string(&self) -> String {
+        unsafe { take_message(LLVMPrintTypeToString(self.inner)) }
+    }
+
+    /// The type of a pointer to a value of this type in the default address space.
+    pub fn ptr_t(&self) -> LLVMType<'ctx> {
+        unsafe {
+            LLVMType::new(LLVMPointerType(self.inner, 0))
+        }
+    }
+
+    pub fn array_t(&self, len: u32) -> LLVMType<'ctx> {
+        unsafe {
+            LLVMType::new(LLVMArrayType(self.inner, len))
+        }
+    }
+
+    pub fn vector_t(&self, len: u32) -> LLVMType<'ctx> {
+        unsafe {
+            LLVMType::new(LLVMVectorType(self.inner, len))
+        }
+    }
+
+    /// Sets the fields of a named struct created with `LLVMContext::named_struct_t`.
+    pub fn set_struct_body(&self, fields: &[&LLVMType<'ctx>], packed: bool) {
+        let mut fields: Vec<LLVMTypeRef> = fields.iter().map(|field| field.inner).collect();
+        unsafe {
+            LLVMStructSetBody(
+                self.inner,
+                fields.as_mut_ptr(),
+                fields.len() as libc::c_uint,
+                packed as llvm::prelude::LLVMBool,
+            )
+        }
     }
 }
 
 #[repr(C)]
-pub struct LLVMValue {
+#[derive(Clone, Copy)]
+pub struct LLVMValue<'ctx> {
     pub(crate) inner: LLVMValueRef,
+    _marker: PhantomData<&'ctx LLVMContext>,
 }
 
-impl LLVMValue {
+impl<'ctx> LLVMValue<'ctx> {
+    pub(crate) fn new(inner: LLVMValueRef) -> LLVMValue<'ctx> {
+        LLVMValue { inner, _marker: PhantomData }
+    }
+
     pub fn name(&self) -> &CStr {
         unsafe {
             let ptr: *const libc::c_char = LLVMGetValueName2(self.inner, &mut mem::uninitialized());
@@ -42,21 +186,57 @@ impl LLVMValue {
         }
     }
 
-    // Unsafe because you can get multiple references to the same value.
-    pub unsafe fn get_param(&self, n: usize) -> LLVMValue {
+    pub fn get_param(&self, n: usize) -> LLVMValue<'ctx> {
         unsafe {
-            LLVMValue {
-                inner: LLVMGetParam(self.inner, n as libc::c_uint),
-            }
+            LLVMValue::new(LLVMGetParam(self.inner, n as libc::c_uint))
+        }
+    }
+
+    /// The type of the value a global points to, which is the function type for functions.
+    pub fn global_value_type(&self) -> LLVMType<'ctx> {
+        unsafe {
+            LLVMType::new(LLVMGlobalGetValueType(self.inner))
+        }
+    }
+
+    /// Attaches the debug info of a function created with `LLVMDIBuilder::create_function`.
+    pub fn set_subprogram(&self, subprogram: &LLVMMetadata<'ctx>) {
+        unsafe { LLVMSetSubprogram(self.inner, subprogram.inner) }
+    }
+
+    /// Checks that a function is well formed. Use `LLVMModule::verify` to get the reason it isn't.
+    pub fn verify_function(&self) -> bool {
+        unsafe { LLVMVerifyFunction(self.inner, LLVMReturnStatusAction) == 0 }
+    }
+
+    /// Prints the value as LLVM IR. For functions, this is the whole definition.
+    pub fn print_to_string(&self) -> String {
+        unsafe { take_message(LLVMPrintValueToString(self.inner)) }
+    }
+
+    /// Adds incoming edges to a phi node.
+    pub fn add_incoming(&self, incoming: &[(&LLVMValue<'ctx>, &LLVMBasicBlock<'ctx>)]) {
+        let mut values: Vec<LLVMValueRef> = incoming.iter().map(|(value, _)| value.inner).collect();
+        let mut blocks: Vec<LLVMBasicBlockRef> = incoming.iter().map(|(_, block)| block.inner).collect();
+        unsafe {
+            LLVMAddIncoming(
+                self.inner,
+                values.as_mut_ptr(),
+                blocks.as_mut_ptr(),
+                incoming.len() as libc::c_uint,
+            )
         }
     }
 
-    pub fn append_basic_block<S: Into<Vec<u8>>>(&mut self, name: S) -> LLVMBasicBlock {
+    /// Adds a case to a switch instruction.
+    pub fn add_case(&self, on_value: &LLVMValue<'ctx>, dest: &LLVMBasicBlock<'ctx>) {
+        unsafe { LLVMAddCase(self.inner, on_value.inner, dest.inner) }
+    }
+
+    pub fn append_basic_block<S: Into<Vec<u8>>>(&self, name: S) -> LLVMBasicBlock<'ctx> {
         unsafe {
             let cstring = CString::new(name).unwrap();
-            LLVMBasicBlock {
-                inner: LLVMAppendBasicBlock(self.inner, cstring.as_ptr()),
-            }
+            LLVMBasicBlock::new(LLVMAppendBasicBlock(self.inner, cstring.as_ptr()))
         }
     }
 }
@@ -75,15 +255,87 @@ impl LLVMContext {
         }
     }
 
-    pub fn i64_t(&self) -> LLVMType {
-        LLVMType {
-            inner: unsafe { LLVMInt64TypeInContext(self.inner) },
+    pub fn create_module<N: Into<Vec<u8>>>(&self, name: N) -> LLVMModule<'_> {
+        LLVMModule::create_with_name_in_ctx(name, self)
+    }
+
+    pub fn create_builder(&self) -> LLVMBuilder<'_> {
+        LLVMBuilder::new(self)
+    }
+
+    pub fn i64_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMInt64TypeInContext(self.inner) })
+    }
+
+    pub fn i1_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMInt1TypeInContext(self.inner) })
+    }
+
+    pub fn i32_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMInt32TypeInContext(self.inner) })
+    }
+
+    pub fn void_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMVoidTypeInContext(self.inner) })
+    }
+
+    pub fn float_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMFloatTypeInContext(self.inner) })
+    }
+
+    pub fn i8_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMInt8TypeInContext(self.inner) })
+    }
+
+    pub fn int_t<'ctx>(&'ctx self, bits: u32) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMIntTypeInContext(self.inner, bits) })
+    }
+
+    pub fn f64_t<'ctx>(&'ctx self) -> LLVMType<'ctx> {
+        LLVMType::new(unsafe { LLVMDoubleTypeInContext(self.inner) })
+    }
+
+    /// A struct type without a name, which is equal to every other struct with the same fields.
+    pub fn struct_t<'ctx>(&'ctx self, fields: &[&LLVMType<'ctx>], packed: bool) -> LLVMType<'ctx> {
+        let mut fields: Vec<LLVMTypeRef> = fields.iter().map(|field| field.inner).collect();
+        LLVMType::new(unsafe {
+            LLVMStructTypeInContext(
+                self.inner,
+                fields.as_mut_ptr(),
+                fields.len() as libc::c_uint,
+                packed as llvm::prelude::LLVMBool,
+            )
+        })
+    }
+
+    /// An opaque struct type. Its fields are set with `LLVMType::set_struct_body`.
+    pub fn named_struct_t<'ctx, S: Into<Vec<u8>>>(&'ctx self, name: S) -> LLVMType<'ctx> {
+        let cstring = CString::new(name).unwrap();
+        LLVMType::new(unsafe { LLVMStructCreateNamed(self.inner, cstring.as_ptr()) })
+    }
+
+    /// A constant of a struct type without a name.
+    pub fn const_struct<'ctx>(&'ctx self, values: &[&LLVMValue<'ctx>], packed: bool) -> LLVMValue<'ctx> {
+        let mut values: Vec<LLVMValueRef> = values.iter().map(|value| value.inner).collect();
+        unsafe {
+            LLVMValue::new(LLVMConstStructInContext(
+                self.inner,
+                values.as_mut_ptr(),
+                values.len() as libc::c_uint,
+                packed as llvm::prelude::LLVMBool,
+            ))
         }
     }
 
-    pub fn float_t(&self) -> LLVMType {
-        LLVMType {
-            inner: unsafe { LLVMFloatTypeInContext(self.inner) },
+    /// A constant `[N x i8]` array holding `string`, followed by a null byte if `null_terminate` is set.
+    pub fn const_string<'ctx>(&'ctx self, string: &[u8], null_terminate: bool) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMConstStringInContext(
+                self.inner,
+                string.as_ptr() as *const libc::c_char,
+                string.len() as libc::c_uint,
+                !null_terminate as llvm::prelude::LLVMBool,
+            ))
         }
     }
 }
@@ -96,17 +348,18 @@ impl Drop for LLVMContext {
     }
 }
 
+/// A module and everything in it, which is disposed when this is dropped.
 #[repr(C)]
-pub struct LLVMModule {
+pub struct LLVMModule<'ctx> {
     pub(crate) inner: LLVMModuleRef,
-    _marker: PhantomData<llvm::LLVMModule>,
+    _marker: PhantomData<&'ctx LLVMContext>,
 }
 
-impl LLVMModule {
+impl<'ctx> LLVMModule<'ctx> {
     pub fn create_with_name_in_ctx<N: Into<Vec<u8>>>(
         name: N,
-        context: &mut LLVMContext,
-    ) -> LLVMModule {
+        context: &'ctx LLVMContext,
+    ) -> LLVMModule<'ctx> {
         let cstring = CString::new(name).unwrap();
         Self {
             inner: unsafe { LLVMModuleCreateWithNameInContext(cstring.as_ptr(), context.inner) },
@@ -114,12 +367,20 @@ impl LLVMModule {
         }
     }
 
-    pub fn add_function<N: Into<Vec<u8>>>(&mut self, name: N, function_ty: LLVMType) -> LLVMValue {
+    pub fn add_function<N: Into<Vec<u8>>>(&mut self, name: N, function_ty: LLVMType<'ctx>) -> LLVMValue<'ctx> {
         let cstring = CString::new(name).unwrap();
         unsafe {
-            LLVMValue {
-                inner: LLVMAddFunction(self.inner, cstring.as_ptr(), function_ty.inner),
-            }
+            LLVMValue::new(LLVMAddFunction(self.inner, cstring.as_ptr(), function_ty.inner))
+        }
+    }
+
+    pub fn get_function<N: Into<Vec<u8>>>(&self, name: N) -> Option<LLVMValue<'ctx>> {
+        let cstring = CString::new(name).unwrap();
+        let inner = unsafe { LLVMGetNamedFunction(self.inner, cstring.as_ptr()) };
+        if inner.is_null() {
+            None
+        } else {
+            Some(LLVMValue::new(inner))
         }
     }
 
@@ -131,136 +392,1849 @@ impl LLVMModule {
         }
     }
 
+    /// Checks that the module is well formed, and returns the verifier's message if it isn't.
+    pub fn verify(&self) -> Result<(), String> {
+        let mut error = ptr::null_mut();
+        unsafe {
+            let failed = LLVMVerifyModule(self.inner, LLVMReturnStatusAction, &mut error) != 0;
+            let message = take_message(error);
+            if failed {
+                return Err(message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a module from textual IR, as written by `to_ir_string` or found in `.ll` files.
+    pub fn parse_ir(context: &'ctx LLVMContext, ir: &str) -> Result<LLVMModule<'ctx>, String> {
+        let name = CString::new("ir").unwrap();
+        unsafe {
+            // The parser takes ownership of the buffer.
+            let buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
+                ir.as_ptr() as *const libc::c_char,
+                ir.len(),
+                name.as_ptr(),
+            );
+            let mut module = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            if LLVMParseIRInContext(context.inner, buffer, &mut module, &mut error) != 0 {
+                return Err(take_message(error));
+            }
+            Ok(LLVMModule { inner: module, _marker: PhantomData })
+        }
+    }
+
+    /// Prints the module as textual IR.
+    pub fn to_ir_string(&self) -> String {
+        unsafe { take_message(LLVMPrintModuleToString(self.inner)) }
+    }
+
+    pub fn write_ir_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let filename = path_to_cstring(path.as_ref());
+        let mut error = ptr::null_mut();
+        unsafe {
+            if LLVMPrintModuleToFile(self.inner, filename.as_ptr(), &mut error) != 0 {
+                return Err(take_message(error));
+            }
+        }
+        Ok(())
+    }
+
     pub fn dump_ir_to_stdout(&self) {
         unsafe {
             LLVMDumpModule(self.inner)
         }
     }
+
+    /// Compiles the module for the host and writes it to `path` as a relocatable object file.
+    /// `llvm_init_native_target` has to be called first.
+    pub fn write_object_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let machine = LLVMTargetMachine::host_generic(CodeGenOptLevel::Default)?;
+        machine.configure_module(self);
+        machine.emit_to_file(self, path, FileType::Object)
+    }
+
+    pub fn write_bitcode_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let filename = path_to_cstring(path.as_ref());
+        if unsafe { LLVMWriteBitcodeToFile(self.inner, filename.as_ptr()) } != 0 {
+            return Err(format!("Failed to write bitcode to `{}`.", path.as_ref().display()));
+        }
+        Ok(())
+    }
+
+    /// Reads a module from a bitcode file written by `write_bitcode_to_file` or `clang -emit-llvm`.
+    pub fn read_bitcode_from_file<P: AsRef<Path>>(
+        path: P,
+        context: &'ctx LLVMContext,
+    ) -> Result<LLVMModule<'ctx>, String> {
+        let filename = path_to_cstring(path.as_ref());
+        unsafe {
+            let mut buffer = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            if LLVMCreateMemoryBufferWithContentsOfFile(filename.as_ptr(), &mut buffer, &mut error) != 0 {
+                return Err(take_message(error));
+            }
+            let mut module = ptr::null_mut();
+            let failed = LLVMParseBitcodeInContext2(context.inner, buffer, &mut module) != 0;
+            LLVMDisposeMemoryBuffer(buffer);
+            if failed {
+                return Err(format!("`{}` doesn't contain valid bitcode.", path.as_ref().display()));
+            }
+            Ok(LLVMModule { inner: module, _marker: PhantomData })
+        }
+    }
+}
+
+fn path_to_cstring(path: &Path) -> CString {
+    CString::new(path.to_string_lossy().into_owned()).unwrap()
+}
+
+/// Copies an error message returned by LLVM into a `String` and frees the original.
+unsafe fn take_message(message: *mut libc::c_char) -> String {
+    if message.is_null() {
+        return String::new();
+    }
+    let string = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeMessage(message);
+    string
+}
+
+/// Copies the message of an `LLVMErrorRef` into a `String` and frees the error.
+unsafe fn take_error(error: LLVMErrorRef) -> String {
+    let message = LLVMGetErrorMessage(error);
+    let string = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    string
 }
 
-impl Drop for LLVMModule {
+impl Drop for LLVMModule<'_> {
     fn drop(&mut self) {
-        let mut error: *mut libc::c_char = ptr::null_mut();
-        unsafe {
-            LLVMVerifyModule(self.inner, LLVMAbortProcessAction, &mut error as *mut _);
-            LLVMDisposeMessage(error);
+        unsafe { LLVMDisposeModule(self.inner) }
+    }
+}
+
+/// The triple of the host, such as `x86_64-unknown-linux-gnu`.
+pub fn llvm_default_target_triple() -> String {
+    unsafe { take_message(LLVMGetDefaultTargetTriple()) }
+}
+
+/// The name of the host CPU, such as `skylake`.
+pub fn llvm_host_cpu_name() -> String {
+    unsafe { take_message(LLVMGetHostCPUName()) }
+}
+
+/// The features of the host CPU, such as `+sse2,+avx`.
+pub fn llvm_host_cpu_features() -> String {
+    unsafe { take_message(LLVMGetHostCPUFeatures()) }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CodeGenOptLevel {
+    None,
+    Less,
+    #[default]
+    Default,
+    Aggressive,
+}
+
+impl From<CodeGenOptLevel> for LLVMCodeGenOptLevel {
+    fn from(level: CodeGenOptLevel) -> Self {
+        match level {
+            CodeGenOptLevel::None => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            CodeGenOptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            CodeGenOptLevel::Default => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            CodeGenOptLevel::Aggressive => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
         }
     }
 }
 
-#[repr(C)]
-pub struct LLVMBasicBlock {
-    pub(crate) inner: LLVMBasicBlockRef,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RelocMode {
+    #[default]
+    Default,
+    Static,
+    /// Position independent code, which is needed to link into shared libraries and PIE executables.
+    Pic,
+    DynamicNoPic,
 }
 
-impl LLVMBasicBlock {
-    pub fn name(&self) -> &CStr {
-        unsafe { CStr::from_ptr(LLVMGetBasicBlockName(self.inner)) }
+impl From<RelocMode> for LLVMRelocMode {
+    fn from(mode: RelocMode) -> Self {
+        match mode {
+            RelocMode::Default => LLVMRelocMode::LLVMRelocDefault,
+            RelocMode::Static => LLVMRelocMode::LLVMRelocStatic,
+            RelocMode::Pic => LLVMRelocMode::LLVMRelocPIC,
+            RelocMode::DynamicNoPic => LLVMRelocMode::LLVMRelocDynamicNoPic,
+        }
     }
 }
 
-#[repr(C)]
-pub struct LLVMBuilder {
-    pub(crate) inner: LLVMBuilderRef,
-    _marker: PhantomData<llvm::LLVMBuilder>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Object,
+    Assembly,
 }
 
-impl LLVMBuilder {
-    pub fn new() -> LLVMBuilder {
-        LLVMBuilder {
-            inner: unsafe { LLVMCreateBuilder() },
-            _marker: PhantomData,
+impl From<FileType> for LLVMCodeGenFileType {
+    fn from(file_type: FileType) -> Self {
+        match file_type {
+            FileType::Object => LLVMCodeGenFileType::LLVMObjectFile,
+            FileType::Assembly => LLVMCodeGenFileType::LLVMAssemblyFile,
         }
     }
+}
+
+/// Generates machine code for a target. The target has to be initialized first, with
+/// `llvm_init_native_target` for the host or `llvm_init_all_targets` for cross compilation.
+pub struct LLVMTargetMachine {
+    pub(crate) inner: LLVMTargetMachineRef,
+}
 
-    pub fn position_at_end(&mut self, block: &LLVMBasicBlock) {
+impl LLVMTargetMachine {
+    /// `cpu` and `features` are in the format of `llc -mcpu` and `llc -mattr`, and may be empty.
+    pub fn new(
+        triple: &str,
+        cpu: &str,
+        features: &str,
+        opt_level: CodeGenOptLevel,
+        reloc_mode: RelocMode,
+    ) -> Result<LLVMTargetMachine, String> {
+        let triple = CString::new(triple).unwrap();
+        let cpu = CString::new(cpu).unwrap();
+        let features = CString::new(features).unwrap();
         unsafe {
-            LLVMPositionBuilderAtEnd(self.inner, block.inner);
+            let mut target = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            if LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut error) != 0 {
+                return Err(take_message(error));
+            }
+            Ok(LLVMTargetMachine {
+                inner: LLVMCreateTargetMachine(
+                    target,
+                    triple.as_ptr(),
+                    cpu.as_ptr(),
+                    features.as_ptr(),
+                    opt_level.into(),
+                    reloc_mode.into(),
+                    LLVMCodeModel::LLVMCodeModelDefault,
+                ),
+            })
         }
     }
 
-    pub fn build_add<S: Into<Vec<u8>>>(
-        &mut self,
-        name: S,
-        lhs: &LLVMValue,
-        rhs: &LLVMValue,
-    ) -> LLVMValue {
+    /// A target machine for the host CPU and all of its features. The generated code may not run
+    /// on other machines.
+    pub fn host(opt_level: CodeGenOptLevel) -> Result<LLVMTargetMachine, String> {
+        LLVMTargetMachine::new(
+            &llvm_default_target_triple(),
+            &llvm_host_cpu_name(),
+            &llvm_host_cpu_features(),
+            opt_level,
+            RelocMode::Pic,
+        )
+    }
+
+    /// A target machine for the host triple and a generic CPU, so the generated code runs on
+    /// every machine with the same triple. This is what `LLVMModule::write_object_file` uses.
+    pub fn host_generic(opt_level: CodeGenOptLevel) -> Result<LLVMTargetMachine, String> {
+        LLVMTargetMachine::new(
+            &llvm_default_target_triple(),
+            "generic",
+            "",
+            opt_level,
+            RelocMode::Pic,
+        )
+    }
+
+    pub fn triple(&self) -> String {
+        unsafe { take_message(LLVMGetTargetMachineTriple(self.inner)) }
+    }
+
+    /// Sets the triple and data layout of `module` to the ones of this machine. Optimizations
+    /// depend on them, so this should be done before optimizing the module.
+    pub fn configure_module(&self, module: &mut LLVMModule<'_>) {
         unsafe {
-            let cstring = CString::new(name).unwrap();
-            LLVMValue {
-                inner: LLVMBuildAdd(self.inner, lhs.inner, rhs.inner, cstring.as_ptr()),
-            }
+            let triple = LLVMGetTargetMachineTriple(self.inner);
+            LLVMSetTarget(module.inner, triple);
+            LLVMDisposeMessage(triple);
+            let layout = LLVMCreateTargetDataLayout(self.inner);
+            LLVMSetModuleDataLayout(module.inner, layout);
+            LLVMDisposeTargetData(layout);
         }
     }
 
-    pub fn build_ret(&mut self, value: &LLVMValue) -> LLVMValue {
+    pub fn emit_to_file<P: AsRef<Path>>(
+        &self,
+        module: &LLVMModule<'_>,
+        path: P,
+        file_type: FileType,
+    ) -> Result<(), String> {
+        let filename = path_to_cstring(path.as_ref());
+        let mut error = ptr::null_mut();
         unsafe {
-            LLVMValue {
-                inner: LLVMBuildRet(self.inner, value.inner),
+            if LLVMTargetMachineEmitToFile(
+                self.inner,
+                module.inner,
+                filename.as_ptr() as *mut _,
+                file_type.into(),
+                &mut error,
+            ) != 0
+            {
+                return Err(take_message(error));
             }
         }
+        Ok(())
     }
 }
 
-impl Drop for LLVMBuilder {
+impl Drop for LLVMTargetMachine {
     fn drop(&mut self) {
-        unsafe { LLVMDisposeBuilder(self.inner) }
+        unsafe { LLVMDisposeTargetMachine(self.inner) }
     }
 }
 
-#[repr(C)]
-pub struct LLVMExecutionEngine {
-    pub(crate) inner: LLVMExecutionEngineRef,
-    _marker: PhantomData<llvm::execution_engine::LLVMOpaqueExecutionEngine>,
+/// A preset optimization pipeline, like the `-O` flags of `clang`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    /// Like `O2`, but avoids optimizations that increase code size.
+    Os,
+    /// Like `Os`, but reduces code size even further.
+    Oz,
+}
+
+impl OptLevel {
+    /// The pass pipeline of this preset, in the format of `opt -passes`.
+    pub fn pipeline(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "default<O0>",
+            OptLevel::O1 => "default<O1>",
+            OptLevel::O2 => "default<O2>",
+            OptLevel::O3 => "default<O3>",
+            OptLevel::Os => "default<Os>",
+            OptLevel::Oz => "default<Oz>",
+        }
+    }
+}
+
+/// A pass pipeline for the new pass manager, such as `default<O2>` or `instcombine,gvn`.
+///
+/// Run it before handing the module to `LLVMExecutionEngine::new_for_module` or
+/// `LLVMTargetMachine::emit_to_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassPipeline {
+    passes: String,
+    verify_each: bool,
 }
 
-impl LLVMExecutionEngine {
-    pub fn new_for_module(module: &LLVMModule) -> Result<LLVMExecutionEngine, CString> {
+impl PassPipeline {
+    /// A pipeline in the format of `opt -passes`.
+    pub fn new<S: Into<String>>(passes: S) -> PassPipeline {
+        PassPipeline {
+            passes: passes.into(),
+            verify_each: false,
+        }
+    }
+
+    pub fn preset(level: OptLevel) -> PassPipeline {
+        PassPipeline::new(level.pipeline())
+    }
+
+    pub fn passes(&self) -> &str {
+        &self.passes
+    }
+
+    /// Verifies the module after every pass, to find the pass that breaks it.
+    pub fn set_verify_each(&mut self, verify_each: bool) {
+        self.verify_each = verify_each;
+    }
+
+    /// Runs the pipeline on `module`. Passing the machine the module is compiled for lets
+    /// the optimizations take the target into account.
+    pub fn run(
+        &self,
+        module: &mut LLVMModule<'_>,
+        machine: Option<&LLVMTargetMachine>,
+    ) -> Result<(), String> {
+        let passes = CString::new(self.passes.as_str()).unwrap();
+        let machine = machine.map_or(ptr::null_mut(), |machine| machine.inner);
         unsafe {
-            let mut ee = mem::uninitialized();
-            let mut error = mem::uninitialized();
-            if LLVMCreateExecutionEngineForModule(&mut ee, module.inner, &mut error) != 0 {
-                return Err(CString::from_raw(error));
+            let options = LLVMCreatePassBuilderOptions();
+            LLVMPassBuilderOptionsSetVerifyEach(options, self.verify_each as llvm::prelude::LLVMBool);
+            let error = LLVMRunPasses(module.inner, passes.as_ptr(), machine, options);
+            LLVMDisposePassBuilderOptions(options);
+            if !error.is_null() {
+                return Err(take_error(error));
             }
-            Ok(LLVMExecutionEngine { inner: ee, _marker: PhantomData })
         }
+        Ok(())
     }
+}
 
-    pub unsafe fn get_function_as<S: Into<Vec<u8>>, F>(&self, name: S) -> F {
-        let cstring = CString::new(name).unwrap();
-        mem::transmute_copy(&LLVMGetFunctionAddress(self.inner, cstring.as_ptr()))
-    } 
+impl From<OptLevel> for PassPipeline {
+    fn from(level: OptLevel) -> Self {
+        PassPipeline::preset(level)
+    }
 }
 
-pub fn llvm_get_function_ty(ret: &LLVMType, params: &mut [LLVMType]) -> LLVMType {
-    unsafe {
-        LLVMType {
-            inner: LLVMFunctionType(
-                ret.inner,
-                params.as_mut_ptr() as *mut _,
-                params.len() as u32,
-                0,
-            ),
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LLVMBasicBlock<'ctx> {
+    pub(crate) inner: LLVMBasicBlockRef,
+    _marker: PhantomData<&'ctx LLVMContext>,
+}
+
+impl<'ctx> LLVMBasicBlock<'ctx> {
+    pub(crate) fn new(inner: LLVMBasicBlockRef) -> LLVMBasicBlock<'ctx> {
+        LLVMBasicBlock { inner, _marker: PhantomData }
+    }
+
+
+    pub fn name(&self) -> &CStr {
+        unsafe { CStr::from_ptr(LLVMGetBasicBlockName(self.inner)) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntPredicate {
+    Eq,
+    Ne,
+    Ugt,
+    Uge,
+    Ult,
+    Ule,
+    Sgt,
+    Sge,
+    Slt,
+    Sle,
+}
+
+impl From<IntPredicate> for LLVMIntPredicate {
+    fn from(predicate: IntPredicate) -> Self {
+        match predicate {
+            IntPredicate::Eq => LLVMIntPredicate::LLVMIntEQ,
+            IntPredicate::Ne => LLVMIntPredicate::LLVMIntNE,
+            IntPredicate::Ugt => LLVMIntPredicate::LLVMIntUGT,
+            IntPredicate::Uge => LLVMIntPredicate::LLVMIntUGE,
+            IntPredicate::Ult => LLVMIntPredicate::LLVMIntULT,
+            IntPredicate::Ule => LLVMIntPredicate::LLVMIntULE,
+            IntPredicate::Sgt => LLVMIntPredicate::LLVMIntSGT,
+            IntPredicate::Sge => LLVMIntPredicate::LLVMIntSGE,
+            IntPredicate::Slt => LLVMIntPredicate::LLVMIntSLT,
+            IntPredicate::Sle => LLVMIntPredicate::LLVMIntSLE,
         }
     }
 }
 
-impl Drop for LLVMExecutionEngine {
-    fn drop(&mut self) {
-        unsafe { LLVMDisposeExecutionEngine(self.inner) }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RealPredicate {
+    /// Always false.
+    False,
+    /// Ordered and equal.
+    Oeq,
+    Ogt,
+    Oge,
+    Olt,
+    Ole,
+    One,
+    /// Neither operand is NaN.
+    Ord,
+    /// Either operand is NaN.
+    Uno,
+    /// Unordered or equal.
+    Ueq,
+    Ugt,
+    Uge,
+    Ult,
+    Ule,
+    Une,
+    /// Always true.
+    True,
+}
+
+impl From<RealPredicate> for LLVMRealPredicate {
+    fn from(predicate: RealPredicate) -> Self {
+        match predicate {
+            RealPredicate::False => LLVMRealPredicate::LLVMRealPredicateFalse,
+            RealPredicate::Oeq => LLVMRealPredicate::LLVMRealOEQ,
+            RealPredicate::Ogt => LLVMRealPredicate::LLVMRealOGT,
+            RealPredicate::Oge => LLVMRealPredicate::LLVMRealOGE,
+            RealPredicate::Olt => LLVMRealPredicate::LLVMRealOLT,
+            RealPredicate::Ole => LLVMRealPredicate::LLVMRealOLE,
+            RealPredicate::One => LLVMRealPredicate::LLVMRealONE,
+            RealPredicate::Ord => LLVMRealPredicate::LLVMRealORD,
+            RealPredicate::Uno => LLVMRealPredicate::LLVMRealUNO,
+            RealPredicate::Ueq => LLVMRealPredicate::LLVMRealUEQ,
+            RealPredicate::Ugt => LLVMRealPredicate::LLVMRealUGT,
+            RealPredicate::Uge => LLVMRealPredicate::LLVMRealUGE,
+            RealPredicate::Ult => LLVMRealPredicate::LLVMRealULT,
+            RealPredicate::Ule => LLVMRealPredicate::LLVMRealULE,
+            RealPredicate::Une => LLVMRealPredicate::LLVMRealUNE,
+            RealPredicate::True => LLVMRealPredicate::LLVMRealPredicateTrue,
+        }
     }
 }
 
-pub fn llvm_init_jit() {
-    unsafe {
-        LLVMLinkInMCJIT();
-        LLVM_InitializeNativeTarget();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CastOp {
+    Trunc,
+    ZExt,
+    SExt,
+    FPToUI,
+    FPToSI,
+    UIToFP,
+    SIToFP,
+    FPTrunc,
+    FPExt,
+    PtrToInt,
+    IntToPtr,
+    BitCast,
+}
+
+impl From<CastOp> for LLVMOpcode {
+    fn from(op: CastOp) -> Self {
+        match op {
+            CastOp::Trunc => LLVMOpcode::LLVMTrunc,
+            CastOp::ZExt => LLVMOpcode::LLVMZExt,
+            CastOp::SExt => LLVMOpcode::LLVMSExt,
+            CastOp::FPToUI => LLVMOpcode::LLVMFPToUI,
+            CastOp::FPToSI => LLVMOpcode::LLVMFPToSI,
+            CastOp::UIToFP => LLVMOpcode::LLVMUIToFP,
+            CastOp::SIToFP => LLVMOpcode::LLVMSIToFP,
+            CastOp::FPTrunc => LLVMOpcode::LLVMFPTrunc,
+            CastOp::FPExt => LLVMOpcode::LLVMFPExt,
+            CastOp::PtrToInt => LLVMOpcode::LLVMPtrToInt,
+            CastOp::IntToPtr => LLVMOpcode::LLVMIntToPtr,
+            CastOp::BitCast => LLVMOpcode::LLVMBitCast,
+        }
     }
 }
 
-pub fn llvm_init_jit_with_printer() {
-    unsafe {
-        llvm_init_jit();
-        LLVM_InitializeNativeAsmPrinter();
+/// Defines builder methods for instructions with two operands.
+macro_rules! binary_ops {
+    ($($(#[$attr:meta])* $name:ident => $build:ident,)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name<S: Into<Vec<u8>>>(
+                &mut self,
+                name: S,
+                lhs: &LLVMValue<'ctx>,
+                rhs: &LLVMValue<'ctx>,
+            ) -> LLVMValue<'ctx> {
+                unsafe {
+                    let cstring = CString::new(name).unwrap();
+                    LLVMValue::new($build(self.inner, lhs.inner, rhs.inner, cstring.as_ptr()))
+                }
+            }
+        )*
+    };
+}
+
+/// Defines builder methods for instructions with one operand.
+macro_rules! unary_ops {
+    ($($(#[$attr:meta])* $name:ident => $build:ident,)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name<S: Into<Vec<u8>>>(&mut self, name: S, value: &LLVMValue<'ctx>) -> LLVMValue<'ctx> {
+                unsafe {
+                    let cstring = CString::new(name).unwrap();
+                    LLVMValue::new($build(self.inner, value.inner, cstring.as_ptr()))
+                }
+            }
+        )*
+    };
+}
+
+#[repr(C)]
+pub struct LLVMBuilder<'ctx> {
+    pub(crate) inner: LLVMBuilderRef,
+    _marker: PhantomData<&'ctx LLVMContext>,
+}
+
+impl<'ctx> LLVMBuilder<'ctx> {
+    pub fn new(context: &'ctx LLVMContext) -> LLVMBuilder<'ctx> {
+        LLVMBuilder {
+            inner: unsafe { LLVMCreateBuilderInContext(context.inner) },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn position_at_end(&mut self, block: &LLVMBasicBlock<'ctx>) {
+        unsafe {
+            LLVMPositionBuilderAtEnd(self.inner, block.inner);
+        }
+    }
+
+    /// Attaches `location` to every instruction built from now on. Pass `None` to stop.
+    pub fn set_debug_location(&mut self, location: Option<&LLVMMetadata<'ctx>>) {
+        let location = location.map_or(ptr::null_mut(), |location| location.inner);
+        unsafe { LLVMSetCurrentDebugLocation2(self.inner, location) }
+    }
+
+    binary_ops! {
+        build_add => LLVMBuildAdd,
+        build_sub => LLVMBuildSub,
+        build_mul => LLVMBuildMul,
+        build_udiv => LLVMBuildUDiv,
+        build_sdiv => LLVMBuildSDiv,
+        build_urem => LLVMBuildURem,
+        build_srem => LLVMBuildSRem,
+        build_and => LLVMBuildAnd,
+        build_or => LLVMBuildOr,
+        build_xor => LLVMBuildXor,
+        build_shl => LLVMBuildShl,
+        /// Shifts right, filling in zeros.
+        build_lshr => LLVMBuildLShr,
+        /// Shifts right, filling in the sign bit.
+        build_ashr => LLVMBuildAShr,
+        build_fadd => LLVMBuildFAdd,
+        build_fsub => LLVMBuildFSub,
+        build_fmul => LLVMBuildFMul,
+        build_fdiv => LLVMBuildFDiv,
+        build_frem => LLVMBuildFRem,
+    }
+
+    unary_ops! {
+        build_neg => LLVMBuildNeg,
+        /// Flips every bit.
+        build_not => LLVMBuildNot,
+        build_fneg => LLVMBuildFNeg,
+    }
+
+    pub fn build_icmp<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        predicate: IntPredicate,
+        lhs: &LLVMValue<'ctx>,
+        rhs: &LLVMValue<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildICmp(self.inner, predicate.into(), lhs.inner, rhs.inner, cstring.as_ptr()))
+        }
     }
-}
\ No newline at end of file
+
+    pub fn build_zext<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        value: &LLVMValue<'ctx>,
+        dest_ty: &LLVMType<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildZExt(self.inner, value.inner, dest_ty.inner, cstring.as_ptr()))
+        }
+    }
+
+    /// Builds an `inbounds` GEP into `ptr`, which points to values of type `ty`.
+    pub fn build_gep<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        ty: &LLVMType<'ctx>,
+        ptr: &LLVMValue<'ctx>,
+        indices: &[&LLVMValue<'ctx>],
+    ) -> LLVMValue<'ctx> {
+        let mut indices: Vec<LLVMValueRef> = indices.iter().map(|index| index.inner).collect();
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildInBoundsGEP2(
+                self.inner,
+                ty.inner,
+                ptr.inner,
+                indices.as_mut_ptr(),
+                indices.len() as libc::c_uint,
+                cstring.as_ptr(),
+            ))
+        }
+    }
+
+    /// Builds a GEP to the field `index` of the struct `ptr` points to.
+    pub fn build_struct_gep<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        struct_ty: &LLVMType<'ctx>,
+        ptr: &LLVMValue<'ctx>,
+        index: u32,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildStructGEP2(self.inner, struct_ty.inner, ptr.inner, index, cstring.as_ptr()))
+        }
+    }
+
+    /// Reserves space for a value of type `ty` on the stack of the current function.
+    pub fn build_alloca<S: Into<Vec<u8>>>(&mut self, name: S, ty: &LLVMType<'ctx>) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildAlloca(self.inner, ty.inner, cstring.as_ptr()))
+        }
+    }
+
+    /// Copies `size` bytes from `src` to `dest`, which must not overlap.
+    pub fn build_memcpy(
+        &mut self,
+        dest: &LLVMValue<'ctx>,
+        dest_align: u32,
+        src: &LLVMValue<'ctx>,
+        src_align: u32,
+        size: &LLVMValue<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildMemCpy(self.inner, dest.inner, dest_align, src.inner, src_align, size.inner))
+        }
+    }
+
+    /// Creates a global holding `string` with a null byte at the end, and returns an `i8*` to it.
+    pub fn build_global_string_ptr<S: Into<Vec<u8>>>(&mut self, name: S, string: &str) -> LLVMValue<'ctx> {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            let string = CString::new(string).unwrap();
+            LLVMValue::new(LLVMBuildGlobalStringPtr(self.inner, string.as_ptr(), name.as_ptr()))
+        }
+    }
+
+    pub fn build_load<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        ty: &LLVMType<'ctx>,
+        ptr: &LLVMValue<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildLoad2(self.inner, ty.inner, ptr.inner, cstring.as_ptr()))
+        }
+    }
+
+    pub fn build_store(&mut self, value: &LLVMValue<'ctx>, ptr: &LLVMValue<'ctx>) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildStore(self.inner, value.inner, ptr.inner))
+        }
+    }
+
+    pub fn build_fcmp<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        predicate: RealPredicate,
+        lhs: &LLVMValue<'ctx>,
+        rhs: &LLVMValue<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildFCmp(self.inner, predicate.into(), lhs.inner, rhs.inner, cstring.as_ptr()))
+        }
+    }
+
+    /// Builds `cond ? then_value : else_value` without branching.
+    pub fn build_select<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        cond: &LLVMValue<'ctx>,
+        then_value: &LLVMValue<'ctx>,
+        else_value: &LLVMValue<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildSelect(self.inner, cond.inner, then_value.inner, else_value.inner, cstring.as_ptr()))
+        }
+    }
+
+    pub fn build_cast<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        op: CastOp,
+        value: &LLVMValue<'ctx>,
+        dest_ty: &LLVMType<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildCast(self.inner, op.into(), value.inner, dest_ty.inner, cstring.as_ptr()))
+        }
+    }
+
+    pub fn build_br(&mut self, dest: &LLVMBasicBlock<'ctx>) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildBr(self.inner, dest.inner))
+        }
+    }
+
+    pub fn build_cond_br(
+        &mut self,
+        cond: &LLVMValue<'ctx>,
+        then_block: &LLVMBasicBlock<'ctx>,
+        else_block: &LLVMBasicBlock<'ctx>,
+    ) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildCondBr(self.inner, cond.inner, then_block.inner, else_block.inner))
+        }
+    }
+
+    /// Jumps to the block of the first case equal to `value`, or `default` if there is none.
+    /// More cases can be added with `LLVMValue::add_case`.
+    pub fn build_switch(
+        &mut self,
+        value: &LLVMValue<'ctx>,
+        default: &LLVMBasicBlock<'ctx>,
+        cases: &[(&LLVMValue<'ctx>, &LLVMBasicBlock<'ctx>)],
+    ) -> LLVMValue<'ctx> {
+        let switch = unsafe {
+            LLVMValue::new(LLVMBuildSwitch(self.inner, value.inner, default.inner, cases.len() as libc::c_uint))
+        };
+        for (on_value, dest) in cases {
+            switch.add_case(on_value, dest);
+        }
+        switch
+    }
+
+    /// Builds an empty phi node. Use `LLVMValue::add_incoming` to add its edges.
+    pub fn build_phi<S: Into<Vec<u8>>>(&mut self, name: S, ty: &LLVMType<'ctx>) -> LLVMValue<'ctx> {
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildPhi(self.inner, ty.inner, cstring.as_ptr()))
+        }
+    }
+
+    pub fn build_call<S: Into<Vec<u8>>>(
+        &mut self,
+        name: S,
+        function_ty: &LLVMType<'ctx>,
+        function: &LLVMValue<'ctx>,
+        args: &[&LLVMValue<'ctx>],
+    ) -> LLVMValue<'ctx> {
+        let mut args: Vec<LLVMValueRef> = args.iter().map(|arg| arg.inner).collect();
+        unsafe {
+            let cstring = CString::new(name).unwrap();
+            LLVMValue::new(LLVMBuildCall2(
+                self.inner,
+                function_ty.inner,
+                function.inner,
+                args.as_mut_ptr(),
+                args.len() as libc::c_uint,
+                cstring.as_ptr(),
+            ))
+        }
+    }
+
+    pub fn build_ret(&mut self, value: &LLVMValue<'ctx>) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildRet(self.inner, value.inner))
+        }
+    }
+
+    pub fn build_ret_void(&mut self) -> LLVMValue<'ctx> {
+        unsafe {
+            LLVMValue::new(LLVMBuildRetVoid(self.inner))
+        }
+    }
+}
+
+impl Drop for LLVMBuilder<'_> {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeBuilder(self.inner) }
+    }
+}
+
+/// Metadata such as debug info, which is owned by the context.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LLVMMetadata<'ctx> {
+    pub(crate) inner: LLVMMetadataRef,
+    _marker: PhantomData<&'ctx LLVMContext>,
+}
+
+impl<'ctx> LLVMMetadata<'ctx> {
+    pub(crate) fn new(inner: LLVMMetadataRef) -> LLVMMetadata<'ctx> {
+        LLVMMetadata { inner, _marker: PhantomData }
+    }
+}
+
+/// A source file in the debug info. Spans into `source` are turned into lines and columns.
+pub struct LLVMDIFile<'src, 'ctx> {
+    pub metadata: LLVMMetadata<'ctx>,
+    pub source: &'src SourceFile,
+}
+
+impl LLVMDIFile<'_, '_> {
+    /// The 1-based line and column of the start of `span`. Columns count characters.
+    ///
+    /// # Panics
+    /// Panics if `span` carries the id of another file.
+    pub fn line_col(&self, span: Span) -> (u32, u32) {
+        assert!(
+            self.source.is_file_of(span),
+            "The span {}..{} doesn't point into `{}`.",
+            span.begin,
+            span.end,
+            self.source.name()
+        );
+        let location = self.source.location(span.begin);
+        (location.line as u32, location.column as u32)
+    }
+}
+
+/// How a DWARF basic type is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwarfEncoding {
+    Boolean,
+    Float,
+    Signed,
+    Unsigned,
+}
+
+impl DwarfEncoding {
+    fn value(self) -> libc::c_uint {
+        // DW_ATE_*
+        match self {
+            DwarfEncoding::Boolean => 0x02,
+            DwarfEncoding::Float => 0x04,
+            DwarfEncoding::Signed => 0x05,
+            DwarfEncoding::Unsigned => 0x07,
+        }
+    }
+}
+
+/// Builds the DWARF debug info of a module, so that the compiled code can be stepped through
+/// in a debugger with the original source lines.
+///
+/// Everything is created in the module the builder was created for, which stays borrowed until
+/// the builder is gone and can be reached through `module` in the meantime. The debug info is
+/// only complete once `finalize` was called or the builder was dropped.
+pub struct LLVMDIBuilder<'m, 'ctx> {
+    pub(crate) inner: LLVMDIBuilderRef,
+    ctx: LLVMContextRef,
+    module: &'m mut LLVMModule<'ctx>,
+}
+
+impl<'m, 'ctx> LLVMDIBuilder<'m, 'ctx> {
+    pub fn new(module: &'m mut LLVMModule<'ctx>) -> LLVMDIBuilder<'m, 'ctx> {
+        let key = "Debug Info Version";
+        unsafe {
+            let ctx = LLVMGetModuleContext(module.inner);
+            // Without this flag, LLVM strips the debug info.
+            let version = LLVMConstInt(LLVMInt32TypeInContext(ctx), LLVMDebugMetadataVersion() as u64, 0);
+            LLVMAddModuleFlag(
+                module.inner,
+                LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                key.as_ptr() as *const libc::c_char,
+                key.len(),
+                LLVMValueAsMetadata(version),
+            );
+            LLVMDIBuilder {
+                inner: LLVMCreateDIBuilder(module.inner),
+                ctx,
+                module,
+            }
+        }
+    }
+
+    /// The module the debug info is created in.
+    pub fn module(&mut self) -> &mut LLVMModule<'ctx> {
+        self.module
+    }
+
+    /// Creates a file for `source`, which spans passed with the file point into.
+    pub fn create_file<'src>(&self, source: &'src SourceFile, directory: &str) -> LLVMDIFile<'src, 'ctx> {
+        let filename = source.name();
+        let metadata = unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateFile(
+                self.inner,
+                filename.as_ptr() as *const libc::c_char,
+                filename.len(),
+                directory.as_ptr() as *const libc::c_char,
+                directory.len(),
+            ))
+        };
+        LLVMDIFile { metadata, source }
+    }
+
+    /// Creates the compile unit for `file`. A module has exactly one, and it is the outermost scope.
+    pub fn create_compile_unit(&self, file: &LLVMDIFile<'_, 'ctx>, producer: &str, optimized: bool) -> LLVMMetadata<'ctx> {
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateCompileUnit(
+                self.inner,
+                // DWARF has no language code for Tuplan.
+                LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+                file.metadata.inner,
+                producer.as_ptr() as *const libc::c_char,
+                producer.len(),
+                optimized as llvm::prelude::LLVMBool,
+                ptr::null(),
+                0,
+                0,
+                ptr::null(),
+                0,
+                LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+                0,
+                0,
+                0,
+                ptr::null(),
+                0,
+                ptr::null(),
+                0,
+            ))
+        }
+    }
+
+    pub fn create_basic_type(&self, name: &str, size_in_bits: u64, encoding: DwarfEncoding) -> LLVMMetadata<'ctx> {
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateBasicType(
+                self.inner,
+                name.as_ptr() as *const libc::c_char,
+                name.len(),
+                size_in_bits,
+                encoding.value(),
+                LLVMDIFlagZero,
+            ))
+        }
+    }
+
+    /// The type of a function. `ret` is `None` for functions that don't return anything.
+    pub fn create_subroutine_type(
+        &self,
+        file: &LLVMDIFile<'_, 'ctx>,
+        ret: Option<&LLVMMetadata<'ctx>>,
+        params: &[LLVMMetadata<'ctx>],
+    ) -> LLVMMetadata<'ctx> {
+        let mut types: Vec<LLVMMetadataRef> = std::iter::once(ret.map_or(ptr::null_mut(), |ret| ret.inner))
+            .chain(params.iter().map(|param| param.inner))
+            .collect();
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateSubroutineType(
+                self.inner,
+                file.metadata.inner,
+                types.as_mut_ptr(),
+                types.len() as libc::c_uint,
+                LLVMDIFlagZero,
+            ))
+        }
+    }
+
+    /// Creates the subprogram of a function defined at `span`. Attach it with `LLVMValue::set_subprogram`.
+    pub fn create_function(
+        &self,
+        scope: &LLVMMetadata<'ctx>,
+        name: &str,
+        file: &LLVMDIFile<'_, 'ctx>,
+        span: Span,
+        ty: &LLVMMetadata<'ctx>,
+        optimized: bool,
+    ) -> LLVMMetadata<'ctx> {
+        let (line, _) = file.line_col(span);
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateFunction(
+                self.inner,
+                scope.inner,
+                name.as_ptr() as *const libc::c_char,
+                name.len(),
+                name.as_ptr() as *const libc::c_char,
+                name.len(),
+                file.metadata.inner,
+                line,
+                ty.inner,
+                0,
+                1,
+                line,
+                LLVMDIFlagZero,
+                optimized as llvm::prelude::LLVMBool,
+            ))
+        }
+    }
+
+    /// Creates a scope for a block that starts at `span`.
+    pub fn create_lexical_block(&self, scope: &LLVMMetadata<'ctx>, file: &LLVMDIFile<'_, 'ctx>, span: Span) -> LLVMMetadata<'ctx> {
+        let (line, column) = file.line_col(span);
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateLexicalBlock(self.inner, scope.inner, file.metadata.inner, line, column))
+        }
+    }
+
+    /// Creates a local variable declared at `span`. Attach it to its storage with `insert_declare_at_end`.
+    pub fn create_auto_variable(
+        &self,
+        scope: &LLVMMetadata<'ctx>,
+        name: &str,
+        file: &LLVMDIFile<'_, 'ctx>,
+        span: Span,
+        ty: &LLVMMetadata<'ctx>,
+    ) -> LLVMMetadata<'ctx> {
+        let (line, _) = file.line_col(span);
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateAutoVariable(
+                self.inner,
+                scope.inner,
+                name.as_ptr() as *const libc::c_char,
+                name.len(),
+                file.metadata.inner,
+                line,
+                ty.inner,
+                1,
+                LLVMDIFlagZero,
+                0,
+            ))
+        }
+    }
+
+    /// Creates the parameter `arg_no`, counting from 1, of the function `scope`.
+    pub fn create_parameter_variable(
+        &self,
+        scope: &LLVMMetadata<'ctx>,
+        name: &str,
+        arg_no: u32,
+        file: &LLVMDIFile<'_, 'ctx>,
+        span: Span,
+        ty: &LLVMMetadata<'ctx>,
+    ) -> LLVMMetadata<'ctx> {
+        let (line, _) = file.line_col(span);
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateParameterVariable(
+                self.inner,
+                scope.inner,
+                name.as_ptr() as *const libc::c_char,
+                name.len(),
+                arg_no,
+                file.metadata.inner,
+                line,
+                ty.inner,
+                1,
+                LLVMDIFlagZero,
+            ))
+        }
+    }
+
+    /// The location of `span` in `scope`, for `LLVMBuilder::set_debug_location`.
+    pub fn location(&self, scope: &LLVMMetadata<'ctx>, file: &LLVMDIFile<'_, 'ctx>, span: Span) -> LLVMMetadata<'ctx> {
+        let (line, column) = file.line_col(span);
+        unsafe {
+            LLVMMetadata::new(LLVMDIBuilderCreateDebugLocation(self.ctx, line, column, scope.inner, ptr::null_mut()))
+        }
+    }
+
+    /// Declares that `variable` lives in `storage`, which is usually an `alloca`.
+    pub fn insert_declare_at_end(
+        &self,
+        storage: &LLVMValue<'ctx>,
+        variable: &LLVMMetadata<'ctx>,
+        location: &LLVMMetadata<'ctx>,
+        block: &LLVMBasicBlock<'ctx>,
+    ) {
+        unsafe {
+            let expr = LLVMDIBuilderCreateExpression(self.inner, ptr::null_mut(), 0);
+            LLVMDIBuilderInsertDeclareAtEnd(self.inner, storage.inner, variable.inner, expr, location.inner, block.inner);
+        }
+    }
+
+    /// Completes the debug info. The module can be used again once the builder is gone.
+    pub fn finalize(self) {
+        // Dropping the builder finalizes it.
+    }
+}
+
+impl Drop for LLVMDIBuilder<'_, '_> {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDIBuilderFinalize(self.inner);
+            LLVMDisposeDIBuilder(self.inner);
+        }
+    }
+}
+
+/// An execution engine and the module it was created for. LLVM disposes the module together
+/// with the engine, so the module is only borrowed from it afterwards.
+#[repr(C)]
+pub struct LLVMExecutionEngine<'ctx> {
+    pub(crate) inner: LLVMExecutionEngineRef,
+    module: ManuallyDrop<LLVMModule<'ctx>>,
+}
+
+impl<'ctx> LLVMExecutionEngine<'ctx> {
+    /// Handles to the values and blocks of `module` stay valid until the engine is dropped.
+    pub fn new_for_module(module: LLVMModule<'ctx>) -> Result<LLVMExecutionEngine<'ctx>, String> {
+        // The module belongs to LLVM from here on, even if creating the engine fails.
+        let module = ManuallyDrop::new(module);
+        unsafe {
+            let mut ee = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            if LLVMCreateExecutionEngineForModule(&mut ee, module.inner, &mut error) != 0 {
+                return Err(take_message(error));
+            }
+            Ok(LLVMExecutionEngine { inner: ee, module })
+        }
+    }
+
+    pub fn module(&self) -> &LLVMModule<'ctx> {
+        &self.module
+    }
+
+    /// Transmutes the address of `name` to `F` without checking anything. The address is 0 if
+    /// there is no such function. Prefer `get_function`.
+    pub unsafe fn get_function_as<S: Into<Vec<u8>>, F>(&self, name: S) -> F {
+        let cstring = CString::new(name).unwrap();
+        mem::transmute_copy(&LLVMGetFunctionAddress(self.inner, cstring.as_ptr()))
+    }
+
+    /// Compiles `name` and returns it as an `F`, such as `extern "C" fn(i64) -> i64`.
+    /// Fails if the module has no function called `name` or if its type doesn't match `F`.
+    pub fn get_function<F: JitSignature>(&self, name: &str) -> Result<JitFunction<'_, F>, String> {
+        let function = self.module.get_function(name)
+            .ok_or_else(|| format!("There is no function named `{name}`."))?;
+        // The context is owned by someone else, so this must not be dropped.
+        let ctx = ManuallyDrop::new(LLVMContext {
+            inner: unsafe { LLVMGetModuleContext(self.module.inner) },
+            _marker: PhantomData,
+        });
+        let expected = F::llvm_type(&ctx);
+        let found = function.global_value_type();
+        if found != expected {
+            return Err(format!(
+                "`{name}` has type `{}`, not `{}`.",
+                found.print_to_string(),
+                expected.print_to_string()
+            ));
+        }
+
+        let addr = unsafe { self.get_function_as::<_, u64>(name) };
+        if addr == 0 {
+            return Err(format!("`{name}` could not be compiled."));
+        }
+        Ok(JitFunction {
+            // SAFETY: `F` is a function pointer with the same type as the function at `addr`.
+            func: unsafe { mem::transmute_copy::<usize, F>(&(addr as usize)) },
+            _marker: PhantomData,
+        })
+    }
+
+    /// Makes the engine resolve `global` to `addr` instead of looking it up in the process.
+    /// Rust executables don't export their symbols, so this is how JIT compiled code calls them.
+    ///
+    /// # Safety
+    /// `addr` has to point to a function or global with the type of `global`.
+    pub unsafe fn add_global_mapping(&self, global: &LLVMValue<'ctx>, addr: *const libc::c_void) {
+        LLVMAddGlobalMapping(self.inner, global.inner, addr as *mut _);
+    }
+}
+
+pub fn llvm_get_function_ty<'ctx>(ret: &LLVMType<'ctx>, params: &[LLVMType<'ctx>]) -> LLVMType<'ctx> {
+    let mut params: Vec<LLVMTypeRef> = params.iter().map(|param| param.inner).collect();
+    unsafe {
+        LLVMType::new(LLVMFunctionType(
+            ret.inner,
+            params.as_mut_ptr(),
+            params.len() as u32,
+            0,
+        ))
+    }
+}
+
+impl Drop for LLVMExecutionEngine<'_> {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeExecutionEngine(self.inner) }
+    }
+}
+
+/// A Rust type that is passed to and returned from `extern "C"` functions as the LLVM type `llvm_type`.
+///
+/// `bool` isn't one: an `i1` is passed in a whole register whose upper bits LLVM doesn't have to
+/// clear, and a `bool` that isn't 0 or 1 is undefined behavior. Pass an `i8` as `u8` instead and
+/// test the lowest bit, like `tuplan-rt` does. `()` isn't one either, since a `void` parameter or
+/// a pointer to `void` isn't valid LLVM IR. It can only be returned, see `JitReturnType`.
+///
+/// # Safety
+/// The type has to have the same size and calling convention as `llvm_type`.
+pub unsafe trait JitType {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_>;
+}
+
+/// A Rust type that is returned from `extern "C"` functions as the LLVM type `llvm_type`. These
+/// are the `JitType`s and `()`, which is returned as `void`.
+///
+/// # Safety
+/// The type has to have the same size and calling convention as `llvm_type`.
+pub unsafe trait JitReturnType {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_>;
+}
+
+unsafe impl<T: JitType> JitReturnType for T {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        T::llvm_type(ctx)
+    }
+}
+
+unsafe impl JitReturnType for () {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        ctx.void_t()
+    }
+}
+
+macro_rules! jit_types {
+    ($($ty:ty => $llvm_type:ident),* $(,)?) => {
+        $(
+            unsafe impl JitType for $ty {
+                fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+                    ctx.$llvm_type()
+                }
+            }
+        )*
+    };
+}
+
+jit_types! {
+    i8 => i8_t,
+    u8 => i8_t,
+    i32 => i32_t,
+    u32 => i32_t,
+    i64 => i64_t,
+    u64 => i64_t,
+    f32 => float_t,
+    f64 => f64_t,
+}
+
+unsafe impl JitType for i16 {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        ctx.int_t(16)
+    }
+}
+
+unsafe impl JitType for u16 {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        ctx.int_t(16)
+    }
+}
+
+unsafe impl JitType for isize {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        ctx.int_t(usize::BITS)
+    }
+}
+
+unsafe impl JitType for usize {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        ctx.int_t(usize::BITS)
+    }
+}
+
+unsafe impl<T: JitType> JitType for *const T {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        T::llvm_type(ctx).ptr_t()
+    }
+}
+
+unsafe impl<T: JitType> JitType for *mut T {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+        T::llvm_type(ctx).ptr_t()
+    }
+}
+
+/// An `extern "C" fn` type that JIT compiled functions can be looked up as.
+///
+/// # Safety
+/// The type has to be a function pointer with the signature `llvm_type`.
+pub unsafe trait JitSignature: Copy {
+    fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_>;
+}
+
+/// A function compiled by an `LLVMExecutionEngine`, which can't outlive the engine.
+#[derive(Clone, Copy)]
+pub struct JitFunction<'ee, F> {
+    func: F,
+    _marker: PhantomData<&'ee ()>,
+}
+
+impl<F: JitSignature> JitFunction<'_, F> {
+    /// The function pointer, for storing it without the borrow of the engine.
+    ///
+    /// # Safety
+    /// The pointer is only valid as long as the engine or JIT that compiled it is alive, and
+    /// calling it is as unsafe as `call`.
+    pub unsafe fn as_raw(&self) -> F {
+        self.func
+    }
+}
+
+macro_rules! jit_signatures {
+    ($($arg:ident),*) => {
+        unsafe impl<R: JitReturnType, $($arg: JitType),*> JitSignature for extern "C" fn($($arg),*) -> R {
+            fn llvm_type(ctx: &LLVMContext) -> LLVMType<'_> {
+                llvm_get_function_ty(&R::llvm_type(ctx), &[$($arg::llvm_type(ctx)),*])
+            }
+        }
+
+        impl<R: JitReturnType, $($arg: JitType),*> JitFunction<'_, extern "C" fn($($arg),*) -> R> {
+            /// Calls the function.
+            ///
+            /// # Safety
+            /// The types are checked, but the compiled code can still do anything.
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub unsafe fn call(&self, $($arg: $arg),*) -> R {
+                (self.func)($($arg),*)
+            }
+        }
+    };
+}
+
+jit_signatures!();
+jit_signatures!(A);
+jit_signatures!(A, B);
+jit_signatures!(A, B, C);
+jit_signatures!(A, B, C, D);
+jit_signatures!(A, B, C, D, E);
+jit_signatures!(A, B, C, D, E, G);
+
+/// An ORC `LLJIT` for the host. Unlike `LLVMExecutionEngine`, modules are added one at a
+/// time and a function is only compiled the first time it is looked up.
+///
+/// Modules have to be created in the context of the JIT, which is returned by `context`.
+pub struct LLVMOrcJit {
+    inner: LLVMOrcLLJITRef,
+    ts_ctx: LLVMOrcThreadSafeContextRef,
+    // The context is owned by `ts_ctx`, so this must not be dropped.
+    ctx: ManuallyDrop<LLVMContext>,
+    /// The types of the functions defined by the added modules.
+    functions: RefCell<HashMap<String, LLVMTypeRef>>,
+}
+
+impl LLVMOrcJit {
+    pub fn new() -> Result<LLVMOrcJit, String> {
+        llvm_init_native_target();
+        unsafe {
+            let mut jit = ptr::null_mut();
+            // Without a builder, the JIT compiles for the host.
+            let error = LLVMOrcCreateLLJIT(&mut jit, ptr::null_mut());
+            if !error.is_null() {
+                return Err(take_error(error));
+            }
+            let ts_ctx = LLVMOrcCreateNewThreadSafeContext();
+            Ok(LLVMOrcJit {
+                inner: jit,
+                ts_ctx,
+                ctx: ManuallyDrop::new(LLVMContext {
+                    inner: LLVMOrcThreadSafeContextGetContext(ts_ctx),
+                    _marker: PhantomData,
+                }),
+                functions: RefCell::new(HashMap::new()),
+            })
+        }
+    }
+
+    /// The context modules added to this JIT have to be created in.
+    pub fn context(&self) -> &LLVMContext {
+        &self.ctx
+    }
+
+    /// Hands `module` to the JIT. Its functions can be looked up from now on, and can call the
+    /// functions of every module added before or after it. Handles to the values and blocks of
+    /// `module` must not be used anymore.
+    pub fn add_module(&self, module: LLVMModule<'_>) -> Result<(), String> {
+        unsafe {
+            if LLVMGetModuleContext(module.inner) != self.ctx.inner {
+                return Err(format!(
+                    "Module `{}` wasn't created in the context of the JIT.",
+                    module.ident().to_string_lossy()
+                ));
+            }
+
+            let mut functions = Vec::new();
+            let mut function = LLVMGetFirstFunction(module.inner);
+            while !function.is_null() {
+                if LLVMIsDeclaration(function) == 0 {
+                    let value = LLVMValue::new(function);
+                    functions.push((value.name().to_string_lossy().into_owned(), value.global_value_type().inner));
+                }
+                function = LLVMGetNextFunction(function);
+            }
+
+            // The JIT takes ownership of the module, even if adding it fails.
+            let module = ManuallyDrop::new(module);
+            let ts_module = LLVMOrcCreateNewThreadSafeModule(module.inner, self.ts_ctx);
+            let dylib = LLVMOrcLLJITGetMainJITDylib(self.inner);
+            let error = LLVMOrcLLJITAddLLVMIRModule(self.inner, dylib, ts_module);
+            if !error.is_null() {
+                return Err(take_error(error));
+            }
+            self.functions.borrow_mut().extend(functions);
+        }
+        Ok(())
+    }
+
+    /// Makes JIT compiled code resolve each name to the host function at its address.
+    /// Rust executables don't export their symbols, so this is how JIT compiled code calls into
+    /// a runtime.
+    ///
+    /// # Safety
+    /// Every address has to point to a function with the type the compiled code declares it with.
+    pub unsafe fn define_host_functions(&self, functions: &[(&str, *const libc::c_void)]) -> Result<(), String> {
+        let flags = LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsExported as u8
+            | LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsCallable as u8;
+        let mut symbols: Vec<LLVMOrcCSymbolMapPair> = functions
+            .iter()
+            .map(|(name, addr)| {
+                let name = CString::new(*name).unwrap();
+                LLVMOrcCSymbolMapPair {
+                    Name: LLVMOrcLLJITMangleAndIntern(self.inner, name.as_ptr()),
+                    Sym: LLVMJITEvaluatedSymbol {
+                        Address: *addr as u64,
+                        Flags: LLVMJITSymbolFlags { GenericFlags: flags, TargetFlags: 0 },
+                    },
+                }
+            })
+            .collect();
+        let unit = LLVMOrcAbsoluteSymbols(symbols.as_mut_ptr(), symbols.len());
+        let error = LLVMOrcJITDylibDefine(LLVMOrcLLJITGetMainJITDylib(self.inner), unit);
+        if !error.is_null() {
+            // The unit is only consumed if it was defined.
+            LLVMOrcDisposeMaterializationUnit(unit);
+            return Err(take_error(error));
+        }
+        Ok(())
+    }
+
+    /// Makes JIT compiled code resolve symbols that aren't defined otherwise to the symbols
+    /// exported by the process, such as the functions of the C library.
+    pub fn search_process_symbols(&self) -> Result<(), String> {
+        unsafe {
+            let mut generator = ptr::null_mut();
+            let error = LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                &mut generator,
+                LLVMOrcLLJITGetGlobalPrefix(self.inner),
+                None,
+                ptr::null_mut(),
+            );
+            if !error.is_null() {
+                return Err(take_error(error));
+            }
+            LLVMOrcJITDylibAddGenerator(LLVMOrcLLJITGetMainJITDylib(self.inner), generator);
+        }
+        Ok(())
+    }
+
+    /// Compiles `name`, if it wasn't already, and returns its address.
+    pub fn lookup(&self, name: &str) -> Result<u64, String> {
+        let cstring = CString::new(name).unwrap();
+        let mut addr = 0;
+        unsafe {
+            let error = LLVMOrcLLJITLookup(self.inner, &mut addr, cstring.as_ptr());
+            if !error.is_null() {
+                return Err(take_error(error));
+            }
+        }
+        Ok(addr)
+    }
+
+    /// Like `LLVMExecutionEngine::get_function`, for the functions of every added module.
+    pub fn get_function<F: JitSignature>(&self, name: &str) -> Result<JitFunction<'_, F>, String> {
+        let found = self.functions.borrow().get(name).copied()
+            .ok_or_else(|| format!("There is no function named `{name}`."))?;
+        let found = LLVMType::new(found);
+        let expected = F::llvm_type(&self.ctx);
+        if found != expected {
+            return Err(format!(
+                "`{name}` has type `{}`, not `{}`.",
+                found.print_to_string(),
+                expected.print_to_string()
+            ));
+        }
+
+        let addr = self.lookup(name)?;
+        Ok(JitFunction {
+            // SAFETY: `F` is a function pointer with the same type as the function at `addr`.
+            func: unsafe { mem::transmute_copy::<usize, F>(&(addr as usize)) },
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl Drop for LLVMOrcJit {
+    fn drop(&mut self) {
+        unsafe {
+            let error = LLVMOrcDisposeLLJIT(self.inner);
+            if !error.is_null() {
+                take_error(error);
+            }
+            LLVMOrcDisposeThreadSafeContext(self.ts_ctx);
+        }
+    }
+}
+
+pub fn llvm_init_jit() {
+    unsafe {
+        LLVMLinkInMCJIT();
+        LLVM_InitializeNativeTarget();
+    }
+}
+
+pub fn llvm_init_native_target() {
+    unsafe {
+        LLVM_InitializeNativeTarget();
+        LLVM_InitializeNativeAsmPrinter();
+    }
+}
+
+pub fn llvm_init_jit_with_printer() {
+    unsafe {
+        llvm_init_jit();
+        LLVM_InitializeNativeAsmPrinter();
+    }
+}
+
+/// Initializes every target LLVM was built with, for cross compiling with `LLVMTargetMachine`.
+pub fn llvm_init_all_targets() {
+    unsafe {
+        LLVM_InitializeAllTargetInfos();
+        LLVM_InitializeAllTargets();
+        LLVM_InitializeAllTargetMCs();
+        LLVM_InitializeAllAsmPrinters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// `i64 square(i64 x) { return x * x; }`
+    fn square(ctx: &LLVMContext) -> LLVMModule<'_> {
+        let mut module = ctx.create_module("square");
+        let i64_t = ctx.i64_t();
+        let function = module.add_function("square", llvm_get_function_ty(&i64_t, &[i64_t]));
+        let x = function.get_param(0);
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&function.append_basic_block("entry"));
+        let y = builder.build_mul("y", &x, &x);
+        builder.build_ret(&y);
+        module
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tuplan-llvm-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn debug_info_encodes_basic_types() {
+        let ctx = LLVMContext::new();
+        let mut module = ctx.create_module("types");
+        let mut di = LLVMDIBuilder::new(&mut module);
+        let source = SourceFile::new("types.tpl", "fn f(a) {}");
+        let file = di.create_file(&source, ".");
+        let unit = di.create_compile_unit(&file, "tuplan", false);
+        let types = [
+            di.create_basic_type("u64", 64, DwarfEncoding::Unsigned),
+            di.create_basic_type("i64", 64, DwarfEncoding::Signed),
+            di.create_basic_type("f64", 64, DwarfEncoding::Float),
+        ];
+        let bool_di = di.create_basic_type("bool", 8, DwarfEncoding::Boolean);
+
+        let i64_t = ctx.i64_t();
+        let function_ty = llvm_get_function_ty(&i64_t, &[i64_t, i64_t, i64_t]);
+        let function = di.module().add_function("f", function_ty);
+        let ty = di.create_subroutine_type(&file, Some(&bool_di), &types);
+        let subprogram = di.create_function(&unit, "f", &file, Span::new(0, 2), &ty, false);
+        function.set_subprogram(&subprogram);
+        let entry = function.append_basic_block("entry");
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&entry);
+        builder.build_ret(&function.get_param(0));
+        di.finalize();
+
+        module.verify().unwrap();
+        let ir = module.to_ir_string();
+        for (name, size, encoding) in [
+            ("u64", 64, "DW_ATE_unsigned"),
+            ("i64", 64, "DW_ATE_signed"),
+            ("f64", 64, "DW_ATE_float"),
+            ("bool", 8, "DW_ATE_boolean"),
+        ] {
+            let expected = format!("!DIBasicType(name: \"{name}\", size: {size}, encoding: {encoding})");
+            assert!(ir.contains(&expected), "missing `{expected}` in:\n{ir}");
+        }
+    }
+
+    #[test]
+    fn verify_reports_blocks_without_terminators() {
+        let ctx = LLVMContext::new();
+        let mut module = ctx.create_module("verify");
+        let function = module.add_function("f", llvm_get_function_ty(&ctx.void_t(), &[]));
+        let entry = function.append_basic_block("entry");
+        let message = module.verify().unwrap_err();
+        assert!(message.contains("does not have terminator"), "{message}");
+
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&entry);
+        builder.build_ret_void();
+        assert_eq!(module.verify(), Ok(()));
+    }
+
+    #[test]
+    fn bitcode_round_trips() {
+        let ctx = LLVMContext::new();
+        let path = temp_path("square.bc");
+        square(&ctx).write_bitcode_to_file(&path).unwrap();
+        let module = LLVMModule::read_bitcode_from_file(&path, &ctx);
+        std::fs::remove_file(&path).unwrap();
+        let module = module.unwrap();
+        assert_eq!(module.verify(), Ok(()));
+        assert!(module.to_ir_string().contains("%y = mul i64 %0, %0"));
+    }
+
+    #[test]
+    fn rejects_files_without_bitcode() {
+        let ctx = LLVMContext::new();
+        let path = temp_path("text.bc");
+        std::fs::write(&path, "not bitcode").unwrap();
+        let module = LLVMModule::read_bitcode_from_file(&path, &ctx);
+        std::fs::remove_file(&path).unwrap();
+        assert!(module.is_err());
+        assert!(LLVMModule::read_bitcode_from_file(temp_path("missing.bc"), &ctx).is_err());
+    }
+
+    #[test]
+    fn emits_object_and_assembly_files() {
+        llvm_init_native_target();
+        let ctx = LLVMContext::new();
+        let mut module = square(&ctx);
+        let machine = LLVMTargetMachine::host_generic(CodeGenOptLevel::Default).unwrap();
+        assert_eq!(machine.triple(), llvm_default_target_triple());
+        machine.configure_module(&mut module);
+
+        let path = temp_path("square.s");
+        machine.emit_to_file(&module, &path, FileType::Assembly).unwrap();
+        let assembly = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(assembly.unwrap().contains("square"));
+
+        let path = temp_path("square.o");
+        module.write_object_file(&path).unwrap();
+        let len = std::fs::metadata(&path).map(|metadata| metadata.len());
+        std::fs::remove_file(&path).unwrap();
+        assert!(len.unwrap() > 0);
+    }
+
+    #[test]
+    fn runs_pass_pipelines() {
+        let ctx = LLVMContext::new();
+        let mut module = ctx.create_module("passes");
+        let i64_t = ctx.i64_t();
+        let function = module.add_function("f", llvm_get_function_ty(&i64_t, &[i64_t]));
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&function.append_basic_block("entry"));
+        let slot = builder.build_alloca("slot", &i64_t);
+        builder.build_store(&function.get_param(0), &slot);
+        let x = builder.build_load("x", &i64_t, &slot);
+        builder.build_ret(&x);
+
+        let mut pipeline = PassPipeline::new("mem2reg");
+        pipeline.set_verify_each(true);
+        pipeline.run(&mut module, None).unwrap();
+        assert!(!module.to_ir_string().contains("alloca"));
+        PassPipeline::preset(OptLevel::O2).run(&mut module, None).unwrap();
+        assert_eq!(module.verify(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unknown_passes() {
+        let ctx = LLVMContext::new();
+        let mut module = square(&ctx);
+        let message = PassPipeline::new("bogus").run(&mut module, None).unwrap_err();
+        assert!(message.contains("bogus"), "{message}");
+    }
+
+    #[test]
+    fn ir_round_trips() {
+        let ctx = LLVMContext::new();
+        let ir = square(&ctx).to_ir_string();
+        let parsed = LLVMModule::parse_ir(&ctx, &ir).unwrap();
+        assert_eq!(parsed.verify(), Ok(()));
+        // The parsed module is named after the buffer, so only the first line differs.
+        let body = |ir: &str| ir.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert_eq!(body(&parsed.to_ir_string()), body(&ir));
+
+        let path = temp_path("square.ll");
+        parsed.write_ir_to_file(&path).unwrap();
+        let written = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.unwrap(), parsed.to_ir_string());
+    }
+
+    #[test]
+    fn rejects_invalid_ir() {
+        let ctx = LLVMContext::new();
+        let message = LLVMModule::parse_ir(&ctx, "define i64 @f() {\n  ret i64 %x\n}\n").err().unwrap();
+        assert!(message.contains("%x"), "{message}");
+    }
+
+    #[test]
+    fn execution_engine_checks_function_types() {
+        llvm_init_jit();
+        let ctx = LLVMContext::new();
+        let engine = LLVMExecutionEngine::new_for_module(square(&ctx)).unwrap();
+        let square = engine.get_function::<extern "C" fn(i64) -> i64>("square").unwrap();
+        assert_eq!(unsafe { square.call(7) }, 49);
+        assert_eq!(unsafe { square.as_raw() }(-3), 9);
+
+        assert_eq!(
+            engine.get_function::<extern "C" fn(i64) -> i64>("cube").err().unwrap(),
+            "There is no function named `cube`."
+        );
+        assert_eq!(
+            engine.get_function::<extern "C" fn(f64) -> f64>("square").err().unwrap(),
+            "`square` has type `i64 (i64)`, not `double (double)`."
+        );
+        assert!(engine.get_function::<extern "C" fn(i64)>("square").is_err());
+    }
+
+    #[test]
+    fn orc_jit_links_modules_and_host_functions() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        static RECORDED: AtomicI64 = AtomicI64::new(0);
+        extern "C" fn record(value: i64) {
+            RECORDED.store(value, Ordering::SeqCst);
+        }
+
+        let jit = LLVMOrcJit::new().unwrap();
+        unsafe {
+            jit.define_host_functions(&[("record", record as *const libc::c_void)]).unwrap();
+        }
+        let ctx = jit.context();
+        jit.add_module(square(ctx)).unwrap();
+
+        // void record_square(i64 x) { record(square(x)); }
+        let mut module = ctx.create_module("record_square");
+        let i64_t = ctx.i64_t();
+        let square_ty = llvm_get_function_ty(&i64_t, &[i64_t]);
+        let record_ty = llvm_get_function_ty(&ctx.void_t(), &[i64_t]);
+        let square = module.add_function("square", square_ty);
+        let record = module.add_function("record", record_ty);
+        let function = module.add_function("record_square", record_ty);
+        let mut builder = ctx.create_builder();
+        builder.position_at_end(&function.append_basic_block("entry"));
+        let y = builder.build_call("y", &square_ty, &square, &[&function.get_param(0)]);
+        builder.build_call("", &record_ty, &record, &[&y]);
+        builder.build_ret_void();
+        jit.add_module(module).unwrap();
+
+        let record_square = jit.get_function::<extern "C" fn(i64)>("record_square").unwrap();
+        unsafe { record_square.call(12) };
+        assert_eq!(RECORDED.load(Ordering::SeqCst), 144);
+
+        // Only the functions defined by added modules can be looked up.
+        assert_eq!(
+            jit.get_function::<extern "C" fn(i64)>("record").err().unwrap(),
+            "There is no function named `record`."
+        );
+        assert_eq!(
+            jit.get_function::<extern "C" fn(i64) -> i64>("record_square").err().unwrap(),
+            "`record_square` has type `void (i64)`, not `i64 (i64)`."
+        );
+    }
+
+    #[test]
+    fn orc_jit_rejects_modules_of_other_contexts() {
+        let jit = LLVMOrcJit::new().unwrap();
+        let ctx = LLVMContext::new();
+        assert_eq!(
+            jit.add_module(square(&ctx)).unwrap_err(),
+            "Module `square` wasn't created in the context of the JIT."
+        );
+    }
+}
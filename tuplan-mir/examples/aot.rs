@@ -0,0 +1,36 @@
+use tuplan_ir::{encode, Op};
+use tuplan_llvm::OptLevel;
+use tuplan_mir::{aot, lift};
+
+fn main() {
+    /*
+
+    i = 0;
+    while i < 10 {
+        peek i;
+        i += 1;
+    }
+
+     */
+
+    let bytecode = encode(&[
+        Op::PushU64(0),
+        Op::LocalCopy(0),
+        Op::PushU64(10),
+        Op::LtU64,
+        Op::GotoIfNot(55),
+        Op::LocalCopy(0),
+        Op::PeekU64,
+        Op::PushU64(1),
+        Op::AddU64,
+        Op::LocalSet(0),
+        Op::Goto(9),
+    ]);
+
+    println!("{}", lift::lift_bytecode(&bytecode, aot::PROGRAM).unwrap());
+
+    aot::compile_bytecode_to_object(&bytecode, "loop.o", OptLevel::O2).unwrap();
+    println!("Wrote loop.o, link it with:");
+    println!("    cargo build -p tuplan-rt");
+    println!("    cc loop.o -L target/debug -ltuplan_rt -lpthread -ldl -o loop");
+}
@@ -0,0 +1,56 @@
+use tuplan_ir::disassemble;
+use tuplan_mir::{bytecode, Function, FunctionBuilder, Type};
+use tuplan_vm::Vm;
+
+fn main() {
+    /*
+
+    i = 0;
+    while i < 10 {
+        peek i;
+        i += 1;
+    }
+
+     */
+
+    let mut func = Function::new("main", &[], None);
+    let mut b = FunctionBuilder::new(&mut func);
+    let header = b.create_block();
+    let i = b.append_block_param(header, Type::U64);
+    let body = b.create_block();
+    let exit = b.create_block();
+
+    let zero = b.const_u64(0);
+    b.jump(header, &[zero]);
+
+    b.switch_to_block(header);
+    let ten = b.const_u64(10);
+    let cond = b.lt(i, ten);
+    b.branch(cond, body, &[], exit, &[]);
+
+    b.switch_to_block(body);
+    b.peek(i);
+    let one = b.const_u64(1);
+    let next = b.add(i, one);
+    b.jump(header, &[next]);
+
+    b.switch_to_block(exit);
+    b.ret(None);
+
+    println!("{func}");
+
+    let bytecode = bytecode::lower_function(&func).unwrap();
+    println!("{}", disassemble(&bytecode));
+    unsafe {
+        Vm::new(bytecode).run();
+    }
+
+    #[cfg(feature = "llvm")]
+    {
+        let mut module = tuplan_mir::Module::new("example");
+        module.add_function(func);
+        let ctx = tuplan_llvm::LLVMContext::new();
+        let llvm_module = tuplan_mir::llvm::lower_module(&module, &ctx).unwrap();
+        llvm_module.dump_ir_to_stdout();
+    }
+}
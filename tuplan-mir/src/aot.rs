@@ -0,0 +1,162 @@
+//! Ahead-of-time compilation of bytecode programs to native object files.
+//!
+//! The program is lifted into MIR, lowered to LLVM IR and wrapped in a C `main` function. The
+//! resulting object file has to be linked against `tuplan-rt`, which provides the functions
+//! behind `peeku64` and `peekbool`:
+//!
+//! ```text
+//! cc program.o -L target/debug -ltuplan_rt -lpthread -ldl -o program
+//! ```
+
+use crate::lift::{lift_bytecode, LiftError};
+use crate::llvm::LowerError;
+use crate::Module;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use tuplan_ir::ByteStream;
+use tuplan_llvm::{
+    llvm_get_function_ty, llvm_init_native_target, CodeGenOptLevel, FileType, LLVMBuilder,
+    LLVMContext, LLVMModule, LLVMTargetMachine, OptLevel, PassPipeline,
+};
+
+/// The name of the function the program is compiled into.
+pub const PROGRAM: &str = "tuplan_program";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AotError {
+    Lift(LiftError),
+    Lower(LowerError),
+    /// The optimization pipeline failed.
+    Optimize(String),
+    /// LLVM failed to emit the object file.
+    Emit(String),
+}
+
+impl fmt::Display for AotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AotError::Lift(err) => err.fmt(f),
+            AotError::Lower(err) => err.fmt(f),
+            AotError::Optimize(err) => write!(f, "Failed to optimize: {err}"),
+            AotError::Emit(err) => write!(f, "Failed to emit object file: {err}"),
+        }
+    }
+}
+
+impl Error for AotError {}
+
+impl From<LiftError> for AotError {
+    fn from(err: LiftError) -> Self {
+        AotError::Lift(err)
+    }
+}
+
+impl From<LowerError> for AotError {
+    fn from(err: LowerError) -> Self {
+        AotError::Lower(err)
+    }
+}
+
+/// Compiles a bytecode program into an LLVM module with a C `main` function.
+pub fn compile_bytecode<'ctx>(
+    bytes: &ByteStream,
+    name: &str,
+    ctx: &'ctx LLVMContext,
+) -> Result<LLVMModule<'ctx>, AotError> {
+    let mut module = Module::new(name);
+    module.add_function(lift_bytecode(bytes, PROGRAM)?);
+    let mut llvm_module = crate::llvm::lower_module(&module, ctx)?;
+
+    // int main() { tuplan_program(); return 0; }
+    let i32_t = ctx.i32_t();
+    let main_ty = llvm_get_function_ty(&i32_t, &[]);
+    let program_ty = llvm_get_function_ty(&ctx.void_t(), &[]);
+    let program = llvm_module.get_function(PROGRAM).unwrap();
+    let main = llvm_module.add_function("main", main_ty);
+    let entry = main.append_basic_block("entry");
+    let mut builder = LLVMBuilder::new(ctx);
+    builder.position_at_end(&entry);
+    builder.build_call("", &program_ty, &program, &[]);
+    builder.build_ret(&i32_t.const_int(0, false));
+
+    Ok(llvm_module)
+}
+
+/// Compiles a bytecode program into a native object file for the host at `path`, optimized
+/// with the preset `level`.
+pub fn compile_bytecode_to_object<P: AsRef<Path>>(
+    bytes: &ByteStream,
+    path: P,
+    level: OptLevel,
+) -> Result<(), AotError> {
+    llvm_init_native_target();
+    let name = path.as_ref().to_string_lossy().into_owned();
+    let ctx = LLVMContext::new();
+    let mut module = compile_bytecode(bytes, &name, &ctx)?;
+
+    let machine =
+        LLVMTargetMachine::host_generic(CodeGenOptLevel::Default).map_err(AotError::Emit)?;
+    machine.configure_module(&mut module);
+    PassPipeline::preset(level)
+        .run(&mut module, Some(&machine))
+        .map_err(AotError::Optimize)?;
+    machine
+        .emit_to_file(&module, path, FileType::Object)
+        .map_err(AotError::Emit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuplan_ir::{encode, Op};
+
+    /// `i = 0; while i < 3 { peek i; i += 1 }`, jumping to byte offsets.
+    fn count() -> ByteStream {
+        encode(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::GotoIfNot(61),
+            Op::LocalCopy(0),
+            Op::PeekU64,
+            Op::Pop,
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(9),
+        ])
+    }
+
+    #[test]
+    fn wraps_the_program_in_main() {
+        let ctx = LLVMContext::new();
+        let module = compile_bytecode(&count(), "count", &ctx).unwrap();
+        assert_eq!(module.verify(), Ok(()));
+        let ir = module.to_ir_string();
+        assert!(ir.contains("define i32 @main()"), "{ir}");
+        assert!(ir.contains(&format!("call void @{PROGRAM}()")), "{ir}");
+        assert!(ir.contains("ret i32 0"), "{ir}");
+    }
+
+    #[test]
+    fn writes_object_files() {
+        let path = std::env::temp_dir().join(format!("tuplan-aot-{}.o", std::process::id()));
+        compile_bytecode_to_object(&count(), &path, OptLevel::O2).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert!(len > 0);
+    }
+
+    #[test]
+    fn rejects_programs_that_cant_be_lifted() {
+        let ctx = LLVMContext::new();
+        let bytes = encode(&[Op::PushU64(0), Op::Ret]);
+        assert!(matches!(
+            compile_bytecode(&bytes, "ret", &ctx),
+            Err(AotError::Lift(LiftError::Unsupported { .. }))
+        ));
+    }
+}
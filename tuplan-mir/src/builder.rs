@@ -0,0 +1,149 @@
+use crate::{
+    Block, BlockCall, Function, Inst, InstKind, Terminator, Type, Value, ValueData, ValueDef,
+};
+
+/// Appends instructions to the end of a block in a function.
+pub struct FunctionBuilder<'f> {
+    func: &'f mut Function,
+    current: Block,
+}
+
+impl<'f> FunctionBuilder<'f> {
+    /// Creates a builder positioned at the entry block of `func`.
+    pub fn new(func: &'f mut Function) -> FunctionBuilder<'f> {
+        let current = func.entry();
+        FunctionBuilder { func, current }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn func(&self) -> &Function {
+        self.func
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn current_block(&self) -> Block {
+        self.current
+    }
+
+    #[inline]
+    pub fn create_block(&mut self) -> Block {
+        self.func.create_block()
+    }
+
+    #[inline]
+    pub fn append_block_param(&mut self, block: Block, ty: Type) -> Value {
+        self.func.append_block_param(block, ty)
+    }
+
+    #[inline]
+    pub fn switch_to_block(&mut self, block: Block) {
+        self.current = block;
+    }
+
+    /// Returns true if the current block already has a terminator.
+    #[inline]
+    #[must_use]
+    pub fn is_terminated(&self) -> bool {
+        self.func.block(self.current).terminator.is_some()
+    }
+
+    fn push(&mut self, kind: InstKind, ty: Option<Type>) -> Option<Value> {
+        assert!(
+            !self.is_terminated(),
+            "Tried to add an instruction to the terminated block {}.",
+            self.current
+        );
+        let block = &mut self.func.blocks[self.current.index()];
+        let result = ty.map(|ty| {
+            let value = Value(self.func.values.len() as u32);
+            self.func.values.push(ValueData {
+                ty,
+                def: ValueDef::Inst(self.current, block.insts.len()),
+            });
+            value
+        });
+        block.insts.push(Inst { kind, result });
+        result
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.func.blocks[self.current.index()];
+        assert!(
+            block.terminator.is_none(),
+            "Block {} is already terminated.",
+            self.current
+        );
+        block.terminator = Some(terminator);
+    }
+
+    pub fn const_u64(&mut self, val: u64) -> Value {
+        self.push(InstKind::ConstU64(val), Some(Type::U64)).unwrap()
+    }
+
+    pub fn const_bool(&mut self, val: bool) -> Value {
+        self.push(InstKind::ConstBool(val), Some(Type::Bool))
+            .unwrap()
+    }
+
+    pub fn add(&mut self, a: Value, b: Value) -> Value {
+        self.push(InstKind::Add(a, b), Some(Type::U64)).unwrap()
+    }
+
+    pub fn sub(&mut self, a: Value, b: Value) -> Value {
+        self.push(InstKind::Sub(a, b), Some(Type::U64)).unwrap()
+    }
+
+    pub fn lt(&mut self, a: Value, b: Value) -> Value {
+        self.push(InstKind::Lt(a, b), Some(Type::Bool)).unwrap()
+    }
+
+    pub fn gt(&mut self, a: Value, b: Value) -> Value {
+        self.push(InstKind::Gt(a, b), Some(Type::Bool)).unwrap()
+    }
+
+    pub fn peek(&mut self, val: Value) {
+        self.push(InstKind::Peek(val), None);
+    }
+
+    pub fn load(&mut self, slot: u32, ty: Type) -> Value {
+        self.push(InstKind::Load(slot, ty), Some(ty)).unwrap()
+    }
+
+    pub fn store(&mut self, slot: u32, val: Value) {
+        self.push(InstKind::Store(slot, val), None);
+    }
+
+    pub fn jump(&mut self, block: Block, args: &[Value]) {
+        self.terminate(Terminator::Jump(BlockCall {
+            block,
+            args: args.to_vec(),
+        }));
+    }
+
+    pub fn branch(
+        &mut self,
+        cond: Value,
+        then_block: Block,
+        then_args: &[Value],
+        else_block: Block,
+        else_args: &[Value],
+    ) {
+        self.terminate(Terminator::Branch {
+            cond,
+            then_dest: BlockCall {
+                block: then_block,
+                args: then_args.to_vec(),
+            },
+            else_dest: BlockCall {
+                block: else_block,
+                args: else_args.to_vec(),
+            },
+        });
+    }
+
+    pub fn ret(&mut self, val: Option<Value>) {
+        self.terminate(Terminator::Return(val));
+    }
+}
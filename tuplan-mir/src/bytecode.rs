@@ -0,0 +1,285 @@
+//! Lowering from MIR to Tuplan bytecode.
+//!
+//! Every value gets its own stack slot. The parameters are expected to be on the stack when the
+//! program starts, and the remaining slots are reserved right after. Instructions copy their
+//! operands to the top of the stack and store the result back into its slot. Block arguments are
+//! all pushed before any of them is stored, so jumps like `bb1(v2, v1)` can swap values.
+//!
+//! There are no calls in the bytecode, so a function is lowered as a whole program. Returning
+//! jumps to the end of the stream with the return value on top of the stack.
+//!
+//! Functions with a frame can't be lowered, since the program has nothing to read it from.
+
+use crate::verify::{verify, VerifyError};
+use crate::{Block, BlockCall, Function, InstKind, Terminator, Type, Value};
+use tuplan_ir::{encode, ByteStream, Op};
+
+/// Compiles `func` into a bytecode program.
+pub fn lower_function(func: &Function) -> Result<ByteStream, VerifyError> {
+    verify(func)?;
+    if func.frame {
+        return Err(VerifyError {
+            function: func.name.clone(),
+            message: "Functions with a frame can't be lowered to bytecode.".to_string(),
+        });
+    }
+
+    // The parameters come first, in order, followed by every other value.
+    let mut slots = vec![u32::MAX; func.values.len()];
+    for (slot, param) in func.params().iter().enumerate() {
+        slots[param.index()] = slot as u32;
+    }
+    let unassigned = slots.iter_mut().filter(|slot| **slot == u32::MAX);
+    for (next_slot, slot) in (func.params().len() as u32..).zip(unassigned) {
+        *slot = next_slot;
+    }
+
+    let mut lowering = Lowering {
+        func,
+        slots,
+        ops: Vec::new(),
+        fixups: Vec::new(),
+        block_start: vec![None; func.blocks.len()],
+    };
+    lowering.lower();
+    let stream = lowering.finish();
+    debug_assert!(
+        tuplan_ir::verify::verify(&stream).is_ok(),
+        "Lowered `{}` into invalid bytecode.",
+        func.name
+    );
+    Ok(stream)
+}
+
+/// The target of a jump that isn't known until all blocks are lowered.
+#[derive(Clone, Copy)]
+enum Label {
+    Block(Block),
+    /// An instruction index in `ops`.
+    Op(usize),
+    End,
+}
+
+struct Lowering<'f> {
+    func: &'f Function,
+    slots: Vec<u32>,
+    ops: Vec<Op>,
+    /// Jumps whose target has to be patched, as `(index in ops, target)`.
+    fixups: Vec<(usize, Label)>,
+    block_start: Vec<Option<usize>>,
+}
+
+impl<'f> Lowering<'f> {
+    #[inline]
+    fn slot(&self, value: Value) -> u32 {
+        self.slots[value.index()]
+    }
+
+    fn jump(&mut self, op: Op, label: Label) {
+        self.fixups.push((self.ops.len(), label));
+        self.ops.push(op);
+    }
+
+    fn lower(&mut self) {
+        // The parameters are already on the stack, reserve a slot for every other value.
+        let mut reserved: Vec<Value> = (0..self.func.values.len() as u32)
+            .map(Value)
+            .filter(|value| self.slot(*value) as usize >= self.func.params().len())
+            .collect();
+        reserved.sort_by_key(|value| self.slot(*value));
+        for value in reserved {
+            match self.func.ty(value) {
+                Type::U64 => self.ops.push(Op::PushU64(0)),
+                Type::Bool => self.ops.extend([Op::PushU64(0), Op::PushU64(0), Op::LtU64]),
+            }
+        }
+
+        for (block, data) in self.func.blocks() {
+            self.block_start[block.index()] = Some(self.ops.len());
+            for inst in &data.insts {
+                let binary = |op: Op, a: Value, b: Value| {
+                    [Op::LocalCopy(self.slot(a)), Op::LocalCopy(self.slot(b)), op]
+                };
+                match inst.kind {
+                    InstKind::ConstU64(val) => self.ops.push(Op::PushU64(val)),
+                    InstKind::ConstBool(val) => {
+                        // There is no instruction for pushing a bool, so compare two constants instead.
+                        self.ops
+                            .extend([Op::PushU64(0), Op::PushU64(val as u64), Op::LtU64])
+                    }
+                    InstKind::Add(a, b) => self.ops.extend(binary(Op::AddU64, a, b)),
+                    InstKind::Sub(a, b) => self.ops.extend(binary(Op::SubU64, a, b)),
+                    InstKind::Lt(a, b) => self.ops.extend(binary(Op::LtU64, a, b)),
+                    InstKind::Gt(a, b) => self.ops.extend(binary(Op::GtU64, a, b)),
+                    InstKind::Peek(val) => {
+                        let peek = match self.func.ty(val) {
+                            Type::U64 => Op::PeekU64,
+                            Type::Bool => Op::PeekBool,
+                        };
+                        self.ops
+                            .extend([Op::LocalCopy(self.slot(val)), peek, Op::Pop]);
+                    }
+                    InstKind::Load(..) | InstKind::Store(..) => {
+                        unreachable!("Frame access in a function without a frame.")
+                    }
+                }
+                if let Some(result) = inst.result {
+                    self.ops.push(Op::LocalSet(self.slot(result)));
+                }
+            }
+
+            match data.terminator.as_ref().unwrap() {
+                Terminator::Jump(dest) => self.lower_block_call(dest),
+                Terminator::Branch {
+                    cond,
+                    then_dest,
+                    else_dest,
+                } => {
+                    self.ops.push(Op::LocalCopy(self.slot(*cond)));
+                    let fixup = self.fixups.len();
+                    self.jump(Op::GotoIfNot(0), Label::End);
+                    self.lower_block_call(then_dest);
+                    self.fixups[fixup].1 = Label::Op(self.ops.len());
+                    self.lower_block_call(else_dest);
+                }
+                Terminator::Return(val) => {
+                    if let Some(val) = val {
+                        self.ops.push(Op::LocalCopy(self.slot(*val)));
+                    }
+                    self.jump(Op::Goto(0), Label::End);
+                }
+            }
+        }
+    }
+
+    fn lower_block_call(&mut self, call: &BlockCall) {
+        let params = &self.func.block(call.block).params;
+        for arg in &call.args {
+            self.ops.push(Op::LocalCopy(self.slot(*arg)));
+        }
+        for param in params.iter().rev() {
+            self.ops.push(Op::LocalSet(self.slot(*param)));
+        }
+        self.jump(Op::Goto(0), Label::Block(call.block));
+    }
+
+    fn finish(mut self) -> ByteStream {
+        let mut offsets = Vec::with_capacity(self.ops.len() + 1);
+        let mut offset = 0;
+        for op in &self.ops {
+            offsets.push(offset as u32);
+            offset += op.encoded_len();
+        }
+        offsets.push(offset as u32);
+
+        for (index, label) in &self.fixups {
+            let target = match label {
+                Label::Block(block) => offsets[self.block_start[block.index()].unwrap()],
+                Label::Op(op) => offsets[*op],
+                Label::End => offsets[self.ops.len()],
+            };
+            self.ops[*index] = match self.ops[*index] {
+                Op::Goto(_) => Op::Goto(target),
+                Op::GotoIf(_) => Op::GotoIf(target),
+                Op::GotoIfNot(_) => Op::GotoIfNot(target),
+                op => op,
+            };
+        }
+        encode(&self.ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FunctionBuilder;
+    use tuplan_vm::{Item, Vm};
+
+    /// Lowers `func` and runs it in the VM, returning the value on top of the stack.
+    fn run(func: &Function) -> Item {
+        let mut vm = Vm::new(lower_function(func).unwrap());
+        unsafe {
+            vm.run();
+        }
+        *vm.stack().last().unwrap()
+    }
+
+    /// `a, b = 0, 1; for _ in 0..n { a, b = b, a + b }; return a`
+    fn fibonacci(n: u64) -> Function {
+        let mut func = Function::new("fibonacci", &[], Some(Type::U64));
+        let mut b = FunctionBuilder::new(&mut func);
+        let header = b.create_block();
+        let i = b.append_block_param(header, Type::U64);
+        let x = b.append_block_param(header, Type::U64);
+        let y = b.append_block_param(header, Type::U64);
+        let body = b.create_block();
+        let exit = b.create_block();
+
+        let zero = b.const_u64(0);
+        let one = b.const_u64(1);
+        b.jump(header, &[zero, zero, one]);
+
+        b.switch_to_block(header);
+        let n = b.const_u64(n);
+        let cond = b.lt(i, n);
+        b.branch(cond, body, &[], exit, &[]);
+
+        b.switch_to_block(body);
+        let one = b.const_u64(1);
+        let next = b.add(i, one);
+        let sum = b.add(x, y);
+        // `y` and `sum` move into the slots of `x` and `y` at the same time.
+        b.jump(header, &[next, y, sum]);
+
+        b.switch_to_block(exit);
+        b.ret(Some(x));
+        func
+    }
+
+    #[test]
+    fn runs_loops() {
+        for (n, expected) in [(0, 0), (1, 1), (2, 1), (10, 55), (50, 12586269025)] {
+            assert_eq!(unsafe { run(&fibonacci(n)).u64() }, expected);
+        }
+    }
+
+    #[test]
+    fn runs_branches_on_bools() {
+        for (val, expected) in [(true, 1), (false, 2)] {
+            let mut func = Function::new("f", &[], Some(Type::U64));
+            let mut b = FunctionBuilder::new(&mut func);
+            let then_block = b.create_block();
+            let else_block = b.create_block();
+            let cond = b.const_bool(val);
+            b.branch(cond, then_block, &[], else_block, &[]);
+            for (block, result) in [(then_block, 1), (else_block, 2)] {
+                b.switch_to_block(block);
+                let result = b.const_u64(result);
+                b.ret(Some(result));
+            }
+            assert_eq!(unsafe { run(&func).u64() }, expected);
+        }
+    }
+
+    #[test]
+    fn returns_bools() {
+        let mut func = Function::new("f", &[], Some(Type::Bool));
+        let mut b = FunctionBuilder::new(&mut func);
+        let three = b.const_u64(3);
+        let four = b.const_u64(4);
+        let cond = b.gt(four, three);
+        b.ret(Some(cond));
+        assert!(unsafe { run(&func).bool() });
+    }
+
+    #[test]
+    fn rejects_frames() {
+        let mut func = Function::new("f", &[], None);
+        func.frame = true;
+        FunctionBuilder::new(&mut func).ret(None);
+        assert_eq!(
+            lower_function(&func).err().unwrap().message,
+            "Functions with a frame can't be lowered to bytecode."
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Tuplan MIR, a typed SSA intermediate representation.
+//!
+//! A function is a list of basic blocks. Every block has a list of parameters, a list of
+//! instructions and a terminator. Instead of phi nodes, jumps pass arguments to the parameters
+//! of the block they jump to. The parameters of the entry block are the parameters of the function.
+//!
+//! A function can also have a frame, an array of 64 bit slots owned by the caller. `load` and
+//! `store` move values in and out of it, which lets a function return more than one value.
+//!
+//! Frontends build functions with [`FunctionBuilder`] and lower them with one of the backends:
+//! [`bytecode`] for the Tuplan VM or [`llvm`] for native code.
+
+use std::fmt;
+
+#[cfg(feature = "llvm")]
+pub mod aot;
+pub mod builder;
+pub mod bytecode;
+pub mod lift;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+pub mod verify;
+
+pub use builder::FunctionBuilder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    U64,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::U64 => f.write_str("u64"),
+            Type::Bool => f.write_str("bool"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(pub u32);
+
+impl Value {
+    #[inline]
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Block(pub u32);
+
+impl Block {
+    #[inline]
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+/// Where a value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueDef {
+    /// The `n`th parameter of a block.
+    Param(Block, usize),
+    /// The result of the `n`th instruction of a block.
+    Inst(Block, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueData {
+    pub ty: Type,
+    pub def: ValueDef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstKind {
+    ConstU64(u64),
+    ConstBool(bool),
+    Add(Value, Value),
+    Sub(Value, Value),
+    Lt(Value, Value),
+    Gt(Value, Value),
+    /// Displays a value to stdout.
+    Peek(Value),
+    /// Reads a slot of the frame. A `bool` is true if the slot isn't 0.
+    Load(u32, Type),
+    /// Writes a value to a slot of the frame. A `bool` is stored as 0 or 1.
+    Store(u32, Value),
+}
+
+impl InstKind {
+    /// The values this instruction reads.
+    #[must_use]
+    pub fn args(&self) -> Vec<Value> {
+        match self {
+            InstKind::ConstU64(_) | InstKind::ConstBool(_) | InstKind::Load(..) => Vec::new(),
+            InstKind::Add(a, b) | InstKind::Sub(a, b) | InstKind::Lt(a, b) | InstKind::Gt(a, b) => {
+                vec![*a, *b]
+            }
+            InstKind::Peek(val) | InstKind::Store(_, val) => vec![*val],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inst {
+    pub kind: InstKind,
+    /// The value this instruction defines, if any.
+    pub result: Option<Value>,
+}
+
+/// A jump to `block` that passes `args` to its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockCall {
+    pub block: Block,
+    pub args: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(BlockCall),
+    Branch {
+        cond: Value,
+        then_dest: BlockCall,
+        else_dest: BlockCall,
+    },
+    Return(Option<Value>),
+}
+
+impl Terminator {
+    #[must_use]
+    pub fn successors(&self) -> Vec<&BlockCall> {
+        match self {
+            Terminator::Jump(dest) => vec![dest],
+            Terminator::Branch {
+                then_dest,
+                else_dest,
+                ..
+            } => vec![then_dest, else_dest],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+
+    /// The values this terminator reads, including the arguments passed to its successors.
+    #[must_use]
+    pub fn args(&self) -> Vec<Value> {
+        match self {
+            Terminator::Jump(dest) => dest.args.clone(),
+            Terminator::Branch {
+                cond,
+                then_dest,
+                else_dest,
+            } => std::iter::once(*cond)
+                .chain(then_dest.args.iter().copied())
+                .chain(else_dest.args.iter().copied())
+                .collect(),
+            Terminator::Return(val) => val.iter().copied().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockData {
+    pub params: Vec<Value>,
+    pub insts: Vec<Inst>,
+    pub terminator: Option<Terminator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub ret: Option<Type>,
+    /// Whether the function takes a frame.
+    pub frame: bool,
+    pub blocks: Vec<BlockData>,
+    pub values: Vec<ValueData>,
+}
+
+impl Function {
+    /// Creates a function with an entry block whose parameters are `params`.
+    #[must_use]
+    pub fn new<S: Into<String>>(name: S, params: &[Type], ret: Option<Type>) -> Function {
+        let mut function = Function {
+            name: name.into(),
+            ret,
+            frame: false,
+            blocks: vec![BlockData::default()],
+            values: Vec::new(),
+        };
+        for ty in params {
+            function.append_block_param(function.entry(), *ty);
+        }
+        function
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn entry(&self) -> Block {
+        Block(0)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn params(&self) -> &[Value] {
+        &self.blocks[0].params
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn block(&self, block: Block) -> &BlockData {
+        &self.blocks[block.index()]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn value(&self, value: Value) -> &ValueData {
+        &self.values[value.index()]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn ty(&self, value: Value) -> Type {
+        self.values[value.index()].ty
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (Block, &BlockData)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, data)| (Block(i as u32), data))
+    }
+
+    /// The blocks reachable from the entry in reverse postorder. Every block comes after the
+    /// blocks that dominate it.
+    #[must_use]
+    pub fn reverse_postorder(&self) -> Vec<Block> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        // (block, index of the next successor to visit)
+        let mut stack = vec![(self.entry(), 0)];
+        visited[self.entry().index()] = true;
+        while let Some((block, next)) = stack.last_mut() {
+            let succs = match &self.blocks[block.index()].terminator {
+                Some(terminator) => terminator.successors(),
+                None => Vec::new(),
+            };
+            match succs.get(*next) {
+                Some(succ) => {
+                    *next += 1;
+                    if !visited[succ.block.index()] {
+                        visited[succ.block.index()] = true;
+                        stack.push((succ.block, 0));
+                    }
+                }
+                None => {
+                    postorder.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    pub fn create_block(&mut self) -> Block {
+        self.blocks.push(BlockData::default());
+        Block(self.blocks.len() as u32 - 1)
+    }
+
+    pub fn append_block_param(&mut self, block: Block, ty: Type) -> Value {
+        let params = &mut self.blocks[block.index()].params;
+        let value = Value(self.values.len() as u32);
+        self.values.push(ValueData {
+            ty,
+            def: ValueDef::Param(block, params.len()),
+        });
+        params.push(value);
+        value
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn args(f: &mut fmt::Formatter<'_>, call: &BlockCall) -> fmt::Result {
+            write!(f, "{}(", call.block)?;
+            for (i, arg) in call.args.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{arg}")?;
+            }
+            f.write_str(")")
+        }
+
+        write!(f, "fn {}(", self.name)?;
+        for (i, param) in self.params().iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{param}: {}", self.ty(*param))?;
+        }
+        f.write_str(")")?;
+        if self.frame {
+            f.write_str(" frame")?;
+        }
+        if let Some(ret) = self.ret {
+            write!(f, " -> {ret}")?;
+        }
+        f.write_str(" {\n")?;
+
+        for (block, data) in self.blocks() {
+            write!(f, "{block}(")?;
+            for (i, param) in data.params.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{param}: {}", self.ty(*param))?;
+            }
+            f.write_str("):\n")?;
+
+            for inst in &data.insts {
+                f.write_str("    ")?;
+                if let Some(result) = inst.result {
+                    write!(f, "{result} = ")?;
+                }
+                match inst.kind {
+                    InstKind::ConstU64(val) => write!(f, "const.u64 {val}")?,
+                    InstKind::ConstBool(val) => write!(f, "const.bool {val}")?,
+                    InstKind::Add(a, b) => write!(f, "add {a}, {b}")?,
+                    InstKind::Sub(a, b) => write!(f, "sub {a}, {b}")?,
+                    InstKind::Lt(a, b) => write!(f, "lt {a}, {b}")?,
+                    InstKind::Gt(a, b) => write!(f, "gt {a}, {b}")?,
+                    InstKind::Peek(val) => write!(f, "peek {val}")?,
+                    InstKind::Load(slot, ty) => write!(f, "load.{ty} frame[{slot}]")?,
+                    InstKind::Store(slot, val) => write!(f, "store frame[{slot}], {val}")?,
+                }
+                f.write_str("\n")?;
+            }
+
+            f.write_str("    ")?;
+            match &data.terminator {
+                Some(Terminator::Jump(dest)) => {
+                    f.write_str("jump ")?;
+                    args(f, dest)?;
+                }
+                Some(Terminator::Branch {
+                    cond,
+                    then_dest,
+                    else_dest,
+                }) => {
+                    write!(f, "branch {cond}, ")?;
+                    args(f, then_dest)?;
+                    f.write_str(", ")?;
+                    args(f, else_dest)?;
+                }
+                Some(Terminator::Return(Some(val))) => write!(f, "return {val}")?,
+                Some(Terminator::Return(None)) => f.write_str("return")?,
+                None => f.write_str("<unterminated>")?,
+            }
+            f.write_str("\n")?;
+        }
+        f.write_str("}\n")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    #[must_use]
+    pub fn new<S: Into<String>>(name: S) -> Module {
+        Module {
+            name: name.into(),
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn add_function(&mut self, function: Function) {
+        self.functions.push(function);
+    }
+
+    #[must_use]
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; module {}", self.name)?;
+        for (i, function) in self.functions.iter().enumerate() {
+            if i != 0 {
+                f.write_str("\n")?;
+            }
+            function.fmt(f)?;
+        }
+        Ok(())
+    }
+}
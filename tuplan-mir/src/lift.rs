@@ -0,0 +1,515 @@
+//! Lifting from Tuplan bytecode to MIR.
+//!
+//! The bytecode is verified first, so the shape of the stack is known at the start of every
+//! basic block. Each stack slot at the start of a block becomes a block parameter, and the
+//! instructions inside a block are evaluated on a stack of MIR values. Jumping to the end of the
+//! stream and falling off the end both return from the function.
+//!
+//! Single loops can also be lifted on their own with [`lift_loop`], which is what the JIT in
+//! `tuplan_vm` compiles.
+
+use crate::{Block, Function, FunctionBuilder, Type, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use tuplan_ir::cfg::{BlockId, Cfg, CfgError};
+use tuplan_ir::verify::{verify, StackMap, Ty, VerifyError};
+use tuplan_ir::{ByteStream, Op};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiftError {
+    Verify(VerifyError),
+    Cfg(CfgError),
+    /// The instruction at `offset` can't be represented in MIR.
+    Unsupported {
+        offset: usize,
+        op: Op,
+    },
+    /// There is no loop whose header starts at `offset`.
+    NotALoop {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for LiftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiftError::Verify(err) => err.fmt(f),
+            LiftError::Cfg(err) => err.fmt(f),
+            LiftError::Unsupported { offset, op } => {
+                write!(f, "Can't lift `{op}` at {offset}.")
+            }
+            LiftError::NotALoop { offset } => write!(f, "There is no loop at {offset}."),
+        }
+    }
+}
+
+impl Error for LiftError {}
+
+impl From<VerifyError> for LiftError {
+    fn from(err: VerifyError) -> Self {
+        LiftError::Verify(err)
+    }
+}
+
+impl From<CfgError> for LiftError {
+    fn from(err: CfgError) -> Self {
+        LiftError::Cfg(err)
+    }
+}
+
+fn mir_ty(ty: Ty) -> Type {
+    match ty {
+        Ty::U64 => Type::U64,
+        Ty::Bool => Type::Bool,
+    }
+}
+
+/// Lifts a whole bytecode program into a function called `name` without parameters or return value.
+///
+/// `ret` is rejected since its target is only known at runtime.
+pub fn lift_bytecode(bytes: &ByteStream, name: &str) -> Result<Function, LiftError> {
+    let stack_map = verify(bytes)?;
+    let cfg = Cfg::build(bytes)?;
+
+    let mut func = Function::new(name, &[], None);
+    let mut b = FunctionBuilder::new(&mut func);
+
+    // LLVM doesn't allow jumps to the entry block, so the first bytecode block gets its own.
+    let order = cfg.reverse_postorder();
+    let blocks = create_blocks(&mut b, &cfg, &stack_map, &order);
+    let exit = b.create_block();
+    b.switch_to_block(exit);
+    b.ret(None);
+
+    b.switch_to_block(b.func().entry());
+    b.jump(blocks[&cfg.entry()], &[]);
+
+    // Returns the MIR block for a bytecode offset, or `exit` for the end of the stream.
+    let dest = |offset: usize| match cfg.block_at(offset) {
+        Some(id) if offset < cfg.code_len() => (blocks[&id], true),
+        _ => (exit, false),
+    };
+    for id in order {
+        lift_block(&mut b, &cfg, id, blocks[&id], &dest)?;
+    }
+
+    Ok(func)
+}
+
+/// A loop lifted by [`lift_loop`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiftedLoop {
+    /// Takes the stack at the loop header in its frame and returns the offset to continue at,
+    /// with the stack at that offset in its frame.
+    pub func: Function,
+    /// The types on the stack at the loop header.
+    pub entry: Vec<Type>,
+    /// Every offset the loop can exit to, with the types on the stack there. Exiting to the end of
+    /// the stream leaves the frame untouched.
+    pub exits: Vec<(usize, Vec<Type>)>,
+}
+
+/// Lifts the loop whose header starts at `header` into a function called `name`.
+///
+/// The loop consists of the blocks dominated by the header that can jump back to it. Every jump
+/// out of the loop stores the stack in the frame and returns the offset it would have jumped to.
+pub fn lift_loop(bytes: &ByteStream, header: usize, name: &str) -> Result<LiftedLoop, LiftError> {
+    let stack_map = verify(bytes)?;
+    let cfg = Cfg::build(bytes)?;
+    let not_a_loop = LiftError::NotALoop { offset: header };
+    let header_id = cfg.block_at(header).ok_or_else(|| not_a_loop.clone())?;
+    let body = natural_loop(&cfg, header_id);
+    if body.is_empty() {
+        return Err(not_a_loop);
+    }
+
+    let mut func = Function::new(name, &[], Some(Type::U64));
+    func.frame = true;
+    let mut b = FunctionBuilder::new(&mut func);
+
+    let order: Vec<BlockId> = cfg
+        .reverse_postorder()
+        .into_iter()
+        .filter(|id| body.contains(id))
+        .collect();
+    let blocks = create_blocks(&mut b, &cfg, &stack_map, &order);
+
+    // Every offset outside of the loop that a block in it jumps or falls through to.
+    let mut exit_offsets = BTreeSet::new();
+    for id in &order {
+        let data = cfg.block(*id);
+        let last = data.last();
+        let targets = last
+            .and_then(|op| op.jump_target())
+            .map(|target| target as usize)
+            .into_iter()
+            .chain((!last.is_some_and(|op| op.is_terminator())).then_some(data.end));
+        for target in targets {
+            if !cfg.block_at(target).is_some_and(|id| body.contains(&id)) {
+                exit_offsets.insert(target);
+            }
+        }
+    }
+
+    let mut exits = Vec::new();
+    let mut exit_blocks = HashMap::new();
+    for offset in exit_offsets {
+        let tys: Vec<Type> = stack_map
+            .at(offset)
+            .unwrap_or(&[])
+            .iter()
+            .map(|ty| mir_ty(*ty))
+            .collect();
+        let block = b.create_block();
+        b.switch_to_block(block);
+        for (slot, ty) in tys.iter().enumerate() {
+            let val = b.append_block_param(block, *ty);
+            b.store(slot as u32, val);
+        }
+        let offset_val = b.const_u64(offset as u64);
+        b.ret(Some(offset_val));
+        exit_blocks.insert(offset, block);
+        exits.push((offset, tys));
+    }
+
+    let entry: Vec<Type> = b
+        .func()
+        .block(blocks[&header_id])
+        .params
+        .iter()
+        .map(|param| b.func().ty(*param))
+        .collect();
+    b.switch_to_block(b.func().entry());
+    let args: Vec<Value> = entry
+        .iter()
+        .enumerate()
+        .map(|(slot, ty)| b.load(slot as u32, *ty))
+        .collect();
+    b.jump(blocks[&header_id], &args);
+
+    let dest = |offset: usize| match cfg.block_at(offset).and_then(|id| blocks.get(&id)) {
+        Some(block) => (*block, true),
+        None => (exit_blocks[&offset], offset < cfg.code_len()),
+    };
+    for id in order {
+        lift_block(&mut b, &cfg, id, blocks[&id], &dest)?;
+    }
+
+    Ok(LiftedLoop { func, entry, exits })
+}
+
+/// The blocks of the loop with the header `header`, or nothing if no back edge jumps to it.
+fn natural_loop(cfg: &Cfg, header: BlockId) -> BTreeSet<BlockId> {
+    let doms = cfg.dominators();
+    let mut body = BTreeSet::new();
+    let mut work: Vec<BlockId> = cfg
+        .block(header)
+        .preds
+        .iter()
+        .copied()
+        .filter(|pred| doms.dominates(header, *pred))
+        .collect();
+    if work.is_empty() {
+        return body;
+    }
+    body.insert(header);
+    while let Some(id) = work.pop() {
+        if body.insert(id) {
+            work.extend(cfg.block(id).preds.iter().copied());
+        }
+    }
+    body
+}
+
+/// Creates a MIR block for each of `ids`, with a parameter for every value on the stack at its start.
+fn create_blocks(
+    b: &mut FunctionBuilder<'_>,
+    cfg: &Cfg,
+    stack_map: &StackMap,
+    ids: &[BlockId],
+) -> HashMap<BlockId, Block> {
+    let mut blocks = HashMap::new();
+    for id in ids {
+        let block = b.create_block();
+        for ty in stack_map.at(cfg.block(*id).start).unwrap_or(&[]) {
+            b.append_block_param(block, mir_ty(*ty));
+        }
+        blocks.insert(*id, block);
+    }
+    blocks
+}
+
+/// Lifts the instructions of the bytecode block `id` into `block`. `dest` returns the MIR block
+/// for a bytecode offset and whether it takes the stack as arguments.
+fn lift_block(
+    b: &mut FunctionBuilder<'_>,
+    cfg: &Cfg,
+    id: BlockId,
+    block: Block,
+    dest: &impl Fn(usize) -> (Block, bool),
+) -> Result<(), LiftError> {
+    b.switch_to_block(block);
+    let mut stack: Vec<Value> = b.func().block(block).params.clone();
+
+    let data = cfg.block(id);
+    for (offset, op) in &data.insts {
+        match *op {
+            Op::LocalSet(slot) => {
+                let val = stack.pop().unwrap();
+                stack[slot as usize] = val;
+            }
+            Op::LocalCopy(slot) => stack.push(stack[slot as usize]),
+            Op::PushU64(val) => stack.push(b.const_u64(val)),
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::Ret => {
+                return Err(LiftError::Unsupported {
+                    offset: *offset,
+                    op: *op,
+                })
+            }
+            Op::Goto(target) => {
+                let (block, takes_stack) = dest(target as usize);
+                b.jump(block, if takes_stack { &stack } else { &[] });
+            }
+            Op::GotoIf(target) | Op::GotoIfNot(target) => {
+                let cond = stack.pop().unwrap();
+                let (jump, jump_takes_stack) = dest(target as usize);
+                let (next, next_takes_stack) = dest(offset + op.encoded_len());
+                let jump_args: &[Value] = if jump_takes_stack { &stack } else { &[] };
+                let next_args: &[Value] = if next_takes_stack { &stack } else { &[] };
+                if matches!(op, Op::GotoIf(_)) {
+                    b.branch(cond, jump, jump_args, next, next_args);
+                } else {
+                    b.branch(cond, next, next_args, jump, jump_args);
+                }
+            }
+            Op::AddU64 | Op::SubU64 | Op::LtU64 | Op::GtU64 => {
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                stack.push(match op {
+                    Op::AddU64 => b.add(lhs, rhs),
+                    Op::SubU64 => b.sub(lhs, rhs),
+                    Op::LtU64 => b.lt(lhs, rhs),
+                    _ => b.gt(lhs, rhs),
+                });
+            }
+            Op::PeekU64 | Op::PeekBool => b.peek(*stack.last().unwrap()),
+        }
+    }
+
+    if !b.is_terminated() {
+        let (block, takes_stack) = dest(data.end);
+        b.jump(block, if takes_stack { &stack } else { &[] });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuplan_ir::encode;
+
+    /// Encodes `ops`, whose jump operands are instruction indices.
+    fn program(ops: &[Op]) -> ByteStream {
+        let offsets = offsets(ops);
+        let ops: Vec<Op> = ops
+            .iter()
+            .map(|op| match *op {
+                Op::Goto(i) => Op::Goto(offsets[i as usize] as u32),
+                Op::GotoIf(i) => Op::GotoIf(offsets[i as usize] as u32),
+                Op::GotoIfNot(i) => Op::GotoIfNot(offsets[i as usize] as u32),
+                op => op,
+            })
+            .collect();
+        encode(&ops)
+    }
+
+    /// The offset of every instruction in `ops`, and of the end.
+    fn offsets(ops: &[Op]) -> Vec<usize> {
+        let mut offsets = vec![0];
+        for op in ops {
+            offsets.push(offsets.last().unwrap() + op.encoded_len());
+        }
+        offsets
+    }
+
+    /// `i = 0; while i < 10 { i += 1 }; peek i`
+    const COUNT: &[Op] = &[
+        Op::PushU64(0),
+        Op::LocalCopy(0),
+        Op::PushU64(10),
+        Op::LtU64,
+        Op::GotoIfNot(10),
+        Op::LocalCopy(0),
+        Op::PushU64(1),
+        Op::AddU64,
+        Op::LocalSet(0),
+        Op::Goto(1),
+        Op::PeekU64,
+    ];
+
+    /// `i = 0; while i < 10 { if i > 5 { goto end } i += 1 }; peek i; end:`
+    const EARLY_EXIT: &[Op] = &[
+        Op::PushU64(0),
+        Op::LocalCopy(0),
+        Op::PushU64(10),
+        Op::LtU64,
+        Op::GotoIfNot(14),
+        Op::LocalCopy(0),
+        Op::PushU64(5),
+        Op::GtU64,
+        Op::GotoIf(15),
+        Op::LocalCopy(0),
+        Op::PushU64(1),
+        Op::AddU64,
+        Op::LocalSet(0),
+        Op::Goto(1),
+        Op::PeekU64,
+    ];
+
+    #[test]
+    fn lifts_stack_slots_to_block_params() {
+        let func = lift_bytecode(&program(COUNT), "count").unwrap();
+        assert_eq!(crate::verify::verify(&func), Ok(()));
+        assert_eq!(
+            func.to_string(),
+            "\
+fn count() {
+bb0():
+    jump bb1()
+bb1():
+    v3 = const.u64 0
+    jump bb2(v3)
+bb2(v0: u64):
+    v4 = const.u64 10
+    v5 = lt v0, v4
+    branch v5, bb3(v0), bb4(v0)
+bb3(v1: u64):
+    v6 = const.u64 1
+    v7 = add v1, v6
+    jump bb2(v7)
+bb4(v2: u64):
+    peek v2
+    jump bb5()
+bb5():
+    return
+}
+"
+        );
+    }
+
+    #[test]
+    fn lifts_jumps_to_the_end_as_returns() {
+        let func = lift_bytecode(&program(EARLY_EXIT), "early_exit").unwrap();
+        assert_eq!(crate::verify::verify(&func), Ok(()));
+        // Jumping to the end and falling off it both go to `bb6`, without the stack.
+        assert_eq!(
+            func.to_string(),
+            "\
+fn early_exit() {
+bb0():
+    jump bb1()
+bb1():
+    v4 = const.u64 0
+    jump bb2(v4)
+bb2(v0: u64):
+    v5 = const.u64 10
+    v6 = lt v0, v5
+    branch v6, bb3(v0), bb5(v0)
+bb3(v1: u64):
+    v7 = const.u64 5
+    v8 = gt v1, v7
+    branch v8, bb6(), bb4(v1)
+bb4(v2: u64):
+    v9 = const.u64 1
+    v10 = add v2, v9
+    jump bb2(v10)
+bb5(v3: u64):
+    peek v3
+    jump bb6()
+bb6():
+    return
+}
+"
+        );
+    }
+
+    #[test]
+    fn rejects_ret() {
+        let ops = [Op::PushU64(0), Op::Ret];
+        assert_eq!(
+            lift_bytecode(&program(&ops), "ret"),
+            Err(LiftError::Unsupported {
+                offset: offsets(&ops)[1],
+                op: Op::Ret
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_bytecode() {
+        assert!(matches!(
+            lift_bytecode(&program(&[Op::AddU64]), "invalid"),
+            Err(LiftError::Verify(_))
+        ));
+    }
+
+    #[test]
+    fn lifts_loops() {
+        let header = offsets(COUNT)[1];
+        let lifted = lift_loop(&program(COUNT), header, "count_loop").unwrap();
+        assert_eq!(crate::verify::verify(&lifted.func), Ok(()));
+        assert_eq!(lifted.entry, [Type::U64]);
+        assert_eq!(lifted.exits, [(offsets(COUNT)[10], vec![Type::U64])]);
+        assert_eq!(
+            lifted.func.to_string(),
+            "\
+fn count_loop() frame -> u64 {
+bb0():
+    v4 = load.u64 frame[0]
+    jump bb1(v4)
+bb1(v0: u64):
+    v5 = const.u64 10
+    v6 = lt v0, v5
+    branch v6, bb2(v0), bb3(v0)
+bb2(v1: u64):
+    v7 = const.u64 1
+    v8 = add v1, v7
+    jump bb1(v8)
+bb3(v2: u64):
+    store frame[0], v2
+    v3 = const.u64 54
+    return v3
+}
+"
+        );
+    }
+
+    #[test]
+    fn lifts_every_exit_of_a_loop() {
+        let offsets = offsets(EARLY_EXIT);
+        let lifted = lift_loop(&program(EARLY_EXIT), offsets[1], "early_exit_loop").unwrap();
+        assert_eq!(crate::verify::verify(&lifted.func), Ok(()));
+        // The end of the stream doesn't take the stack.
+        assert_eq!(
+            lifted.exits,
+            [(offsets[14], vec![Type::U64]), (offsets[15], vec![])]
+        );
+    }
+
+    #[test]
+    fn rejects_blocks_that_arent_loop_headers() {
+        let offsets = offsets(COUNT);
+        let bytes = program(COUNT);
+        // The entry block doesn't loop, and the body doesn't start a block.
+        for offset in [0, offsets[2], offsets[10]] {
+            assert_eq!(
+                lift_loop(&bytes, offset, "loop"),
+                Err(LiftError::NotALoop { offset })
+            );
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Lowering from MIR to LLVM IR.
+//!
+//! Block parameters become phi nodes. `u64` is lowered to `i64` and `bool` to `i1`.
+//! `peek` calls `tuplan_peek_u64(i64)` or `tuplan_peek_bool(i1)`, which have to be provided
+//! by the runtime the module is linked or JIT compiled against.
+//!
+//! The frame of a function is passed as an `i64*` before its other parameters.
+//!
+//! Blocks that can't be reached from the entry are not lowered.
+//!
+//! The MIR is verified before it is lowered and the LLVM IR after, so a bug in this backend is
+//! reported as [`LowerError::InvalidIr`] instead of crashing LLVM later on.
+
+use crate::verify::{verify, VerifyError};
+use crate::{BlockCall, Function, InstKind, Module, Terminator, Type, Value};
+use std::error::Error;
+use std::fmt;
+use tuplan_llvm::{
+    llvm_get_function_ty, IntPredicate, LLVMBasicBlock, LLVMBuilder, LLVMContext, LLVMModule,
+    LLVMType, LLVMValue,
+};
+
+pub const PEEK_U64: &str = "tuplan_peek_u64";
+pub const PEEK_BOOL: &str = "tuplan_peek_bool";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LowerError {
+    Mir(VerifyError),
+    /// LLVM rejected the IR this backend produced.
+    InvalidIr {
+        /// The first function LLVM rejected, if the problem is inside a function.
+        function: Option<String>,
+        message: String,
+        /// The IR of `function`, or of the whole module.
+        ir: String,
+    },
+}
+
+impl fmt::Display for LowerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowerError::Mir(err) => err.fmt(f),
+            LowerError::InvalidIr {
+                function: Some(function),
+                message,
+                ir,
+            } => write!(
+                f,
+                "Internal compiler error: LLVM rejected the IR lowered from `{function}`.\n{message}\n{ir}"
+            ),
+            LowerError::InvalidIr {
+                function: None,
+                message,
+                ir,
+            } => write!(
+                f,
+                "Internal compiler error: LLVM rejected the lowered module.\n{message}\n{ir}"
+            ),
+        }
+    }
+}
+
+impl Error for LowerError {}
+
+impl From<VerifyError> for LowerError {
+    fn from(err: VerifyError) -> Self {
+        LowerError::Mir(err)
+    }
+}
+
+fn llvm_ty(ctx: &LLVMContext, ty: Type) -> LLVMType<'_> {
+    match ty {
+        Type::U64 => ctx.i64_t(),
+        Type::Bool => ctx.i1_t(),
+    }
+}
+
+fn get<'a, 'ctx>(values: &'a [Option<LLVMValue<'ctx>>], value: Value) -> &'a LLVMValue<'ctx> {
+    values[value.index()]
+        .as_ref()
+        .expect("Value used before it was defined.")
+}
+
+/// The runtime functions a module may call.
+struct Runtime<'ctx> {
+    peek_u64_ty: LLVMType<'ctx>,
+    peek_u64: LLVMValue<'ctx>,
+    peek_bool_ty: LLVMType<'ctx>,
+    peek_bool: LLVMValue<'ctx>,
+}
+
+impl<'ctx> Runtime<'ctx> {
+    fn declare(ctx: &'ctx LLVMContext, module: &mut LLVMModule<'ctx>) -> Runtime<'ctx> {
+        let void_t = ctx.void_t();
+        let peek_u64_ty = llvm_get_function_ty(&void_t, &[ctx.i64_t()]);
+        let peek_bool_ty = llvm_get_function_ty(&void_t, &[ctx.i1_t()]);
+        Runtime {
+            peek_u64: module.add_function(PEEK_U64, peek_u64_ty),
+            peek_u64_ty,
+            peek_bool: module.add_function(PEEK_BOOL, peek_bool_ty),
+            peek_bool_ty,
+        }
+    }
+}
+
+/// Lowers every function in `module` into a new LLVM module.
+pub fn lower_module<'ctx>(
+    module: &Module,
+    ctx: &'ctx LLVMContext,
+) -> Result<LLVMModule<'ctx>, LowerError> {
+    for func in &module.functions {
+        verify(func)?;
+    }
+
+    let mut llvm_module = LLVMModule::create_with_name_in_ctx(module.name.as_str(), ctx);
+    let runtime = Runtime::declare(ctx, &mut llvm_module);
+    for func in &module.functions {
+        lower_function(func, ctx, &mut llvm_module, &runtime);
+    }
+
+    if let Err(message) = llvm_module.verify() {
+        let invalid = module.functions.iter().find_map(|func| {
+            let function = llvm_module.get_function(func.name.as_str())?;
+            (!function.verify_function()).then(|| (func.name.clone(), function.print_to_string()))
+        });
+        return Err(match invalid {
+            Some((function, ir)) => LowerError::InvalidIr {
+                function: Some(function),
+                message,
+                ir,
+            },
+            None => LowerError::InvalidIr {
+                function: None,
+                message,
+                ir: llvm_module.to_ir_string(),
+            },
+        });
+    }
+    Ok(llvm_module)
+}
+
+fn lower_function<'ctx>(
+    func: &Function,
+    ctx: &'ctx LLVMContext,
+    module: &mut LLVMModule<'ctx>,
+    runtime: &Runtime<'ctx>,
+) {
+    let ret_t = match func.ret {
+        Some(ty) => llvm_ty(ctx, ty),
+        None => ctx.void_t(),
+    };
+    let frame_t = func.frame.then(|| ctx.i64_t().ptr_t());
+    let param_ts: Vec<LLVMType> = frame_t
+        .into_iter()
+        .chain(
+            func.params()
+                .iter()
+                .map(|param| llvm_ty(ctx, func.ty(*param))),
+        )
+        .collect();
+    let first_param = func.frame as usize;
+    let function_ty = llvm_get_function_ty(&ret_t, &param_ts);
+    let function = module.add_function(func.name.as_str(), function_ty);
+
+    // Reverse postorder puts the entry first and every definition before its uses.
+    let order = func.reverse_postorder();
+    let mut blocks: Vec<Option<LLVMBasicBlock>> = (0..func.blocks.len()).map(|_| None).collect();
+    for block in &order {
+        blocks[block.index()] = Some(function.append_basic_block(block.to_string()));
+    }
+    let mut values: Vec<Option<LLVMValue>> = (0..func.values.len()).map(|_| None).collect();
+    let mut builder = LLVMBuilder::new(ctx);
+
+    // Create the phi nodes up front since jumps may go to blocks that come later.
+    for block in &order {
+        builder.position_at_end(blocks[block.index()].as_ref().unwrap());
+        for (i, param) in func.block(*block).params.iter().enumerate() {
+            values[param.index()] = Some(if *block == func.entry() {
+                function.get_param(first_param + i)
+            } else {
+                builder.build_phi(param.to_string(), &llvm_ty(ctx, func.ty(*param)))
+            });
+        }
+    }
+
+    let frame = func.frame.then(|| function.get_param(0));
+    let frame_slot = |builder: &mut LLVMBuilder<'ctx>, slot: u32| {
+        let frame = frame.as_ref().unwrap();
+        let index = ctx.i64_t().const_int(slot as u64, false);
+        builder.build_gep(format!("frame.{slot}"), &ctx.i64_t(), frame, &[&index])
+    };
+
+    // The edges of every phi node, as `(block param, incoming value, incoming block)`.
+    let mut incoming = Vec::new();
+    for block in &order {
+        let data = func.block(*block);
+        builder.position_at_end(blocks[block.index()].as_ref().unwrap());
+
+        for inst in &data.insts {
+            let name = inst.result.map(|r| r.to_string()).unwrap_or_default();
+            let value = match inst.kind {
+                InstKind::ConstU64(val) => Some(ctx.i64_t().const_int(val, false)),
+                InstKind::ConstBool(val) => Some(ctx.i1_t().const_int(val as u64, false)),
+                InstKind::Add(a, b) => {
+                    Some(builder.build_add(name, get(&values, a), get(&values, b)))
+                }
+                InstKind::Sub(a, b) => {
+                    Some(builder.build_sub(name, get(&values, a), get(&values, b)))
+                }
+                InstKind::Lt(a, b) => Some(builder.build_icmp(
+                    name,
+                    IntPredicate::Ult,
+                    get(&values, a),
+                    get(&values, b),
+                )),
+                InstKind::Gt(a, b) => Some(builder.build_icmp(
+                    name,
+                    IntPredicate::Ugt,
+                    get(&values, a),
+                    get(&values, b),
+                )),
+                InstKind::Peek(val) => {
+                    let (ty, peek) = match func.ty(val) {
+                        Type::U64 => (&runtime.peek_u64_ty, &runtime.peek_u64),
+                        Type::Bool => (&runtime.peek_bool_ty, &runtime.peek_bool),
+                    };
+                    builder.build_call("", ty, peek, &[get(&values, val)]);
+                    None
+                }
+                InstKind::Load(slot, ty) => {
+                    let ptr = frame_slot(&mut builder, slot);
+                    Some(match ty {
+                        Type::U64 => builder.build_load(name, &ctx.i64_t(), &ptr),
+                        Type::Bool => {
+                            let val = builder.build_load("", &ctx.i64_t(), &ptr);
+                            let zero = ctx.i64_t().const_int(0, false);
+                            builder.build_icmp(name, IntPredicate::Ne, &val, &zero)
+                        }
+                    })
+                }
+                InstKind::Store(slot, val) => {
+                    let ptr = frame_slot(&mut builder, slot);
+                    match func.ty(val) {
+                        Type::U64 => builder.build_store(get(&values, val), &ptr),
+                        Type::Bool => {
+                            let val = builder.build_zext("", get(&values, val), &ctx.i64_t());
+                            builder.build_store(&val, &ptr)
+                        }
+                    };
+                    None
+                }
+            };
+            if let (Some(result), Some(value)) = (inst.result, value) {
+                values[result.index()] = Some(value);
+            }
+        }
+
+        let mut edge = |call: &BlockCall| {
+            for (param, arg) in func.block(call.block).params.iter().zip(&call.args) {
+                incoming.push((*param, *arg, *block));
+            }
+        };
+        let dest = |call: &BlockCall| blocks[call.block.index()].as_ref().unwrap();
+        match data.terminator.as_ref().unwrap() {
+            Terminator::Jump(call) => {
+                edge(call);
+                builder.build_br(dest(call));
+            }
+            Terminator::Branch {
+                cond,
+                then_dest,
+                else_dest,
+            } => {
+                edge(then_dest);
+                // LLVM expects a single incoming value per predecessor, even if there are two edges.
+                if then_dest.block != else_dest.block {
+                    edge(else_dest);
+                }
+                builder.build_cond_br(get(&values, *cond), dest(then_dest), dest(else_dest));
+            }
+            Terminator::Return(Some(val)) => {
+                builder.build_ret(get(&values, *val));
+            }
+            Terminator::Return(None) => {
+                builder.build_ret_void();
+            }
+        }
+    }
+
+    for (param, arg, block) in incoming {
+        let from = blocks[block.index()].as_ref().unwrap();
+        get(&values, param).add_incoming(&[(get(&values, arg), from)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FunctionBuilder;
+
+    fn lower(func: Function) -> Result<String, LowerError> {
+        let mut module = Module::new("test");
+        module.add_function(func);
+        let ctx = LLVMContext::new();
+        let llvm_module = lower_module(&module, &ctx)?;
+        Ok(llvm_module.to_ir_string())
+    }
+
+    #[test]
+    fn lowers_block_params_to_phis() {
+        // `while x > 0 { peek x; x -= 1 }`
+        let mut func = Function::new("countdown", &[Type::U64], None);
+        let mut b = FunctionBuilder::new(&mut func);
+        let x = b.func().params()[0];
+        let header = b.create_block();
+        let param = b.append_block_param(header, Type::U64);
+        let body = b.create_block();
+        let exit = b.create_block();
+        b.jump(header, &[x]);
+        b.switch_to_block(header);
+        let zero = b.const_u64(0);
+        let cond = b.gt(param, zero);
+        b.branch(cond, body, &[], exit, &[]);
+        b.switch_to_block(body);
+        b.peek(param);
+        let one = b.const_u64(1);
+        let next = b.sub(param, one);
+        b.jump(header, &[next]);
+        b.switch_to_block(exit);
+        b.ret(None);
+
+        let ir = lower(func).unwrap();
+        assert!(ir.contains("define void @countdown(i64 %0)"), "{ir}");
+        assert!(ir.contains("phi i64"), "{ir}");
+        assert!(ir.contains("icmp ugt i64"), "{ir}");
+        assert!(ir.contains(&format!("call void @{PEEK_U64}(i64")), "{ir}");
+    }
+
+    #[test]
+    fn lowers_frames_and_bools() {
+        let mut func = Function::new("f", &[], Some(Type::Bool));
+        func.frame = true;
+        let mut b = FunctionBuilder::new(&mut func);
+        let flag = b.load(0, Type::Bool);
+        b.peek(flag);
+        let yes = b.const_bool(true);
+        b.store(1, yes);
+        b.ret(Some(flag));
+
+        let ir = lower(func).unwrap();
+        assert!(ir.contains("define i1 @f("), "{ir}");
+        assert!(ir.contains("getelementptr"), "{ir}");
+        assert!(ir.contains("icmp ne i64"), "{ir}");
+        assert!(ir.contains(&format!("call void @{PEEK_BOOL}(i1")), "{ir}");
+        assert!(ir.contains("store i64 1"), "{ir}");
+    }
+
+    #[test]
+    fn skips_unreachable_blocks() {
+        let mut func = Function::new("f", &[], None);
+        let mut b = FunctionBuilder::new(&mut func);
+        let dead = b.create_block();
+        b.ret(None);
+        b.switch_to_block(dead);
+        b.jump(dead, &[]);
+        let ir = lower(func).unwrap();
+        assert!(!ir.contains("bb1"), "{ir}");
+    }
+
+    #[test]
+    fn rejects_invalid_mir() {
+        let mut func = Function::new("f", &[], Some(Type::U64));
+        let mut b = FunctionBuilder::new(&mut func);
+        let yes = b.const_bool(true);
+        b.ret(Some(yes));
+        assert!(matches!(lower(func), Err(LowerError::Mir(_))));
+    }
+}
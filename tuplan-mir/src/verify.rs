@@ -0,0 +1,317 @@
+//! Type and structure checks for MIR functions.
+//!
+//! Besides the types, every value has to be defined before it is used on every path through
+//! the function: its definition has to dominate the use. Blocks that can't be reached from the
+//! entry are never lowered, so the uses in them aren't checked for dominance.
+
+use crate::{Block, BlockCall, Function, InstKind, Terminator, Type, Value, ValueDef};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid MIR in `{}`: {}", self.function, self.message)
+    }
+}
+
+impl Error for VerifyError {}
+
+pub fn verify(func: &Function) -> Result<(), VerifyError> {
+    let err = |message: String| VerifyError {
+        function: func.name.clone(),
+        message,
+    };
+    let ty = |value: Value| {
+        func.values
+            .get(value.index())
+            .map(|data| data.ty)
+            .ok_or_else(|| err(format!("{value} is not defined.")))
+    };
+    let expect = |value: Value, expected: Type| {
+        let found = ty(value)?;
+        if found != expected {
+            return Err(err(format!(
+                "Expected {value} to be `{expected}`, found `{found}`."
+            )));
+        }
+        Ok(())
+    };
+    let check_call = |call: &BlockCall| {
+        let params = &func
+            .blocks
+            .get(call.block.index())
+            .ok_or_else(|| err(format!("{} does not exist.", call.block)))?
+            .params;
+        if params.len() != call.args.len() {
+            return Err(err(format!(
+                "{} takes {} arguments but {} were passed.",
+                call.block,
+                params.len(),
+                call.args.len()
+            )));
+        }
+        for (param, arg) in params.iter().zip(&call.args) {
+            expect(*arg, func.ty(*param))?;
+        }
+        Ok(())
+    };
+
+    for (block, data) in func.blocks() {
+        for inst in &data.insts {
+            match inst.kind {
+                InstKind::ConstU64(_) | InstKind::ConstBool(_) => (),
+                InstKind::Add(a, b)
+                | InstKind::Sub(a, b)
+                | InstKind::Lt(a, b)
+                | InstKind::Gt(a, b) => {
+                    expect(a, Type::U64)?;
+                    expect(b, Type::U64)?;
+                }
+                InstKind::Peek(val) => {
+                    ty(val)?;
+                }
+                InstKind::Load(..) | InstKind::Store(..) if !func.frame => {
+                    return Err(err(format!(
+                        "{block} accesses the frame of a function without one."
+                    )))
+                }
+                InstKind::Load(..) => (),
+                InstKind::Store(_, val) => {
+                    ty(val)?;
+                }
+            }
+        }
+
+        match &data.terminator {
+            None => return Err(err(format!("{block} has no terminator."))),
+            Some(Terminator::Jump(dest)) => check_call(dest)?,
+            Some(Terminator::Branch {
+                cond,
+                then_dest,
+                else_dest,
+            }) => {
+                expect(*cond, Type::Bool)?;
+                check_call(then_dest)?;
+                check_call(else_dest)?;
+                if then_dest.block == else_dest.block && then_dest.args != else_dest.args {
+                    return Err(err(format!(
+                        "{block} branches to {} twice with different arguments.",
+                        then_dest.block
+                    )));
+                }
+            }
+            Some(Terminator::Return(val)) => match (val, func.ret) {
+                (Some(val), Some(ret)) => expect(*val, ret)?,
+                (None, None) => (),
+                (Some(_), None) => {
+                    return Err(err(format!(
+                        "{block} returns a value from a function without a return type."
+                    )))
+                }
+                (None, Some(ret)) => {
+                    return Err(err(format!(
+                        "{block} returns nothing but `{ret}` was expected."
+                    )))
+                }
+            },
+        }
+    }
+
+    let idom = immediate_dominators(func);
+    let dominates = |a: Block, b: Block| {
+        let mut cur = Some(b);
+        while let Some(block) = cur {
+            if block == a {
+                return true;
+            }
+            cur = idom[block.index()];
+        }
+        false
+    };
+    for block in func.reverse_postorder() {
+        let data = func.block(block);
+        // A use in the terminator comes after every instruction of the block.
+        let uses = data
+            .insts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, inst)| inst.kind.args().into_iter().map(move |value| (i, value)))
+            .chain(
+                data.terminator
+                    .iter()
+                    .flat_map(Terminator::args)
+                    .map(|value| (data.insts.len(), value)),
+            );
+        for (i, value) in uses {
+            let defined = match func.value(value).def {
+                ValueDef::Param(def, n) => {
+                    func.blocks
+                        .get(def.index())
+                        .and_then(|data| data.params.get(n))
+                        == Some(&value)
+                        && dominates(def, block)
+                }
+                ValueDef::Inst(def, n) => {
+                    func.blocks
+                        .get(def.index())
+                        .and_then(|data| data.insts.get(n))
+                        .and_then(|inst| inst.result)
+                        == Some(value)
+                        && if def == block {
+                            n < i
+                        } else {
+                            dominates(def, block)
+                        }
+                }
+            };
+            if !defined {
+                return Err(err(format!(
+                    "{value} is used in {block} but isn't defined on every path to it."
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The immediate dominator of every block, computed with the algorithm by Cooper, Harvey and
+/// Kennedy. This is `None` for the entry and for unreachable blocks.
+fn immediate_dominators(func: &Function) -> Vec<Option<Block>> {
+    let rpo = func.reverse_postorder();
+    let mut rpo_index = vec![usize::MAX; func.blocks.len()];
+    for (i, block) in rpo.iter().enumerate() {
+        rpo_index[block.index()] = i;
+    }
+    let mut preds = vec![Vec::new(); func.blocks.len()];
+    for block in &rpo {
+        for succ in func
+            .block(*block)
+            .terminator
+            .iter()
+            .flat_map(Terminator::successors)
+        {
+            preds[succ.block.index()].push(*block);
+        }
+    }
+
+    let mut idom: Vec<Option<Block>> = vec![None; func.blocks.len()];
+    idom[func.entry().index()] = Some(func.entry());
+
+    let intersect = |idom: &[Option<Block>], mut a: Block, mut b: Block| {
+        while a != b {
+            while rpo_index[a.index()] > rpo_index[b.index()] {
+                a = idom[a.index()].unwrap();
+            }
+            while rpo_index[b.index()] > rpo_index[a.index()] {
+                b = idom[b.index()].unwrap();
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in rpo.iter().skip(1) {
+            let mut new_idom = None;
+            for pred in &preds[block.index()] {
+                if idom[pred.index()].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => *pred,
+                    Some(cur) => intersect(&idom, *pred, cur),
+                });
+            }
+            if new_idom.is_some() && idom[block.index()] != new_idom {
+                idom[block.index()] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom[func.entry().index()] = None;
+    idom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::FunctionBuilder;
+
+    #[test]
+    fn accepts_values_from_dominating_blocks() {
+        let mut func = Function::new("f", &[Type::U64], Some(Type::U64));
+        let mut b = FunctionBuilder::new(&mut func);
+        let x = b.func().params()[0];
+        let one = b.const_u64(1);
+        let then_block = b.create_block();
+        let join = b.create_block();
+        let param = b.append_block_param(join, Type::U64);
+        let cond = b.lt(x, one);
+        b.branch(cond, then_block, &[], join, &[one]);
+        b.switch_to_block(then_block);
+        let sum = b.add(x, one);
+        b.jump(join, &[sum]);
+        b.switch_to_block(join);
+        let result = b.add(param, one);
+        b.ret(Some(result));
+        assert_eq!(verify(&func), Ok(()));
+    }
+
+    #[test]
+    fn rejects_values_from_other_branches() {
+        let mut func = Function::new("f", &[Type::U64], Some(Type::U64));
+        let mut b = FunctionBuilder::new(&mut func);
+        let x = b.func().params()[0];
+        let one = b.const_u64(1);
+        let then_block = b.create_block();
+        let join = b.create_block();
+        let cond = b.lt(x, one);
+        b.branch(cond, then_block, &[], join, &[]);
+        b.switch_to_block(then_block);
+        let sum = b.add(x, one);
+        b.jump(join, &[]);
+        b.switch_to_block(join);
+        // `sum` is only defined if the branch was taken.
+        b.ret(Some(sum));
+        assert_eq!(
+            verify(&func).unwrap_err().message,
+            format!("{sum} is used in {join} but isn't defined on every path to it.")
+        );
+    }
+
+    #[test]
+    fn rejects_uses_before_definitions() {
+        let mut func = Function::new("f", &[], None);
+        let mut b = FunctionBuilder::new(&mut func);
+        let one = b.const_u64(1);
+        b.peek(one);
+        b.ret(None);
+        // Move the `peek` in front of the constant it uses.
+        func.blocks[0].insts.swap(0, 1);
+        func.values[one.index()].def = ValueDef::Inst(Block(0), 1);
+        assert_eq!(
+            verify(&func).unwrap_err().message,
+            format!("{one} is used in bb0 but isn't defined on every path to it.")
+        );
+    }
+
+    #[test]
+    fn ignores_unreachable_blocks() {
+        let mut func = Function::new("f", &[], None);
+        let mut b = FunctionBuilder::new(&mut func);
+        let dead = b.create_block();
+        b.ret(None);
+        b.switch_to_block(dead);
+        let one = b.const_u64(1);
+        b.peek(one);
+        b.ret(None);
+        assert_eq!(verify(&func), Ok(()));
+    }
+}
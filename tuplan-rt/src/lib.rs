@@ -0,0 +1,16 @@
+//! The runtime natively compiled Tuplan programs are linked against.
+//!
+//! These functions implement the instructions that interact with the outside world,
+//! and print exactly what `tuplan_vm::Vm` prints for the same instruction.
+
+/// Implements `peeku64`.
+#[no_mangle]
+pub extern "C" fn tuplan_peek_u64(val: u64) {
+    println!("{}", val)
+}
+
+/// Implements `peekbool`. The value is passed as an LLVM `i1`, so only the lowest bit is meaningful.
+#[no_mangle]
+pub extern "C" fn tuplan_peek_bool(val: u8) {
+    println!("{}", val & 1 != 0)
+}
@@ -0,0 +1,38 @@
+use std::time::Instant;
+
+use tuplan_ir::{encode, Op};
+use tuplan_vm::Vm;
+
+fn main() {
+    /*
+
+    i = 0;
+    while i < 100000000 {
+        i += 1;
+    }
+    peek i;
+
+     */
+
+    let bytecode = encode(&[
+        Op::PushU64(0),
+        Op::LocalCopy(0),
+        Op::PushU64(100_000_000),
+        Op::LtU64,
+        Op::GotoIfNot(54),
+        Op::LocalCopy(0),
+        Op::PushU64(1),
+        Op::AddU64,
+        Op::LocalSet(0),
+        Op::Goto(9),
+        Op::PeekU64,
+    ]);
+
+    let start = Instant::now();
+    let mut vm = Vm::new(bytecode);
+    vm.set_jit_threshold(100);
+    unsafe {
+        vm.run();
+    }
+    println!("Took {:?}", start.elapsed());
+}
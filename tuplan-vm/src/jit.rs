@@ -0,0 +1,347 @@
+//! Tiered compilation of hot loops.
+//!
+//! The interpreter counts how often every backward jump is taken. Once the header of a loop has
+//! been jumped back to `threshold` times, the loop is lifted to MIR, lowered to LLVM IR, optimized
+//! with `O2` and added to an ORC JIT. From then on, jumping back to the header runs the native
+//! code instead. The stack, which also holds the locals, is copied into the frame of the compiled
+//! function and copied back once it returns the offset the interpreter continues at.
+//!
+//! Loops that can't be compiled, for example because they contain `ret`, keep being interpreted.
+
+use crate::Item;
+use std::collections::HashMap;
+use std::ffi::c_void;
+#[cfg(test)]
+use tests::{peek_bool, peek_u64};
+use tuplan_ir::ByteStream;
+use tuplan_llvm::{LLVMOrcJit, OptLevel, PassPipeline};
+use tuplan_mir::lift::lift_loop;
+use tuplan_mir::llvm::{lower_module, PEEK_BOOL, PEEK_U64};
+use tuplan_mir::{Module, Type};
+#[cfg(not(test))]
+use tuplan_rt::{tuplan_peek_bool as peek_bool, tuplan_peek_u64 as peek_u64};
+
+/// How many times a loop runs before it is compiled, unless set with `Vm::set_jit_threshold`.
+pub const DEFAULT_THRESHOLD: u32 = 1000;
+
+/// Takes the stack in the frame and returns the offset to continue at.
+type LoopFn = extern "C" fn(*mut u64) -> u64;
+
+struct CompiledLoop {
+    /// Points into the code owned by `Jit::orc`, so it must not be called once that is dropped.
+    func: LoopFn,
+    entry: Vec<Type>,
+    exits: HashMap<u64, Vec<Type>>,
+    frame_len: usize,
+}
+
+enum Tier {
+    /// Interpreted, with the number of times the header was jumped back to.
+    Interpreted(u32),
+    Compiled(CompiledLoop),
+    /// The loop couldn't be compiled.
+    Failed,
+}
+
+pub struct Jit {
+    threshold: u32,
+    /// The loops that have been jumped back to, by the offset of their header.
+    loops: HashMap<usize, Tier>,
+    frame: Vec<u64>,
+    /// Owns the compiled code that `loops` point into. Created when the first loop is compiled
+    /// and only dropped together with the loops.
+    orc: Option<LLVMOrcJit>,
+}
+
+impl Jit {
+    #[cold]
+    #[must_use]
+    pub fn new(threshold: u32) -> Jit {
+        Jit {
+            threshold,
+            loops: HashMap::new(),
+            frame: Vec::new(),
+            orc: None,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    #[inline]
+    pub fn set_threshold(&mut self, threshold: u32) {
+        self.threshold = threshold;
+    }
+
+    /// Called when the interpreter jumps back to `header`. Runs the loop natively if it is
+    /// compiled, and returns the offset to continue at.
+    ///
+    /// # Safety
+    /// `code` has to be the program that is running, and `stack` has to match its stack map at
+    /// `header`.
+    pub unsafe fn back_edge(
+        &mut self,
+        header: usize,
+        code: &ByteStream,
+        stack: &mut Vec<Item>,
+    ) -> Option<usize> {
+        let tier = self.loops.entry(header).or_insert(Tier::Interpreted(0));
+        if let Tier::Interpreted(count) = tier {
+            *count += 1;
+            if *count < self.threshold {
+                return None;
+            }
+            let tier = match self.compile(header, code) {
+                Some(compiled) => Tier::Compiled(compiled),
+                None => Tier::Failed,
+            };
+            self.loops.insert(header, tier);
+        }
+
+        let Some(Tier::Compiled(compiled)) = self.loops.get(&header) else {
+            return None;
+        };
+        debug_assert_eq!(stack.len(), compiled.entry.len());
+
+        self.frame.clear();
+        self.frame.extend(
+            stack
+                .iter()
+                .zip(&compiled.entry)
+                .map(|(item, ty)| match ty {
+                    Type::U64 => item.u64(),
+                    Type::Bool => item.bool() as u64,
+                }),
+        );
+        self.frame.resize(compiled.frame_len, 0);
+
+        let next = (compiled.func)(self.frame.as_mut_ptr());
+
+        stack.clear();
+        let exit = &compiled.exits[&next];
+        stack.extend(self.frame.iter().zip(exit).map(|(val, ty)| match ty {
+            Type::U64 => Item::from_u64(*val),
+            Type::Bool => Item::from_bool(*val != 0),
+        }));
+        Some(next as usize)
+    }
+
+    unsafe fn compile(&mut self, header: usize, code: &ByteStream) -> Option<CompiledLoop> {
+        let name = format!("tuplan_loop_{header}");
+        let lifted = lift_loop(code, header, &name).ok()?;
+        let mut module = Module::new(name.as_str());
+        module.add_function(lifted.func);
+
+        let orc = match &self.orc {
+            Some(orc) => orc,
+            None => {
+                let orc = LLVMOrcJit::new().ok()?;
+                orc.define_host_functions(&[
+                    (PEEK_U64, peek_u64 as *const c_void),
+                    (PEEK_BOOL, peek_bool as *const c_void),
+                ])
+                .ok()?;
+                self.orc.insert(orc)
+            }
+        };
+        let mut llvm_module = lower_module(&module, orc.context()).ok()?;
+        PassPipeline::preset(OptLevel::O2)
+            .run(&mut llvm_module, None)
+            .ok()?;
+        orc.add_module(llvm_module).ok()?;
+        // The pointer is only called through `self.loops`, which `orc` outlives.
+        let func = orc.get_function::<LoopFn>(&name).ok()?.as_raw();
+
+        let frame_len = lifted
+            .exits
+            .iter()
+            .map(|(_, tys)| tys.len())
+            .chain([lifted.entry.len()])
+            .max()
+            .unwrap_or(0);
+        Some(CompiledLoop {
+            func,
+            entry: lifted.entry,
+            exits: lifted
+                .exits
+                .into_iter()
+                .map(|(offset, tys)| (offset as u64, tys))
+                .collect(),
+            frame_len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Vm, OUTPUT};
+    use tuplan_ir::{encode, Op};
+
+    /// Records what compiled code peeks like the interpreter does in tests.
+    pub(super) extern "C" fn peek_u64(val: u64) {
+        crate::print(val)
+    }
+
+    pub(super) extern "C" fn peek_bool(val: u8) {
+        crate::print(val & 1 != 0)
+    }
+
+    /// Encodes `ops`, whose jump operands are instruction indices.
+    fn program(ops: &[Op]) -> ByteStream {
+        let mut offsets = vec![0];
+        for op in ops {
+            offsets.push(offsets.last().unwrap() + op.encoded_len() as u32);
+        }
+        let ops: Vec<Op> = ops
+            .iter()
+            .map(|op| match *op {
+                Op::Goto(i) => Op::Goto(offsets[i as usize]),
+                Op::GotoIf(i) => Op::GotoIf(offsets[i as usize]),
+                Op::GotoIfNot(i) => Op::GotoIfNot(offsets[i as usize]),
+                op => op,
+            })
+            .collect();
+        encode(&ops)
+    }
+
+    /// Runs `ops` and returns what it peeked, the `u64`s left on the stack and whether a loop
+    /// was compiled.
+    fn run(ops: &[Op], threshold: u32) -> (Vec<String>, Vec<u64>, bool) {
+        OUTPUT.with(|output| output.borrow_mut().clear());
+        let mut vm = Vm::new(program(ops));
+        vm.set_jit_threshold(threshold);
+        unsafe {
+            vm.run();
+        }
+        let stack = vm
+            .stack()
+            .iter()
+            .map(|item| unsafe { item.u64() })
+            .collect();
+        let compiled = vm
+            .jit
+            .loops
+            .values()
+            .any(|tier| matches!(tier, Tier::Compiled(_)));
+        (OUTPUT.with(|output| output.take()), stack, compiled)
+    }
+
+    /// Checks that compiling the loops of `ops` on their first back edge doesn't change what
+    /// the program peeks and leaves on the stack, and returns what it peeked.
+    fn same_as_interpreter(ops: &[Op]) -> Vec<String> {
+        let (output, stack, compiled) = run(ops, u32::MAX);
+        assert!(!compiled);
+        let (jit_output, jit_stack, compiled) = run(ops, 1);
+        assert!(compiled, "No loop was compiled.");
+        assert_eq!(jit_output, output);
+        assert_eq!(jit_stack, stack);
+        output
+    }
+
+    #[test]
+    fn while_loops() {
+        // `i = 0; while i < 5 { peek i; i += 1 }`
+        let output = same_as_interpreter(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(5),
+            Op::LtU64,
+            Op::GotoIfNot(13),
+            Op::LocalCopy(0),
+            Op::PeekU64,
+            Op::Pop,
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(1),
+        ]);
+        assert_eq!(output, ["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn do_while_loops() {
+        // `i = 0; do { i += 1; peek i < 3 } while i < 3; peek i`
+        let output = same_as_interpreter(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::PeekBool,
+            Op::GotoIf(1),
+            Op::PeekU64,
+        ]);
+        assert_eq!(output, ["true", "true", "false", "3"]);
+    }
+
+    #[test]
+    fn nested_loops() {
+        // `i = 0; while i < 3 { j = 0; while j < i { peek j; j += 1 }; peek i; i += 1 }`
+        same_as_interpreter(&[
+            Op::PushU64(0),
+            Op::LocalCopy(0),
+            Op::PushU64(3),
+            Op::LtU64,
+            Op::GotoIfNot(27),
+            Op::PushU64(0),
+            Op::LocalCopy(1),
+            Op::LocalCopy(0),
+            Op::LtU64,
+            Op::GotoIfNot(18),
+            Op::LocalCopy(1),
+            Op::PeekU64,
+            Op::Pop,
+            Op::LocalCopy(1),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(1),
+            Op::Goto(6),
+            Op::Pop,
+            Op::LocalCopy(0),
+            Op::PeekU64,
+            Op::Pop,
+            Op::LocalCopy(0),
+            Op::PushU64(1),
+            Op::AddU64,
+            Op::LocalSet(0),
+            Op::Goto(1),
+            Op::PushU64(100),
+            Op::PeekU64,
+        ]);
+    }
+
+    #[test]
+    fn loops_with_several_exits() {
+        // `i = 0; while i < 10 { if i > 5 { goto end } i += 1 }; peek i; end:`
+        let ops = |limit| {
+            [
+                Op::PushU64(0),
+                Op::LocalCopy(0),
+                Op::PushU64(limit),
+                Op::LtU64,
+                Op::GotoIfNot(14),
+                Op::LocalCopy(0),
+                Op::PushU64(5),
+                Op::GtU64,
+                Op::GotoIf(15),
+                Op::LocalCopy(0),
+                Op::PushU64(1),
+                Op::AddU64,
+                Op::LocalSet(0),
+                Op::Goto(1),
+                Op::PeekU64,
+            ]
+        };
+        // Leaves through the condition of the loop, and through the jump to the end.
+        assert_eq!(same_as_interpreter(&ops(3)), ["3"]);
+        assert!(same_as_interpreter(&ops(10)).is_empty());
+    }
+}
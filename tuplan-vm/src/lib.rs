@@ -1,6 +1,9 @@
 use disc::FromDiscriminant;
 use tuplan_ir::{ByteStream, Inst};
 
+#[cfg(all(feature = "jit", not(feature = "checked")))]
+pub mod jit;
+
 #[derive(Debug)]
 #[cfg(feature = "checked")]
 #[derive(Copy, Clone)]
@@ -93,10 +96,27 @@ impl Item {
     }
 }
 
+/// Prints what `peeku64` and `peekbool` peeked. Tests record it instead, so that the output of
+/// the interpreter and of compiled loops can be compared.
+#[inline]
+fn print(val: impl std::fmt::Display) {
+    #[cfg(test)]
+    OUTPUT.with(|output| output.borrow_mut().push(val.to_string()));
+    #[cfg(not(test))]
+    println!("{}", val)
+}
+
+#[cfg(test)]
+thread_local! {
+    static OUTPUT: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
 // TODO: Make item not take up so much space without adding performance overhead
 pub struct Vm {
     code: ByteStream,
     stack: Vec<Item>,
+    #[cfg(all(feature = "jit", not(feature = "checked")))]
+    jit: jit::Jit,
 }
 
 impl Vm {
@@ -107,7 +127,37 @@ impl Vm {
         Vm {
             code,
             stack: Vec::new(),
+            #[cfg(all(feature = "jit", not(feature = "checked")))]
+            jit: jit::Jit::new(jit::DEFAULT_THRESHOLD),
+        }
+    }
+
+    /// The stack, which also holds the locals. A program that ran to the end leaves its result on top.
+    #[inline]
+    #[must_use]
+    pub fn stack(&self) -> &[Item] {
+        &self.stack
+    }
+
+    /// Sets how many times a loop runs in the interpreter before it is compiled.
+    #[cfg(all(feature = "jit", not(feature = "checked")))]
+    #[inline]
+    pub fn set_jit_threshold(&mut self, threshold: u32) {
+        self.jit.set_threshold(threshold);
+    }
+
+    /// Continues at `addr`. Jumping backwards runs the loop natively once it is hot.
+    #[cfg(not(feature = "checked"))]
+    #[inline]
+    unsafe fn jump(&mut self, addr: usize) {
+        #[cfg(feature = "jit")]
+        if addr < self.code.position() {
+            if let Some(next) = self.jit.back_edge(addr, &self.code, &mut self.stack) {
+                self.code.jump_unchecked(next);
+                return;
+            }
         }
+        self.code.jump_unchecked(addr);
     }
 
     #[cfg(feature = "checked")]
@@ -151,7 +201,7 @@ impl Vm {
                     self.code.read_into_const(&mut addr_bytes);
 
                     let addr = u32::from_le_bytes(addr_bytes);
-                    self.code.jump_unchecked(addr as usize);
+                    self.jump(addr as usize);
                 }
                 Inst::GotoIf => {
                     let mut addr_bytes = [0u8; 4];
@@ -161,7 +211,7 @@ impl Vm {
                     }
 
                     let addr = u32::from_le_bytes(addr_bytes);
-                    self.code.jump_unchecked(addr as usize);
+                    self.jump(addr as usize);
                 }
                 Inst::GotoIfNot => {
                     let mut addr_bytes = [0u8; 4];
@@ -171,7 +221,7 @@ impl Vm {
                     }
 
                     let addr = u32::from_le_bytes(addr_bytes);
-                    self.code.jump_unchecked(addr as usize);
+                    self.jump(addr as usize);
                 }
                 Inst::AddU64 => {
                     let b = self.stack.pop().unwrap_unchecked().u64();
@@ -195,11 +245,11 @@ impl Vm {
                 }
                 Inst::PeekU64 => {
                     let val = self.stack.last().unwrap_unchecked().u64();
-                    println!("{}", val)
+                    print(val)
                 }
                 Inst::PeekBool => {
                     let val = self.stack.last().unwrap_unchecked().bool();
-                    println!("{}", val)
+                    print(val)
                 }
             }
         }